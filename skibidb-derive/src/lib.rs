@@ -0,0 +1,76 @@
+//! `#[derive(SkibiRow)]`, which implements `skibidb::row::Row` for a struct
+//! with named fields. Each field becomes a column with the same name, in
+//! declaration order, whose type is given by the field type's
+//! `skibidb::row::ColumnValue` implementation.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields};
+
+#[proc_macro_derive(SkibiRow)]
+pub fn derive_skibi_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Generates the `Row` implementation for `input`, or an error if it is not
+/// a struct with named fields.
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "SkibiRow can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "SkibiRow can only be derived for structs",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let names: Vec<_> = idents.iter().map(|ident| ident.to_string()).collect();
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    let indices: Vec<_> = (0..fields.len()).collect();
+    let len = fields.len();
+
+    Ok(quote! {
+        impl #impl_generics ::skibidb::row::Row for #name #ty_generics #where_clause {
+            fn schema() -> ::skibidb::datatypes::Schema {
+                ::skibidb::datatypes::Schema::with_names(
+                    vec![#(#names.to_string()),*],
+                    vec![#(<#types as ::skibidb::row::ColumnValue>::column_type()),*],
+                )
+                .expect("struct fields have unique names")
+            }
+
+            fn to_tuple(&self) -> ::skibidb::datatypes::Tuple {
+                ::skibidb::datatypes::Tuple::new(vec![
+                    #(::skibidb::row::ColumnValue::to_value(&self.#idents)),*
+                ])
+            }
+
+            fn from_tuple(
+                tuple: &::skibidb::datatypes::Tuple,
+            ) -> ::skibidb::DBResult<Self> {
+                let values = ::skibidb::row::check_row_len(tuple, #len)?;
+                Ok(Self {
+                    #(#idents: <#types as ::skibidb::row::ColumnValue>::from_value(
+                        &values[#indices],
+                    )?),*
+                })
+            }
+        }
+    })
+}
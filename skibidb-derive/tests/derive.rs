@@ -0,0 +1,59 @@
+use skibidb::datatypes::{Schema, Tuple, Type, Value};
+use skibidb::row::Row;
+use skibidb::DBResult;
+use skibidb_derive::SkibiRow;
+
+#[derive(Debug, PartialEq, SkibiRow)]
+struct Person {
+    id: i32,
+    name: String,
+    score: Option<f64>,
+    active: bool,
+}
+
+#[test]
+fn test_derived_schema() -> DBResult<()> {
+    let expected = Schema::with_names(
+        vec![
+            "id".to_string(),
+            "name".to_string(),
+            "score".to_string(),
+            "active".to_string(),
+        ],
+        vec![Type::Int, Type::Varchar(u16::MAX), Type::Double, Type::Bool],
+    )?;
+    assert_eq!(Person::schema(), expected);
+    Ok(())
+}
+
+#[test]
+fn test_derived_round_trip() -> DBResult<()> {
+    for score in [Some(9.5), None] {
+        let person = Person {
+            id: 7,
+            name: "Ada".to_string(),
+            score,
+            active: true,
+        };
+
+        let tuple = person.to_tuple();
+        Person::schema().check_tuple(&tuple)?;
+        assert_eq!(Person::from_tuple(&tuple)?, person);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_derived_from_tuple_errs() {
+    let short = Tuple::new(vec![Value::Int(1)]);
+    assert!(Person::from_tuple(&short).is_err());
+
+    // Only `Option` fields accept nulls
+    let null_name = Tuple::new(vec![
+        Value::Int(1),
+        Value::Null,
+        Value::Null,
+        Value::Bool(false),
+    ]);
+    assert!(Person::from_tuple(&null_name).is_err());
+}
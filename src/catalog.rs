@@ -0,0 +1,214 @@
+//! Definitions of the user-defined types that `Type::Enum` and
+//! `Type::Struct` columns refer to by name.
+
+use std::collections::HashMap;
+
+use crate::datatypes::Type;
+use crate::storage::error::StorageError;
+use crate::{DBError, DBResult};
+
+/// A single variant of an enum type: its name and the types of the values
+/// it carries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumVariant {
+    name: String,
+    fields: Vec<Type>,
+}
+
+impl EnumVariant {
+    /// Creates a new `EnumVariant` called `name` which carries values of the
+    /// given `fields` types, in order.
+    pub fn new(name: String, fields: Vec<Type>) -> Self {
+        EnumVariant { name, fields }
+    }
+
+    /// Returns the name of this variant.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the types of the values this variant carries.
+    pub fn fields(&self) -> &[Type] {
+        &self.fields
+    }
+}
+
+/// The definition of an enum type. The discriminant of each variant is its
+/// index in the list of variants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDef {
+    variants: Vec<EnumVariant>,
+}
+
+impl EnumDef {
+    /// Creates a new `EnumDef` with the given `variants`. Returns an error
+    /// if there are more variants than fit in a `u8` discriminant or two
+    /// variants have the same name.
+    pub fn new(variants: Vec<EnumVariant>) -> DBResult<Self> {
+        if variants.len() > u8::MAX as usize + 1 {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "enum has {} variants; the maximum is {}.",
+                variants.len(),
+                u8::MAX as usize + 1
+            ))));
+        }
+
+        for (i, variant) in variants.iter().enumerate() {
+            if variants[..i].iter().any(|v| v.name == variant.name) {
+                return Err(DBError::from(StorageError::InvalidArgument(format!(
+                    "enum has more than one variant named `{}`.",
+                    variant.name
+                ))));
+            }
+        }
+
+        Ok(EnumDef { variants })
+    }
+
+    /// Returns the variants of this enum, in discriminant order.
+    pub fn variants(&self) -> &[EnumVariant] {
+        &self.variants
+    }
+
+    /// Returns the variant with the given `discriminant`, if there is one.
+    pub fn variant(&self, discriminant: u8) -> Option<&EnumVariant> {
+        self.variants.get(discriminant as usize)
+    }
+
+    /// Returns the discriminant of the variant called `name`, if there is
+    /// one.
+    pub fn discriminant(&self, name: &str) -> Option<u8> {
+        self.variants
+            .iter()
+            .position(|variant| variant.name == name)
+            .map(|i| i as u8)
+    }
+}
+
+/// The definition of a struct type: the types of its fields, in order, and
+/// the alignment of their serialized bytes.
+///
+/// By default fields are packed with no padding between them. For interop
+/// with C-style fixed records, an alignment can be given with
+/// `with_alignment`; each field is then padded to start at a multiple of
+/// its natural alignment, capped at the struct's alignment, relative to the
+/// start of the first field, and the fields are padded at the end to a
+/// multiple of the struct's alignment. See `serialize_struct`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDef {
+    fields: Vec<Type>,
+    alignment: usize,
+}
+
+impl StructDef {
+    /// Creates a new packed `StructDef` with fields of the given types.
+    pub fn new(fields: Vec<Type>) -> Self {
+        StructDef {
+            fields,
+            alignment: 1,
+        }
+    }
+
+    /// Sets the alignment of the struct's fields in bytes. Returns an error
+    /// if `alignment` is not a power of two.
+    pub fn with_alignment(mut self, alignment: usize) -> DBResult<Self> {
+        if !alignment.is_power_of_two() {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "struct alignment must be a power of two, not {}.",
+                alignment
+            ))));
+        }
+        self.alignment = alignment;
+        Ok(self)
+    }
+
+    /// Returns the types of the struct's fields, in order.
+    pub fn fields(&self) -> &[Type] {
+        &self.fields
+    }
+
+    /// Returns the alignment of the struct's fields in bytes; 1 if they are
+    /// packed.
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+}
+
+/// A `Catalog` holds the definitions of user-defined types, keyed by the
+/// names that `Type::Enum` and `Type::Struct` columns use to refer to them.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    enums: HashMap<String, EnumDef>,
+    structs: HashMap<String, StructDef>,
+}
+
+impl Catalog {
+    /// Creates a new empty `Catalog`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the enum type `name` with the definition `def`. Returns an error
+    /// if an enum with that name already exists.
+    pub fn define_enum(&mut self, name: String, def: EnumDef) -> DBResult<()> {
+        if self.enums.contains_key(&name) {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "enum `{}` is already defined.",
+                name
+            ))));
+        }
+        self.enums.insert(name, def);
+        Ok(())
+    }
+
+    /// Returns the definition of the enum type `name`, or an error if there
+    /// is no such enum.
+    pub fn get_enum(&self, name: &str) -> DBResult<&EnumDef> {
+        self.enums.get(name).ok_or_else(|| {
+            DBError::from(StorageError::InvalidArgument(format!(
+                "unknown enum `{}`.",
+                name
+            )))
+        })
+    }
+
+    /// Adds the struct type `name` with the definition `def`. Returns an
+    /// error if a struct with that name already exists.
+    pub fn define_struct(&mut self, name: String, def: StructDef) -> DBResult<()> {
+        if self.structs.contains_key(&name) {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "struct `{}` is already defined.",
+                name
+            ))));
+        }
+        self.structs.insert(name, def);
+        Ok(())
+    }
+
+    /// Returns the definition of the struct type `name`, or an error if
+    /// there is no such struct.
+    pub fn get_struct(&self, name: &str) -> DBResult<&StructDef> {
+        self.structs.get(name).ok_or_else(|| {
+            DBError::from(StorageError::InvalidArgument(format!(
+                "unknown struct `{}`.",
+                name
+            )))
+        })
+    }
+
+    /// Returns the name of the variant of enum `enum_name` with the given
+    /// `discriminant`, or an error if there is no such enum or variant.
+    pub fn resolve_enum_variant(&self, enum_name: &str, discriminant: u8) -> DBResult<&str> {
+        let def = self.get_enum(enum_name)?;
+        def.variant(discriminant)
+            .map(EnumVariant::name)
+            .ok_or_else(|| {
+                DBError::from(StorageError::TypeMismatch(format!(
+                    "discriminant {} is out of range for enum `{}` with {} variants.",
+                    discriminant,
+                    enum_name,
+                    def.variants.len()
+                )))
+            })
+    }
+}
@@ -0,0 +1,424 @@
+//! A `Database` is a file of tables which describes itself: a catalog of
+//! the tables it holds is stored in the file alongside them, so a reopened
+//! file can list and open its tables by name.
+//!
+//! The catalog is a `HeapFile` whose chain of pages starts at page 1, the
+//! first page allocated in a new file. It holds one tuple per column of each
+//! table, in column order, with the following columns:
+//!
+//! * `table`: the name of the table.
+//! * `first_page`: the first page of the table's `HeapFile`.
+//! * `position`: the position of the column in the table's schema.
+//! * `column`: the name of the column.
+//! * `type`: the type of the column, as formatted by `Type`'s `Display`.
+//! * `checksummed`: whether the column is checksummed.
+//! * `max_value_bytes` and `max_tuple_bytes`: the size limits of the table's
+//!   schema, repeated for each column.
+
+use crate::datatypes::{Schema, Table, Tuple, Type, Value};
+use crate::storage::error::StorageError;
+use crate::storage::{FileManager, HeapFile};
+use crate::{DBError, DBResult};
+
+/// The page at which the catalog's chain of pages starts.
+const CATALOG_PAGE: u64 = 1;
+
+/// A `Database` stores tables in the file of a `FileManager`, along with a
+/// catalog of their names and schemas.
+pub struct Database {
+    fm: FileManager,
+    catalog: HeapFile,
+}
+
+/// The catalog's record of a table: where its tuples are stored, and their
+/// schema, as returned by `Database::get_table`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableEntry {
+    pub name: String,
+    /// The first page of the table's `HeapFile`.
+    pub first_page: u64,
+    pub schema: Schema,
+}
+
+impl Database {
+    /// Opens the database stored in the file of `fm`. If the file has no
+    /// pages, a new empty catalog is created; otherwise, the file must have
+    /// been created by a `Database`.
+    pub fn open(mut fm: FileManager) -> DBResult<Self> {
+        let catalog = if fm.num_pages() == 0 {
+            HeapFile::create(&mut fm, catalog_schema())?
+        } else {
+            HeapFile::open(&mut fm, CATALOG_PAGE, catalog_schema())?
+        };
+        Ok(Database { fm, catalog })
+    }
+
+    /// Returns the `FileManager` which stores this database, such as for
+    /// inserting tuples into a `HeapFile` returned by `heap_file`.
+    pub fn file_manager(&mut self) -> &mut FileManager {
+        &mut self.fm
+    }
+
+    /// Creates a new empty table called `name` with the given `schema`, and
+    /// returns the `HeapFile` which stores its tuples. Returns an error if a
+    /// table called `name` already exists or `schema` has no columns.
+    pub fn create_table(&mut self, name: &str, schema: Schema) -> DBResult<HeapFile> {
+        if self.get_table(name)?.is_some() {
+            return Err(DBError::from(StorageError::DuplicateTable(
+                name.to_string(),
+            )));
+        }
+        if schema.types().is_empty() {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "table `{}` must have at least one column.",
+                name
+            ))));
+        }
+
+        let heap = HeapFile::create(&mut self.fm, schema.clone())?;
+        let entry = TableEntry {
+            name: name.to_string(),
+            first_page: heap.first_page(),
+            schema,
+        };
+        for tuple in catalog_rows(&entry) {
+            self.catalog.insert(&mut self.fm, &tuple)?;
+        }
+        Ok(heap)
+    }
+
+    /// Renames the table called `old` to `new`. Returns an error if there is
+    /// no table called `old` or a table called `new` already exists.
+    pub fn rename_table(&mut self, old: &str, new: &str) -> DBResult<()> {
+        let mut entries = self.entries()?;
+        if entries.iter().any(|entry| entry.name == new) {
+            return Err(DBError::from(StorageError::DuplicateTable(new.to_string())));
+        }
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.name == old)
+            .ok_or_else(|| DBError::from(StorageError::UnknownTable(old.to_string())))?;
+        entry.name = new.to_string();
+        self.rewrite_catalog(&entries)
+    }
+
+    /// Returns the names of the tables in this database, in the order they
+    /// were created.
+    pub fn list_tables(&mut self) -> DBResult<Vec<String>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect())
+    }
+
+    /// Opens the `HeapFile` which stores the tuples of the table called
+    /// `name`. Returns an error if there is no such table.
+    pub fn heap_file(&mut self, name: &str) -> DBResult<HeapFile> {
+        let entry = self.expect_entry(name)?;
+        HeapFile::open(&mut self.fm, entry.first_page, entry.schema)
+    }
+
+    /// Reads all tuples of the table called `name` into an in-memory
+    /// `Table`. Returns an error if there is no such table.
+    pub fn open_table(&mut self, name: &str) -> DBResult<Table> {
+        let entry = self.expect_entry(name)?;
+        let heap = HeapFile::open(&mut self.fm, entry.first_page, entry.schema.clone())?;
+
+        let mut table = Table::new(entry.name, entry.schema);
+        for tuple in heap.scan(&mut self.fm) {
+            table.insert(tuple?)?;
+        }
+        Ok(table)
+    }
+
+    /// Returns the catalog's record of the table called `name`, or `None` if
+    /// there is no such table.
+    pub fn get_table(&mut self, name: &str) -> DBResult<Option<TableEntry>> {
+        Ok(self.entries()?.into_iter().find(|entry| entry.name == name))
+    }
+
+    /// Returns the catalog's record of the table called `name`, or an error
+    /// if there is no such table.
+    fn expect_entry(&mut self, name: &str) -> DBResult<TableEntry> {
+        self.get_table(name)?
+            .ok_or_else(|| DBError::from(StorageError::UnknownTable(name.to_string())))
+    }
+
+    /// Drops the table called `name`, removing it from the catalog and
+    /// deallocating the pages of its `HeapFile` and blobs so that they can be
+    /// reused. Returns an error if there is no such table.
+    pub fn drop_table(&mut self, name: &str) -> DBResult<()> {
+        let mut entries = self.entries()?;
+        let position = entries
+            .iter()
+            .position(|entry| entry.name == name)
+            .ok_or_else(|| DBError::from(StorageError::UnknownTable(name.to_string())))?;
+        let entry = entries.remove(position);
+
+        // Forget the table before freeing its pages, so that a failure
+        // leaks pages instead of leaving the catalog pointing at free ones
+        self.rewrite_catalog(&entries)?;
+        HeapFile::open(&mut self.fm, entry.first_page, entry.schema)?.destroy(&mut self.fm)
+    }
+
+    /// Replaces the contents of the catalog with the records of `entries`, in
+    /// order. The catalog is rewritten from its first page rather than
+    /// updated in place, so that each table's columns stay consecutive.
+    fn rewrite_catalog(&mut self, entries: &[TableEntry]) -> DBResult<()> {
+        self.catalog.truncate(&mut self.fm)?;
+        for tuple in entries.iter().flat_map(catalog_rows) {
+            self.catalog.insert(&mut self.fm, &tuple)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the records of every table from the catalog, in the order the
+    /// tables were created.
+    fn entries(&mut self) -> DBResult<Vec<TableEntry>> {
+        let rows: Vec<Tuple> = self.catalog.scan(&mut self.fm).collect::<DBResult<_>>()?;
+
+        // Each table's columns are stored consecutively and in order
+        rows.chunk_by(|a, b| a.values()[0] == b.values()[0])
+            .map(table_entry)
+            .collect()
+    }
+}
+
+/// Returns the catalog tuples of the columns of the table recorded by
+/// `entry`, in column order.
+fn catalog_rows(entry: &TableEntry) -> Vec<Tuple> {
+    let schema = &entry.schema;
+    let columns = schema.names().iter().zip(schema.types()).enumerate();
+    columns
+        .map(|(position, (column, ty))| {
+            Tuple::new(vec![
+                Value::Varchar(entry.name.clone()),
+                Value::UnsignedBigInt(entry.first_page),
+                Value::UnsignedShort(position as u16),
+                Value::Varchar(column.clone()),
+                Value::Varchar(ty.to_string()),
+                Value::Bool(schema.is_checksummed(position)),
+                Value::UnsignedBigInt(schema.max_value_bytes() as u64),
+                Value::UnsignedBigInt(schema.max_tuple_bytes() as u64),
+            ])
+        })
+        .collect()
+}
+
+/// Builds the record of a table from the catalog tuples of its columns.
+fn table_entry(rows: &[Tuple]) -> DBResult<TableEntry> {
+    let invalid = || {
+        DBError::from(StorageError::DeserializationError(
+            "invalid catalog entry.".to_string(),
+        ))
+    };
+
+    let (mut name, mut first_page, mut limits) = (String::new(), 0, (0, 0));
+    let (mut names, mut types, mut checksummed) = (Vec::new(), Vec::new(), Vec::new());
+    for row in rows {
+        let [table, page, _, column, ty, checked, max_value, max_tuple] = row.values() else {
+            return Err(invalid());
+        };
+        let (Value::Varchar(table), Value::UnsignedBigInt(page)) = (table, page) else {
+            return Err(invalid());
+        };
+        let (Value::Varchar(column), Value::Varchar(ty), Value::Bool(checked)) =
+            (column, ty, checked)
+        else {
+            return Err(invalid());
+        };
+        let (Value::UnsignedBigInt(max_value), Value::UnsignedBigInt(max_tuple)) =
+            (max_value, max_tuple)
+        else {
+            return Err(invalid());
+        };
+        (name, first_page) = (table.clone(), *page);
+        limits = (*max_value as usize, *max_tuple as usize);
+        names.push(column.clone());
+        types.push(ty.parse().map_err(|_| invalid())?);
+        if *checked {
+            checksummed.push(column.clone());
+        }
+    }
+
+    let schema = checksummed
+        .iter()
+        .try_fold(Schema::with_names(names, types)?, |schema, column| {
+            schema.with_checksum(column)
+        })?
+        .with_size_limits(limits.0, limits.1);
+    Ok(TableEntry {
+        name,
+        first_page,
+        schema,
+    })
+}
+
+/// Returns the schema of the catalog's tuples.
+fn catalog_schema() -> Schema {
+    let names = [
+        "table",
+        "first_page",
+        "position",
+        "column",
+        "type",
+        "checksummed",
+        "max_value_bytes",
+        "max_tuple_bytes",
+    ];
+    let types = vec![
+        Type::Varchar(u16::MAX),
+        Type::UnsignedBigInt,
+        Type::UnsignedShort,
+        Type::Varchar(u16::MAX),
+        Type::Varchar(u16::MAX),
+        Type::Bool,
+        Type::UnsignedBigInt,
+        Type::UnsignedBigInt,
+    ];
+    Schema::with_names(names.map(String::from).to_vec(), types).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::remove_file;
+
+    use super::*;
+
+    #[test]
+    fn test_reopen_catalog() -> DBResult<()> {
+        let path = "db_test_catalog.db";
+        let people = Schema::with_names(
+            vec!["id".to_string(), "name".to_string()],
+            vec![Type::Int, Type::Varchar(16)],
+        )?
+        .with_checksum("name")?;
+        let scores = Schema::with_names(vec!["score".to_string()], vec![Type::Decimal(2)])?;
+        let rows = [(1, "alice"), (2, "bob")]
+            .map(|(id, name)| Tuple::new(vec![Value::Int(id), Value::Varchar(name.to_string())]));
+        {
+            let mut db = Database::open(FileManager::new(path, 256, 16)?)?;
+            let mut heap = db.create_table("people", people.clone())?;
+            db.create_table("scores", scores.clone())?;
+            for row in &rows {
+                heap.insert(db.file_manager(), row)?;
+            }
+            assert!(matches!(
+                db.create_table("people", scores.clone()),
+                Err(DBError::StorageError(StorageError::DuplicateTable(_)))
+            ));
+        }
+
+        let mut db = Database::open(FileManager::new(path, 256, 16)?)?;
+        assert_eq!(db.list_tables()?, ["people", "scores"]);
+        let table = db.open_table("people")?;
+        assert_eq!(table.name(), "people");
+        assert_eq!(table.schema(), &people);
+        assert!(table.iter().eq(rows.iter()));
+        assert_eq!(db.heap_file("scores")?.schema(), &scores);
+        let entry = db.get_table("scores")?.unwrap();
+        assert_eq!(entry.schema, scores);
+        assert_eq!(entry.first_page, db.heap_file("scores")?.first_page());
+        assert_eq!(db.get_table("missing")?, None);
+        assert!(matches!(
+            db.open_table("missing"),
+            Err(DBError::StorageError(StorageError::UnknownTable(_)))
+        ));
+
+        drop(db);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_keeps_size_limits() -> DBResult<()> {
+        let path = "db_test_size_limits.db";
+        let schema = Schema::with_names(vec!["note".to_string()], vec![Type::Varchar(64)])?
+            .with_size_limits(32, 48);
+        {
+            let mut db = Database::open(FileManager::new(path, 256, 16)?)?;
+            db.create_table("notes", schema.clone())?;
+        }
+
+        let mut db = Database::open(FileManager::new(path, 256, 16)?)?;
+        let reopened = db.get_table("notes")?.unwrap().schema;
+        assert_eq!(reopened, schema);
+        assert_eq!(reopened.max_value_bytes(), 32);
+        assert_eq!(reopened.max_tuple_bytes(), 48);
+
+        drop(db);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_table() -> DBResult<()> {
+        let mut db = Database::open(FileManager::in_memory(128, 16)?)?;
+        let schema = Schema::with_names(
+            vec!["id".to_string(), "data".to_string()],
+            vec![Type::Int, Type::Blob],
+        )?;
+        let mut heap = db.create_table("blobs", schema)?;
+        for i in 0..10 {
+            let handle = db.file_manager().store_blob(&[i as u8; 200])?;
+            heap.insert(
+                db.file_manager(),
+                &Tuple::new(vec![Value::Int(i), Value::Blob(handle)]),
+            )?;
+        }
+        let num_pages = db.file_manager().num_pages();
+
+        db.drop_table("blobs")?;
+        assert!(db.list_tables()?.is_empty());
+        assert!(matches!(
+            db.drop_table("blobs"),
+            Err(DBError::StorageError(StorageError::UnknownTable(_)))
+        ));
+
+        // Every page but the catalog's is handed out again before the file
+        // grows
+        let mut reused: Vec<u64> = (1..num_pages)
+            .map(|_| db.file_manager().allocate_page())
+            .collect::<DBResult<_>>()?;
+        reused.sort_unstable();
+        assert_eq!(reused, (CATALOG_PAGE + 1..=num_pages).collect::<Vec<_>>());
+        assert_eq!(db.file_manager().num_pages(), num_pages);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_table() -> DBResult<()> {
+        let path = "db_test_rename_table.db";
+        let schema = |column: &str| Schema::with_names(vec![column.to_string()], vec![Type::Int]);
+        {
+            let mut db = Database::open(FileManager::new(path, 256, 16)?)?;
+            for name in ["a", "b", "c"] {
+                let mut heap = db.create_table(name, schema(name)?)?;
+                heap.insert(db.file_manager(), &Tuple::new(vec![Value::Int(1)]))?;
+            }
+            db.rename_table("b", "renamed")?;
+
+            assert!(matches!(
+                db.rename_table("missing", "d"),
+                Err(DBError::StorageError(StorageError::UnknownTable(_)))
+            ));
+            assert!(matches!(
+                db.rename_table("a", "c"),
+                Err(DBError::StorageError(StorageError::DuplicateTable(_)))
+            ));
+        }
+
+        // The rename is persisted, and tables keep their order and contents
+        let mut db = Database::open(FileManager::new(path, 256, 16)?)?;
+        assert_eq!(db.list_tables()?, ["a", "renamed", "c"]);
+        let table = db.open_table("renamed")?;
+        assert_eq!(table.schema(), &schema("b")?);
+        assert_eq!(table.len(), 1);
+        assert_eq!(db.get_table("b")?, None);
+
+        drop(db);
+        remove_file(path)?;
+        Ok(())
+    }
+}
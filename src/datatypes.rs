@@ -1,3 +1,18 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::num::{IntErrorKind, ParseIntError};
+use std::str::FromStr;
+
+use crate::catalog::Catalog;
+use crate::storage::error::StorageError;
+use crate::storage::tuple::{deserialize_tuple, serialize_tuple};
+use crate::storage::BlobHandle;
+use crate::{DBError, DBResult};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Char(u16),
     Varchar(u16),
@@ -13,14 +28,392 @@ pub enum Type {
     Double,
     Decimal(u8),
     Bool,
+    Date,      // Days since 1970-01-01
+    Timestamp, // Microseconds since 1970-01-01 00:00:00 UTC
+    Uuid,
+    Blob,
     Enum(String),   // Lookup by Enum name
     Struct(String), // Lookup by Struct name
 }
 
+/// Formats types as they are written in schemas, such as `Int`,
+/// `Varchar(16)`, `Decimal(2)`, or `Enum(mood)`. This is the format that
+/// `Type::from_str` parses.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Enum(name) => write!(f, "Enum({})", name),
+            Type::Struct(name) => write!(f, "Struct({})", name),
+            ty => write!(f, "{:?}", ty),
+        }
+    }
+}
+
+impl FromStr for Type {
+    type Err = DBError;
+
+    /// Parses a type written as by `Display`.
+    fn from_str(s: &str) -> DBResult<Self> {
+        let invalid = || DBError::from(StorageError::ParseError(format!("`{}` is not a type.", s)));
+        let (name, arg) = match s.strip_suffix(')').and_then(|s| s.split_once('(')) {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let len = || {
+            arg.and_then(|arg| arg.parse::<u16>().ok())
+                .ok_or_else(invalid)
+        };
+
+        let ty = match (name, arg) {
+            ("Char", _) => Type::Char(len()?),
+            ("Varchar", _) => Type::Varchar(len()?),
+            ("Decimal", Some(arg)) => Type::Decimal(arg.parse().map_err(|_| invalid())?),
+            ("Enum", Some(arg)) if !arg.is_empty() => Type::Enum(arg.to_string()),
+            ("Struct", Some(arg)) if !arg.is_empty() => Type::Struct(arg.to_string()),
+            (_, Some(_)) => return Err(invalid()),
+            ("Tiny", None) => Type::Tiny,
+            ("Short", None) => Type::Short,
+            ("Int", None) => Type::Int,
+            ("BigInt", None) => Type::BigInt,
+            ("UnsignedTiny", None) => Type::UnsignedTiny,
+            ("UnsignedShort", None) => Type::UnsignedShort,
+            ("UnsignedInt", None) => Type::UnsignedInt,
+            ("UnsignedBigInt", None) => Type::UnsignedBigInt,
+            ("Float", None) => Type::Float,
+            ("Double", None) => Type::Double,
+            ("Bool", None) => Type::Bool,
+            ("Date", None) => Type::Date,
+            ("Timestamp", None) => Type::Timestamp,
+            ("Uuid", None) => Type::Uuid,
+            ("Blob", None) => Type::Blob,
+            _ => return Err(invalid()),
+        };
+        Ok(ty)
+    }
+}
+
+/// The default limit on the serialized size of a single value; see
+/// `Schema::with_size_limits`.
+pub const DEFAULT_MAX_VALUE_BYTES: usize = 16 << 20;
+
+/// The default limit on the serialized size of a whole tuple; see
+/// `Schema::with_size_limits`.
+pub const DEFAULT_MAX_TUPLE_BYTES: usize = 64 << 20;
+
+/// A problem with one value of a tuple, as reported by
+/// `Schema::validate_tuple`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// The position of the value in the tuple, or of the column it is
+    /// missing from.
+    pub column: usize,
+    /// A description of the problem.
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Schema {
+    names: Vec<String>,
     types: Vec<Type>,
+    checksummed: Vec<bool>,
+    max_value_bytes: usize,
+    max_tuple_bytes: usize,
+}
+
+impl Schema {
+    /// Creates a new `Schema` whose columns have the given `types`, in order.
+    /// The columns are named by their position: `column_0`, `column_1`, and
+    /// so on.
+    pub fn new(types: Vec<Type>) -> Self {
+        let names = (0..types.len()).map(|i| format!("column_{}", i)).collect();
+        let checksummed = vec![false; types.len()];
+        Schema {
+            names,
+            types,
+            checksummed,
+            max_value_bytes: DEFAULT_MAX_VALUE_BYTES,
+            max_tuple_bytes: DEFAULT_MAX_TUPLE_BYTES,
+        }
+    }
+
+    /// Creates a new `Schema` whose columns have the given `names` and
+    /// `types`, in order. Returns an error if there is not exactly one name
+    /// per type or two columns have the same name.
+    pub fn with_names(names: Vec<String>, types: Vec<Type>) -> DBResult<Self> {
+        if names.len() != types.len() {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "schema has {} column names but {} types.",
+                names.len(),
+                types.len()
+            ))));
+        }
+
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(DBError::from(StorageError::InvalidArgument(format!(
+                    "schema has more than one column named `{}`.",
+                    name
+                ))));
+            }
+        }
+
+        let checksummed = vec![false; types.len()];
+        Ok(Schema {
+            names,
+            types,
+            checksummed,
+            max_value_bytes: DEFAULT_MAX_VALUE_BYTES,
+            max_tuple_bytes: DEFAULT_MAX_TUPLE_BYTES,
+        })
+    }
+
+    /// Sets the largest number of bytes that a single value and a whole
+    /// tuple may take up when serialized according to this schema; larger
+    /// tuples are rejected by `serialize_tuple` before any of their bytes
+    /// are written. This guards against runaway values independently of the
+    /// page size, since large values can be stored in overflow pages. The
+    /// defaults are `DEFAULT_MAX_VALUE_BYTES` and `DEFAULT_MAX_TUPLE_BYTES`.
+    pub fn with_size_limits(mut self, max_value_bytes: usize, max_tuple_bytes: usize) -> Self {
+        self.max_value_bytes = max_value_bytes;
+        self.max_tuple_bytes = max_tuple_bytes;
+        self
+    }
+
+    /// Returns the largest serialized size of a value in bytes; see
+    /// `with_size_limits`.
+    pub fn max_value_bytes(&self) -> usize {
+        self.max_value_bytes
+    }
+
+    /// Returns the largest serialized size of a tuple in bytes; see
+    /// `with_size_limits`.
+    pub fn max_tuple_bytes(&self) -> usize {
+        self.max_tuple_bytes
+    }
+
+    /// Makes the column called `name` checksummed: each of its non-null
+    /// values is stored with a CRC-32 of its serialized bytes, which is
+    /// verified whenever the value is read. This catches values which were
+    /// corrupted before they reached disk, which page checksums cannot.
+    /// Returns an error if there is no column called `name`.
+    ///
+    /// Tuples serialized with and without the checksum are not compatible,
+    /// so this must not be changed for a schema with stored tuples.
+    pub fn with_checksum(mut self, name: &str) -> DBResult<Self> {
+        let column = self.column_index(name).ok_or_else(|| {
+            DBError::from(StorageError::InvalidArgument(format!(
+                "schema has no column named `{}`.",
+                name
+            )))
+        })?;
+        self.checksummed[column] = true;
+        Ok(self)
+    }
+
+    /// Returns `true` if the values in the given `column` are checksummed;
+    /// see `with_checksum`.
+    pub fn is_checksummed(&self, column: usize) -> bool {
+        self.checksummed[column]
+    }
+
+    /// Returns the names of the columns in this `Schema`, in order.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the types of the columns in this `Schema`, in order.
+    pub fn types(&self) -> &[Type] {
+        &self.types
+    }
+
+    /// Returns a hash of the names, types, and checksum settings of the
+    /// columns of this `Schema`, in order, which is the same for identical
+    /// schemas on every run and machine. Schemas whose tuples are serialized
+    /// differently have different fingerprints, except in the unlikely case
+    /// of a collision, so fingerprints can be compared to check that stored
+    /// tuples match a schema.
+    pub fn fingerprint(&self) -> u64 {
+        // 64-bit FNV-1a, since `std`'s hashers are not stable across releases
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for &byte in bytes {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for ((name, ty), &checksummed) in self.names.iter().zip(&self.types).zip(&self.checksummed)
+        {
+            let ty = ty.to_string();
+            for field in [name.as_bytes(), ty.as_bytes()] {
+                write(&(field.len() as u32).to_le_bytes());
+                write(field);
+            }
+            write(&[checksummed as u8]);
+        }
+        hash
+    }
+
+    /// Returns the position of the column called `name`, if there is one.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Renames the column called `old` to `new`. Returns an error if there
+    /// is no column called `old` or another column is already called `new`.
+    pub fn rename_column(&mut self, old: &str, new: &str) -> DBResult<()> {
+        let column = self.column_index(old).ok_or_else(|| {
+            DBError::from(StorageError::InvalidArgument(format!(
+                "schema has no column named `{}`.",
+                old
+            )))
+        })?;
+
+        if old != new && self.column_index(new).is_some() {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "schema already has a column named `{}`.",
+                new
+            ))));
+        }
+
+        self.names[column] = new.to_string();
+        Ok(())
+    }
+
+    /// Reorders the columns of this `Schema` so that the column at position
+    /// `i` is the one that was at position `new_order[i]`. Returns an error
+    /// if `new_order` is not a permutation of the column positions.
+    ///
+    /// Tuples stored against this schema must be reordered to match; use
+    /// `Table::reorder_columns` to reorder a table's schema and tuples
+    /// together.
+    pub fn reorder_columns(&mut self, new_order: &[usize]) -> DBResult<()> {
+        let mut sorted = new_order.to_vec();
+        sorted.sort_unstable();
+        let is_permutation = sorted.into_iter().eq(0..self.types.len());
+        if !is_permutation {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "{:?} is not a reordering of a schema with {} columns.",
+                new_order,
+                self.types.len()
+            ))));
+        }
+
+        self.names = reorder(&self.names, new_order);
+        self.types = reorder(&self.types, new_order);
+        self.checksummed = reorder(&self.checksummed, new_order);
+        Ok(())
+    }
+
+    /// Computes the changes which turn this schema into `other`, for
+    /// generating migrations.
+    ///
+    /// Columns with the same name in both schemas are matched, and reported
+    /// if their type changed. Of the remaining columns, a column of this
+    /// schema is assumed to have been renamed if the column at the same
+    /// position of `other` has the same type; the rest were removed or
+    /// added.
+    pub fn diff(&self, other: &Schema) -> SchemaDiff {
+        let mut diff = SchemaDiff::default();
+        for (name, ty) in self.names.iter().zip(&self.types) {
+            if let Some(i) = other.column_index(name) {
+                if other.types[i] != *ty {
+                    diff.type_changes
+                        .push((name.clone(), ty.clone(), other.types[i].clone()));
+                }
+            }
+        }
+
+        let unmatched_old: Vec<usize> = (0..self.names.len())
+            .filter(|&i| other.column_index(&self.names[i]).is_none())
+            .collect();
+        let mut unmatched_new: Vec<usize> = (0..other.names.len())
+            .filter(|&i| self.column_index(&other.names[i]).is_none())
+            .collect();
+        for i in unmatched_old {
+            let renamed = unmatched_new
+                .iter()
+                .position(|&j| j == i && other.types[j] == self.types[i]);
+            match renamed {
+                Some(pos) => {
+                    unmatched_new.remove(pos);
+                    diff.renamed
+                        .push((self.names[i].clone(), other.names[i].clone()));
+                }
+                None => diff
+                    .removed
+                    .push((self.names[i].clone(), self.types[i].clone())),
+            }
+        }
+        for j in unmatched_new {
+            diff.added
+                .push((other.names[j].clone(), other.types[j].clone()));
+        }
+        diff
+    }
+
+    /// Checks that `tuple` has exactly one value for each column of this
+    /// `Schema`, and that each value can be stored in its column.
+    pub fn check_tuple(&self, tuple: &Tuple) -> DBResult<()> {
+        if tuple.values().len() != self.types.len() {
+            return Err(DBError::from(StorageError::TypeMismatch(format!(
+                "tuple has {} values but schema has {} columns.",
+                tuple.values().len(),
+                self.types.len()
+            ))));
+        }
+
+        for (value, ty) in tuple.values().iter().zip(&self.types) {
+            value.check_type(ty)?;
+        }
+        Ok(())
+    }
+
+    /// Checks `tuple` like `check_tuple`, but reports every problem with it
+    /// rather than only the first: each value which cannot be stored in its
+    /// column, each column without a value, and each value beyond the last
+    /// column, in order of position.
+    pub fn validate_tuple(&self, tuple: &Tuple) -> Result<(), Vec<FieldError>> {
+        let values = tuple.values();
+        let errors: Vec<FieldError> = (0..values.len().max(self.types.len()))
+            .filter_map(|column| {
+                let message = match (values.get(column), self.types.get(column)) {
+                    (Some(value), Some(ty)) => match value.check_type(ty) {
+                        Ok(()) => return None,
+                        Err(DBError::StorageError(StorageError::TypeMismatch(message))) => message,
+                        Err(e) => e.to_string(),
+                    },
+                    (None, _) => format!("missing a value for column `{}`.", self.names[column]),
+                    (_, None) => format!(
+                        "value {:?} is beyond the last of the schema's {} columns.",
+                        values[column],
+                        self.types.len()
+                    ),
+                };
+                Some(FieldError { column, message })
+            })
+            .collect();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    /// Returns the type of the given `column`, or an error if it is out of
+    /// range.
+    pub(crate) fn column_type(&self, column: usize) -> DBResult<&Type> {
+        self.types.get(column).ok_or_else(|| {
+            DBError::from(StorageError::InvalidArgument(format!(
+                "column {} is out of range for a schema with {} columns.",
+                column,
+                self.types.len()
+            )))
+        })
+    }
 }
 
+/// Values of the same variant are ordered by their contents; values of
+/// different variants are ordered by variant, which is only meaningful for
+/// keeping the ordering total within a column.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Value {
     Char(String),
     Varchar(String),
@@ -36,16 +429,3203 @@ pub enum Value {
     Double(f64),
     Decimal(i64, u8),
     Bool(bool),
+    Date(i32),
+    Timestamp(i64),
+    Uuid([u8; 16]),
+    Blob(BlobHandle),     // Points to the blob's bytes in overflow pages
     Enum(u8, Vec<Value>), // An Enum has a numeric value, and possible data
     Struct(Vec<Value>),   // A struct is just several fields of values
+    Null,                 // The absence of a value; allowed in any column
 }
 
+/// A `ValueRef` is a `Value` which borrows its string data from the bytes it
+/// was deserialized from, such as a page in the buffer pool, so that reading
+/// it does not allocate. Composite values are not supported, since they
+/// cannot be deserialized yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueRef<'a> {
+    Char(&'a str),
+    Varchar(&'a str),
+    Tiny(i8),
+    Short(i16),
+    Int(i32),
+    BigInt(i64),
+    UnsignedTiny(u8),
+    UnsignedShort(u16),
+    UnsignedInt(u32),
+    UnsignedBigInt(u64),
+    Float(f32),
+    Double(f64),
+    Decimal(i64, u8),
+    Bool(bool),
+    Date(i32),
+    Timestamp(i64),
+    Uuid([u8; 16]),
+    Blob(BlobHandle),
+    Null,
+}
+
+impl ValueRef<'_> {
+    /// Converts this `ValueRef` into an owned `Value`, copying any string
+    /// data.
+    pub fn to_owned(&self) -> Value {
+        match *self {
+            ValueRef::Char(s) => Value::Char(s.to_string()),
+            ValueRef::Varchar(s) => Value::Varchar(s.to_string()),
+            ValueRef::Tiny(v) => Value::Tiny(v),
+            ValueRef::Short(v) => Value::Short(v),
+            ValueRef::Int(v) => Value::Int(v),
+            ValueRef::BigInt(v) => Value::BigInt(v),
+            ValueRef::UnsignedTiny(v) => Value::UnsignedTiny(v),
+            ValueRef::UnsignedShort(v) => Value::UnsignedShort(v),
+            ValueRef::UnsignedInt(v) => Value::UnsignedInt(v),
+            ValueRef::UnsignedBigInt(v) => Value::UnsignedBigInt(v),
+            ValueRef::Float(v) => Value::Float(v),
+            ValueRef::Double(v) => Value::Double(v),
+            ValueRef::Decimal(v, scale) => Value::Decimal(v, scale),
+            ValueRef::Bool(v) => Value::Bool(v),
+            ValueRef::Date(v) => Value::Date(v),
+            ValueRef::Timestamp(v) => Value::Timestamp(v),
+            ValueRef::Uuid(v) => Value::Uuid(v),
+            ValueRef::Blob(handle) => Value::Blob(handle),
+            ValueRef::Null => Value::Null,
+        }
+    }
+}
+
+impl Value {
+    /// Checks that this is a valid value of the enum type `enum_name`
+    /// defined in `catalog`: its discriminant must be one of the enum's
+    /// variants, and its payload must have one value of the right type for
+    /// each of that variant's fields. Nested enum values are checked too.
+    pub fn validate_enum(&self, enum_name: &str, catalog: &Catalog) -> DBResult<()> {
+        let Value::Enum(discriminant, payload) = self else {
+            return Err(DBError::from(StorageError::TypeMismatch(format!(
+                "value {:?} is not an enum value.",
+                self
+            ))));
+        };
+
+        let variant_name = catalog.resolve_enum_variant(enum_name, *discriminant)?;
+        let fields = catalog.get_enum(enum_name)?.variants()[*discriminant as usize].fields();
+        if payload.len() != fields.len() {
+            return Err(DBError::from(StorageError::TypeMismatch(format!(
+                "variant `{}` of enum `{}` has {} fields but the value has {}.",
+                variant_name,
+                enum_name,
+                fields.len(),
+                payload.len()
+            ))));
+        }
+
+        for (value, ty) in payload.iter().zip(fields) {
+            value.check_type(ty)?;
+            if let (Value::Enum(..), Type::Enum(name)) = (value, ty) {
+                value.validate_enum(name, catalog)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses `s` as a value of type `ty`, as when importing text. Integers
+    /// must be in the range of their type, `Bool`s are written as `true`,
+    /// `false`, `1`, or `0`, and `Decimal`s may have at most as many digits
+    /// after the decimal point as their scale. Strings are taken as they
+    /// are, but must fit in their column.
+    ///
+    /// Returns an error if `s` is malformed or out of range, or if values of
+    /// type `ty` cannot be parsed from text.
+    pub fn parse(s: &str, ty: &Type) -> DBResult<Value> {
+        let value = match ty {
+            Type::Char(_) => Value::Char(s.to_string()),
+            Type::Varchar(_) => Value::Varchar(s.to_string()),
+            Type::Tiny => Value::Tiny(parse_int(s, ty)?),
+            Type::Short => Value::Short(parse_int(s, ty)?),
+            Type::Int => Value::Int(parse_int(s, ty)?),
+            Type::BigInt => Value::BigInt(parse_int(s, ty)?),
+            Type::UnsignedTiny => Value::UnsignedTiny(parse_int(s, ty)?),
+            Type::UnsignedShort => Value::UnsignedShort(parse_int(s, ty)?),
+            Type::UnsignedInt => Value::UnsignedInt(parse_int(s, ty)?),
+            Type::UnsignedBigInt => Value::UnsignedBigInt(parse_int(s, ty)?),
+            Type::Float => Value::Float(s.parse().map_err(|_| parse_error(s, ty))?),
+            Type::Double => Value::Double(s.parse().map_err(|_| parse_error(s, ty))?),
+            Type::Decimal(scale) => Value::Decimal(parse_decimal(s, *scale)?, *scale),
+            Type::Bool => match s.to_ascii_lowercase().as_str() {
+                "true" | "1" => Value::Bool(true),
+                "false" | "0" => Value::Bool(false),
+                _ => return Err(parse_error(s, ty)),
+            },
+            Type::Date => {
+                let days = parse_date(s).ok_or_else(|| parse_error(s, ty))?;
+                Value::Date(i32::try_from(days).map_err(|_| parse_error(s, ty))?)
+            }
+            Type::Timestamp => {
+                Value::Timestamp(parse_timestamp(s).ok_or_else(|| parse_error(s, ty))?)
+            }
+            Type::Uuid => Value::Uuid(parse_uuid(s).ok_or_else(|| parse_error(s, ty))?),
+            Type::Blob | Type::Enum(_) | Type::Struct(_) => {
+                return Err(DBError::from(StorageError::UnsupportedType(format!(
+                    "values of type {:?} cannot be parsed from text.",
+                    ty
+                ))))
+            }
+        };
+
+        value.check_type(ty)?;
+        Ok(value)
+    }
+
+    /// Returns `true` if this is `Null`.
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Returns a copy of this value, or `default` if it is `Null`.
+    pub fn unwrap_or(&self, default: Value) -> Value {
+        if self.is_null() {
+            default
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Returns a copy of the first value in `values` which is not `Null`, as
+    /// in SQL's `COALESCE`. Returns `Null` if every value is `Null` or
+    /// `values` is empty.
+    pub fn coalesce(values: &[Value]) -> Value {
+        values
+            .iter()
+            .find(|value| !value.is_null())
+            .cloned()
+            .unwrap_or(Value::Null)
+    }
+
+    /// Adds two integer values; see `Value::checked_op`.
+    pub fn add(&self, other: &Value) -> DBResult<Value> {
+        self.checked_op(other, "+", i128::checked_add)
+    }
+
+    /// Subtracts `other` from this integer value; see `Value::checked_op`.
+    pub fn sub(&self, other: &Value) -> DBResult<Value> {
+        self.checked_op(other, "-", i128::checked_sub)
+    }
+
+    /// Multiplies two integer values; see `Value::checked_op`.
+    pub fn mul(&self, other: &Value) -> DBResult<Value> {
+        self.checked_op(other, "*", i128::checked_mul)
+    }
+
+    /// Divides this integer value by `other`, rounding towards zero; see
+    /// `Value::checked_op`. Returns an error if `other` is zero.
+    pub fn div(&self, other: &Value) -> DBResult<Value> {
+        self.checked_op(other, "/", i128::checked_div)
+    }
+
+    /// Returns the remainder of dividing this integer value by `other`; see
+    /// `Value::checked_op`. Returns an error if `other` is zero.
+    pub fn rem(&self, other: &Value) -> DBResult<Value> {
+        self.checked_op(other, "%", i128::checked_rem)
+    }
+
+    /// Applies the arithmetic operation `op` to two integer values.
+    ///
+    /// If the values have different integer types with the same signedness,
+    /// the narrower one is promoted to the wider type, which is also the
+    /// type of the result. Mixing signed and unsigned values, or using a
+    /// non-integer value, is a type mismatch. If either value is `Null`, the
+    /// result is `Null`.
+    ///
+    /// Returns an error if the result does not fit in the result type or the
+    /// operation divides by zero.
+    fn checked_op(
+        &self,
+        other: &Value,
+        symbol: &str,
+        op: fn(i128, i128) -> Option<i128>,
+    ) -> DBResult<Value> {
+        if self.is_null() || other.is_null() {
+            return Ok(Value::Null);
+        }
+
+        let mismatch = || {
+            DBError::from(StorageError::TypeMismatch(format!(
+                "cannot compute {:?} {} {:?}.",
+                self, symbol, other
+            )))
+        };
+        let ((a, a_type), (b, b_type)) = self
+            .as_integer()
+            .zip(other.as_integer())
+            .ok_or_else(mismatch)?;
+        let ty = promote_integers(&a_type, &b_type).ok_or_else(mismatch)?;
+
+        // Only division by zero can fail with a zero right-hand side
+        let overflow = || {
+            DBError::from(StorageError::ArithmeticError(format!(
+                "{:?} {} {:?} overflows {:?}.",
+                self, symbol, other, ty
+            )))
+        };
+        match op(a, b) {
+            Some(result) => Value::from_integer(result, &ty).ok_or_else(overflow),
+            None if b == 0 => Err(DBError::from(StorageError::ArithmeticError(format!(
+                "division by zero in {:?} {} {:?}.",
+                self, symbol, other
+            )))),
+            None => Err(overflow()),
+        }
+    }
+
+    /// Returns this value as an `i128` along with its type, if it is an
+    /// integer.
+    fn as_integer(&self) -> Option<(i128, Type)> {
+        match *self {
+            Value::Tiny(v) => Some((v as i128, Type::Tiny)),
+            Value::Short(v) => Some((v as i128, Type::Short)),
+            Value::Int(v) => Some((v as i128, Type::Int)),
+            Value::BigInt(v) => Some((v as i128, Type::BigInt)),
+            Value::UnsignedTiny(v) => Some((v as i128, Type::UnsignedTiny)),
+            Value::UnsignedShort(v) => Some((v as i128, Type::UnsignedShort)),
+            Value::UnsignedInt(v) => Some((v as i128, Type::UnsignedInt)),
+            Value::UnsignedBigInt(v) => Some((v as i128, Type::UnsignedBigInt)),
+            _ => None,
+        }
+    }
+
+    /// Converts `v` into a value of the integer type `ty`, or returns `None`
+    /// if it is out of that type's range.
+    fn from_integer(v: i128, ty: &Type) -> Option<Value> {
+        match ty {
+            Type::Tiny => v.try_into().ok().map(Value::Tiny),
+            Type::Short => v.try_into().ok().map(Value::Short),
+            Type::Int => v.try_into().ok().map(Value::Int),
+            Type::BigInt => v.try_into().ok().map(Value::BigInt),
+            Type::UnsignedTiny => v.try_into().ok().map(Value::UnsignedTiny),
+            Type::UnsignedShort => v.try_into().ok().map(Value::UnsignedShort),
+            Type::UnsignedInt => v.try_into().ok().map(Value::UnsignedInt),
+            Type::UnsignedBigInt => v.try_into().ok().map(Value::UnsignedBigInt),
+            _ => None,
+        }
+    }
+
+    /// Returns this value as an `f64` if it is numeric, for interpolating
+    /// between values.
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Tiny(v) => Some(v as f64),
+            Value::Short(v) => Some(v as f64),
+            Value::Int(v) => Some(v as f64),
+            Value::BigInt(v) => Some(v as f64),
+            Value::UnsignedTiny(v) => Some(v as f64),
+            Value::UnsignedShort(v) => Some(v as f64),
+            Value::UnsignedInt(v) => Some(v as f64),
+            Value::UnsignedBigInt(v) => Some(v as f64),
+            Value::Float(v) => Some(v as f64),
+            Value::Double(v) => Some(v),
+            Value::Decimal(v, scale) => Some(v as f64 / 10f64.powi(scale as i32)),
+            Value::Bool(v) => Some(v as u8 as f64),
+            Value::Date(v) => Some(v as f64),
+            Value::Timestamp(v) => Some(v as f64),
+            _ => None,
+        }
+    }
+
+    /// Checks that this value can be stored in a column of type `ty`.
+    pub(crate) fn check_type(&self, ty: &Type) -> DBResult<()> {
+        let matches = match (self, ty) {
+            (Value::Char(s), Type::Char(max)) | (Value::Varchar(s), Type::Varchar(max)) => {
+                if s.len() > *max as usize {
+                    return Err(DBError::from(StorageError::TypeMismatch(format!(
+                        "string of {} bytes is longer than the column maximum of {} bytes.",
+                        s.len(),
+                        max
+                    ))));
+                }
+                true
+            }
+            (Value::Decimal(_, scale), Type::Decimal(expected)) => {
+                if scale != expected {
+                    return Err(DBError::from(StorageError::TypeMismatch(format!(
+                        "decimal has scale {} but column has scale {}.",
+                        scale, expected
+                    ))));
+                }
+                true
+            }
+            (Value::Null, _)
+            | (Value::Tiny(_), Type::Tiny)
+            | (Value::Short(_), Type::Short)
+            | (Value::Int(_), Type::Int)
+            | (Value::BigInt(_), Type::BigInt)
+            | (Value::UnsignedTiny(_), Type::UnsignedTiny)
+            | (Value::UnsignedShort(_), Type::UnsignedShort)
+            | (Value::UnsignedInt(_), Type::UnsignedInt)
+            | (Value::UnsignedBigInt(_), Type::UnsignedBigInt)
+            | (Value::Float(_), Type::Float)
+            | (Value::Double(_), Type::Double)
+            | (Value::Bool(_), Type::Bool)
+            | (Value::Date(_), Type::Date)
+            | (Value::Timestamp(_), Type::Timestamp)
+            | (Value::Uuid(_), Type::Uuid)
+            | (Value::Blob(_), Type::Blob)
+            | (Value::Enum(..), Type::Enum(_))
+            | (Value::Struct(_), Type::Struct(_)) => true,
+            _ => false,
+        };
+
+        if !matches {
+            return Err(DBError::from(StorageError::TypeMismatch(format!(
+                "value {:?} cannot be stored in a column of type {:?}.",
+                self, ty
+            ))));
+        }
+        Ok(())
+    }
+}
+
+/// Formats values as they would be shown in query output: strings without
+/// quotes, decimals with their decimal point, dates and timestamps in
+/// ISO-8601 format, UUIDs hyphenated, and `Null` as `NULL`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Char(s) | Value::Varchar(s) => write!(f, "{}", s),
+            Value::Tiny(v) => write!(f, "{}", v),
+            Value::Short(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::BigInt(v) => write!(f, "{}", v),
+            Value::UnsignedTiny(v) => write!(f, "{}", v),
+            Value::UnsignedShort(v) => write!(f, "{}", v),
+            Value::UnsignedInt(v) => write!(f, "{}", v),
+            Value::UnsignedBigInt(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Double(v) => write!(f, "{}", v),
+            Value::Decimal(v, 0) => write!(f, "{}", v),
+            Value::Decimal(v, scale) => {
+                let digits = format!("{:0>width$}", v.unsigned_abs(), width = *scale as usize + 1);
+                let (whole, fraction) = digits.split_at(digits.len() - *scale as usize);
+                let sign = if *v < 0 { "-" } else { "" };
+                write!(f, "{}{}.{}", sign, whole, fraction)
+            }
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Date(days) => {
+                let (year, month, day) = civil_from_days(*days as i64);
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)
+            }
+            Value::Timestamp(micros) => {
+                let (days, micros) = (
+                    micros.div_euclid(MICROS_PER_DAY),
+                    micros.rem_euclid(MICROS_PER_DAY),
+                );
+                let (year, month, day) = civil_from_days(days);
+                let seconds = micros / 1_000_000;
+                write!(
+                    f,
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    year,
+                    month,
+                    day,
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                )?;
+                if micros % 1_000_000 != 0 {
+                    write!(f, ".{:06}", micros % 1_000_000)?;
+                }
+                write!(f, "Z")
+            }
+            Value::Uuid(bytes) => {
+                for (i, byte) in bytes.iter().enumerate() {
+                    if let 4 | 6 | 8 | 10 = i {
+                        write!(f, "-")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Value::Blob(handle) => write!(f, "<blob of {} bytes>", handle.len()),
+            Value::Enum(discriminant, payload) if payload.is_empty() => {
+                write!(f, "#{}", discriminant)
+            }
+            Value::Enum(discriminant, payload) => {
+                write!(f, "#{}({})", discriminant, join_values(payload))
+            }
+            Value::Struct(fields) => write!(f, "{{{}}}", join_values(fields)),
+            Value::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+/// The changes between two versions of a `Schema`, as computed by
+/// `Schema::diff`. Columns are listed in the order they appear in the
+/// schemas.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SchemaDiff {
+    /// Name and type of each column only in the new schema.
+    pub added: Vec<(String, Type)>,
+    /// Name and type of each column only in the old schema.
+    pub removed: Vec<(String, Type)>,
+    /// Old and new name of each renamed column.
+    pub renamed: Vec<(String, String)>,
+    /// Name, old type, and new type of each column whose type changed.
+    pub type_changes: Vec<(String, Type, Type)>,
+}
+
+impl SchemaDiff {
+    /// Returns `true` if the two schemas have the same columns.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+            && self.type_changes.is_empty()
+    }
+}
+
+/// Floats are hashed so that values which are equal, such as `0.0` and
+/// `-0.0`, hash the same. All NaNs also hash the same, so that tuples
+/// containing them can be deduplicated; see `Tuple`'s `PartialEq`.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Value::Char(v) | Value::Varchar(v) => v.hash(state),
+            Value::Tiny(v) => v.hash(state),
+            Value::Short(v) => v.hash(state),
+            Value::Int(v) => v.hash(state),
+            Value::BigInt(v) => v.hash(state),
+            Value::UnsignedTiny(v) => v.hash(state),
+            Value::UnsignedShort(v) => v.hash(state),
+            Value::UnsignedInt(v) => v.hash(state),
+            Value::UnsignedBigInt(v) => v.hash(state),
+            Value::Float(v) if v.is_nan() => f32::NAN.to_bits().hash(state),
+            Value::Float(v) => (v + 0.0).to_bits().hash(state),
+            Value::Double(v) if v.is_nan() => f64::NAN.to_bits().hash(state),
+            Value::Double(v) => (v + 0.0).to_bits().hash(state),
+            Value::Decimal(v, scale) => (v, scale).hash(state),
+            Value::Bool(v) => v.hash(state),
+            Value::Date(v) => v.hash(state),
+            Value::Timestamp(v) => v.hash(state),
+            Value::Uuid(v) => v.hash(state),
+            Value::Blob(v) => v.hash(state),
+            Value::Enum(tag, data) => (tag, data).hash(state),
+            Value::Struct(fields) => fields.hash(state),
+            Value::Null => {}
+        }
+    }
+}
+
+/// Tuples are equal if all of their values are equal, except that nulls
+/// are equal to each other and NaNs are equal to NaNs of the same type, as
+/// needed to deduplicate rows for `DISTINCT`. The same rules apply to the
+/// fields of `Struct` and `Enum` values, and enums are only equal if their
+/// discriminants are. This makes `Tuple`'s equality an equivalence relation,
+/// unlike `Value`'s, so tuples can be used as keys.
+#[derive(Debug, Clone)]
 pub struct Tuple {
     values: Vec<Value>,
 }
 
+impl PartialEq for Tuple {
+    fn eq(&self, other: &Self) -> bool {
+        values_equal(&self.values, &other.values)
+    }
+}
+
+impl Eq for Tuple {}
+
+impl Hash for Tuple {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.values.hash(state);
+    }
+}
+
+impl Tuple {
+    /// Creates a new `Tuple` containing the given `values`, in column order.
+    pub fn new(values: Vec<Value>) -> Self {
+        Tuple { values }
+    }
+
+    /// Returns the values in this `Tuple`, in column order.
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+}
+
+/// A comparison of a column against a constant value in a predicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A predicate comparing one column of a tuple against a constant value,
+/// such as the filter of a partial index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub column: usize,
+    pub op: CompareOp,
+    pub value: Value,
+}
+
+impl Comparison {
+    /// Creates a new `Comparison` of `column` against `value`.
+    pub fn new(column: usize, op: CompareOp, value: Value) -> Self {
+        Comparison { column, op, value }
+    }
+
+    /// Returns whether `tuple` satisfies the comparison. As in SQL, a null on
+    /// either side never does.
+    pub fn matches(&self, tuple: &Tuple) -> bool {
+        compare(&tuple.values[self.column], self.op, &self.value)
+    }
+
+    /// Returns whether every tuple which satisfies this comparison also
+    /// satisfies `other`. This is conservative: comparisons of different
+    /// columns never imply each other, even when they happen to.
+    fn implies(&self, other: &Comparison) -> bool {
+        if self.column != other.column || self.value.is_null() {
+            return false;
+        }
+        if self.op == CompareOp::Eq {
+            return compare(&self.value, other.op, &other.value);
+        }
+        let Some(ordering) = self.value.partial_cmp(&other.value) else {
+            return false;
+        };
+        let strict = |op| self.op == op;
+        match (self.op, other.op) {
+            (CompareOp::Gt | CompareOp::Ge, CompareOp::Gt | CompareOp::Ne) => {
+                ordering == Ordering::Greater
+                    || (ordering == Ordering::Equal && strict(CompareOp::Gt))
+            }
+            (CompareOp::Lt | CompareOp::Le, CompareOp::Lt | CompareOp::Ne) => {
+                ordering == Ordering::Less || (ordering == Ordering::Equal && strict(CompareOp::Lt))
+            }
+            (CompareOp::Gt | CompareOp::Ge, CompareOp::Ge) => ordering != Ordering::Less,
+            (CompareOp::Lt | CompareOp::Le, CompareOp::Le) => ordering != Ordering::Greater,
+            (CompareOp::Ne, CompareOp::Ne) => ordering == Ordering::Equal,
+            _ => false,
+        }
+    }
+}
+
+/// Returns whether `left op right` is true. Nulls never compare.
+fn compare(left: &Value, op: CompareOp, right: &Value) -> bool {
+    if left.is_null() || right.is_null() {
+        return false;
+    }
+    match op {
+        CompareOp::Eq => left == right,
+        CompareOp::Ne => left != right,
+        CompareOp::Lt => left < right,
+        CompareOp::Le => left <= right,
+        CompareOp::Gt => left > right,
+        CompareOp::Ge => left >= right,
+    }
+}
+
+/// Selectivity assumed for an equality predicate on a column without stats.
+const DEFAULT_EQ_SELECTIVITY: f64 = 0.1;
+
+/// Selectivity assumed for a range predicate on a column without stats.
+const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// Maximum number of characters shown in a cell by `Table::to_ascii_table`.
+const MAX_CELL_WIDTH: usize = 32;
+
+/// Statistics about the values in one column of a `Table`, as collected by
+/// `Table::analyze`. NaNs are unordered, so they are left out of `min` and
+/// `max`, but they count as a single distinct value as for `DISTINCT`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub row_count: usize,
+    pub null_count: usize,
+    pub distinct_count: usize,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+}
+
+/// An aggregate function over the values of a column. Like in SQL, nulls
+/// are ignored, and every aggregate except `Count` is `Null` if there are
+/// no other values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// The number of non-null values, as a `BigInt`.
+    Count,
+    /// The sum of the values: a `BigInt` or `UnsignedBigInt` for signed or
+    /// unsigned integers, a `Double` for floats, or a `Decimal` of the same
+    /// scale. Returns an error if an integer or decimal sum overflows.
+    Sum,
+    /// The smallest value.
+    Min,
+    /// The largest value.
+    Max,
+    /// The mean of the values, as a `Double`.
+    Avg,
+}
+
+/// Computes an `Aggregate` over values passed to it one at a time, so that
+/// the values do not all need to be held in memory.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    aggregate: Aggregate,
+    count: u64,
+    sum: Option<Value>,
+    float_sum: f64,
+    best: Option<Value>,
+}
+
+impl Accumulator {
+    /// Creates a new `Accumulator` for `aggregate` which has seen no values.
+    pub fn new(aggregate: Aggregate) -> Self {
+        Accumulator {
+            aggregate,
+            count: 0,
+            sum: None,
+            float_sum: 0.0,
+            best: None,
+        }
+    }
+
+    /// Adds `value` to the aggregate. Returns an error if `Sum` or `Avg` is
+    /// given a non-numeric value, or a sum overflows.
+    pub fn update(&mut self, value: &Value) -> DBResult<()> {
+        if value.is_null() {
+            return Ok(());
+        }
+        let mismatch = || {
+            DBError::from(StorageError::TypeMismatch(format!(
+                "cannot compute {:?} of {:?}.",
+                self.aggregate, value
+            )))
+        };
+
+        match self.aggregate {
+            Aggregate::Count => {}
+            Aggregate::Sum => {
+                let sum = match self.sum.take() {
+                    None => widen_for_sum(value).ok_or_else(mismatch)?,
+                    Some(Value::Double(sum)) => {
+                        Value::Double(sum + value.as_f64().ok_or_else(mismatch)?)
+                    }
+                    Some(Value::Decimal(sum, scale)) => {
+                        let Value::Decimal(v, _) = value else {
+                            return Err(mismatch());
+                        };
+                        let sum = sum.checked_add(*v).ok_or_else(|| {
+                            DBError::from(StorageError::ArithmeticError(format!(
+                                "sum of Decimal({}) values overflows.",
+                                scale
+                            )))
+                        })?;
+                        Value::Decimal(sum, scale)
+                    }
+                    Some(sum) => sum.add(value)?,
+                };
+                self.sum = Some(sum);
+            }
+            Aggregate::Avg => self.float_sum += value.as_f64().ok_or_else(mismatch)?,
+            Aggregate::Min | Aggregate::Max => {
+                let wanted = if self.aggregate == Aggregate::Min {
+                    std::cmp::Ordering::Less
+                } else {
+                    std::cmp::Ordering::Greater
+                };
+                let replace = match &self.best {
+                    None => true,
+                    Some(best) => value.partial_cmp(best) == Some(wanted),
+                };
+                if replace {
+                    self.best = Some(value.clone());
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Returns the value of the aggregate over every value seen so far.
+    pub fn finish(&self) -> Value {
+        match self.aggregate {
+            Aggregate::Count => Value::BigInt(self.count as i64),
+            Aggregate::Sum => self.sum.clone().unwrap_or(Value::Null),
+            Aggregate::Avg if self.count == 0 => Value::Null,
+            Aggregate::Avg => Value::Double(self.float_sum / self.count as f64),
+            Aggregate::Min | Aggregate::Max => self.best.clone().unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// Returns `value` converted to the type of a `Sum` of values like it, or
+/// `None` if it cannot be summed.
+fn widen_for_sum(value: &Value) -> Option<Value> {
+    match value {
+        Value::Float(_) | Value::Double(_) => value.as_f64().map(Value::Double),
+        Value::Decimal(..) => Some(value.clone()),
+        Value::UnsignedTiny(_)
+        | Value::UnsignedShort(_)
+        | Value::UnsignedInt(_)
+        | Value::UnsignedBigInt(_) => Some(Value::UnsignedBigInt(value.as_integer()?.0 as u64)),
+        _ => Some(Value::BigInt(value.as_integer()?.0 as i64)),
+    }
+}
+
+/// What `Table::insert_with` does when a tuple has the same primary key as
+/// a tuple already in the table, like SQLite's conflict clauses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Return a `DuplicateKey` error. This is the default.
+    #[default]
+    Error,
+    /// Leave the existing tuple and skip the new one.
+    Ignore,
+    /// Overwrite the existing tuple with the new one.
+    Replace,
+}
+
+/// What `Table::insert_with` did with a tuple.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// The tuple was added to the table.
+    Inserted,
+    /// The tuple was skipped because of `OnConflict::Ignore`.
+    Ignored,
+    /// The tuple overwrote another because of `OnConflict::Replace`.
+    Replaced,
+}
+
+/// The result of loading a batch of tuples into a table with
+/// `Table::load_from` or `HeapFile::load_csv`.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// Number of tuples which were inserted.
+    pub inserted: usize,
+    /// The position in the batch of each tuple which was rejected, or its
+    /// line number for `HeapFile::load_csv`, and the error it was rejected
+    /// with, in order.
+    pub rejected: Vec<(usize, DBError)>,
+}
+
+/// A view of the tuples of a `Table` which match a predicate, projected onto
+/// some of its columns, as returned by `Table::view`. Nothing is filtered or
+/// copied until the view is iterated, and iterating it again re-evaluates the
+/// predicate.
+pub struct TableView<'a, P> {
+    table: &'a Table,
+    predicate: P,
+    columns: Vec<usize>,
+    schema: Schema,
+}
+
+impl<'a, P: Fn(&Tuple) -> bool> TableView<'a, P> {
+    /// Returns the schema of the view's rows: the projected columns of the
+    /// table, in the order they were given to `Table::view`.
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Iterates over the projected values of each tuple of the table which
+    /// matches the predicate, in the table's order.
+    pub fn iter(&self) -> impl Iterator<Item = Tuple> + '_ {
+        self.table
+            .tuples
+            .iter()
+            .filter(|tuple| (self.predicate)(tuple))
+            .map(|tuple| {
+                let values = self
+                    .columns
+                    .iter()
+                    .map(|&column| tuple.values[column].clone());
+                Tuple::new(values.collect())
+            })
+    }
+}
+
+/// The primary key of a `Table`: the positions of its columns, and an index
+/// from each tuple's key values to its position in the table.
+#[derive(Debug, Clone)]
+struct PrimaryKey {
+    columns: Vec<usize>,
+    rows: HashMap<Tuple, usize>,
+}
+
+impl PrimaryKey {
+    /// Indexes `tuples` by the values of the key `columns`. Returns an error
+    /// if two tuples have the same key or any key value is null.
+    fn build(columns: Vec<usize>, tuples: &[Tuple], table: &str) -> DBResult<Self> {
+        let mut key = PrimaryKey {
+            columns,
+            rows: HashMap::new(),
+        };
+        for (row, tuple) in tuples.iter().enumerate() {
+            let values = key.key_of(tuple)?;
+            if key.rows.contains_key(&values) {
+                return Err(duplicate_key(table, &values));
+            }
+            key.rows.insert(values, row);
+        }
+        Ok(key)
+    }
+
+    /// Returns the values of the key columns of `tuple`, or an error if any
+    /// of them is null.
+    fn key_of(&self, tuple: &Tuple) -> DBResult<Tuple> {
+        let values: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|&column| tuple.values[column].clone())
+            .collect();
+        if values.iter().any(Value::is_null) {
+            return Err(DBError::from(StorageError::TypeMismatch(
+                "primary key columns cannot be null.".to_string(),
+            )));
+        }
+        Ok(Tuple::new(values))
+    }
+}
+
+/// A partial index of a `Table`: an index from the values of one column to
+/// the positions of the tuples with that value, covering only the tuples
+/// which satisfy `filter`. Nulls are not indexed.
+#[derive(Debug, Clone)]
+struct PartialIndex {
+    column: usize,
+    filter: Comparison,
+    /// Positions of the tuples with each value, in ascending order.
+    rows: HashMap<Tuple, Vec<usize>>,
+}
+
+impl PartialIndex {
+    /// Adds the tuple at position `row` to the index if it satisfies the
+    /// filter.
+    fn add(&mut self, tuple: &Tuple, row: usize) {
+        let value = &tuple.values[self.column];
+        if value.is_null() || !self.filter.matches(tuple) {
+            return;
+        }
+        let rows = self
+            .rows
+            .entry(Tuple::new(vec![value.clone()]))
+            .or_default();
+        if let Err(i) = rows.binary_search(&row) {
+            rows.insert(i, row);
+        }
+    }
+
+    /// Removes the tuple at position `row` from the index, if it is indexed.
+    fn remove(&mut self, tuple: &Tuple, row: usize) {
+        let key = Tuple::new(vec![tuple.values[self.column].clone()]);
+        if let Some(rows) = self.rows.get_mut(&key) {
+            rows.retain(|&r| r != row);
+            if rows.is_empty() {
+                self.rows.remove(&key);
+            }
+        }
+    }
+}
+
 pub struct Table {
     name: String,
     schema: Schema,
     tuples: Vec<Tuple>,
+    stats: Vec<Option<ColumnStats>>,
+    key: Option<PrimaryKey>,
+    indexes: Vec<PartialIndex>,
+    /// Versions of the tuples which have been replaced or updated, by
+    /// position. Every other tuple is at version 0.
+    versions: HashMap<usize, u64>,
+}
+
+impl Table {
+    /// Creates a new empty `Table` with the given `name` and `schema`.
+    pub fn new(name: String, schema: Schema) -> Self {
+        Table {
+            name,
+            schema,
+            tuples: Vec::new(),
+            stats: Vec::new(),
+            key: None,
+            indexes: Vec::new(),
+            versions: HashMap::new(),
+        }
+    }
+
+    /// Makes the columns named in `columns` the primary key of the table, so
+    /// that no two tuples can have the same values in all of them and none
+    /// of them can be null. Returns an error if a column does not exist, no
+    /// columns are given, or the tuples already in the table violate the key.
+    pub fn with_primary_key(mut self, columns: &[&str]) -> DBResult<Self> {
+        if columns.is_empty() {
+            return Err(DBError::from(StorageError::InvalidArgument(
+                "a primary key needs at least one column.".to_string(),
+            )));
+        }
+        let positions = columns
+            .iter()
+            .map(|name| {
+                self.schema.column_index(name).ok_or_else(|| {
+                    DBError::from(StorageError::InvalidArgument(format!(
+                        "schema has no column named `{}`.",
+                        name
+                    )))
+                })
+            })
+            .collect::<DBResult<_>>()?;
+
+        self.key = Some(PrimaryKey::build(positions, &self.tuples, &self.name)?);
+        Ok(self)
+    }
+
+    /// Returns the tuple whose primary key columns have the given `key`
+    /// values, in the order the columns were given to `with_primary_key`.
+    /// Returns `None` if there is no such tuple or the table has no key.
+    pub fn get_by_key(&self, key: &[Value]) -> Option<&Tuple> {
+        let row = *self.key.as_ref()?.rows.get(&Tuple::new(key.to_vec()))?;
+        Some(&self.tuples[row])
+    }
+
+    /// Returns the name of the table.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Renames the table. Names derived from it later, such as those of the
+    /// tables returned by `partition`, use `new_name`.
+    pub fn rename(&mut self, new_name: String) {
+        self.name = new_name;
+    }
+
+    /// Returns the schema of the table.
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Renames a column of the table; see `Schema::rename_column`.
+    pub fn rename_column(&mut self, old: &str, new: &str) -> DBResult<()> {
+        self.schema.rename_column(old, new)
+    }
+
+    /// Reorders the columns of the table's schema as in
+    /// `Schema::reorder_columns`, and rearranges the values of every tuple
+    /// to match.
+    pub fn reorder_columns(&mut self, new_order: &[usize]) -> DBResult<()> {
+        self.schema.reorder_columns(new_order)?;
+        if let Some(key) = &mut self.key {
+            for column in &mut key.columns {
+                *column = new_order.iter().position(|&old| old == *column).unwrap();
+            }
+        }
+        let new_position = |column: usize| new_order.iter().position(|&old| old == column).unwrap();
+        for index in &mut self.indexes {
+            index.column = new_position(index.column);
+            index.filter.column = new_position(index.filter.column);
+        }
+        for tuple in &mut self.tuples {
+            tuple.values = reorder(&tuple.values, new_order);
+        }
+        if !self.stats.is_empty() {
+            self.stats = reorder(&self.stats, new_order);
+        }
+        Ok(())
+    }
+
+    /// Inserts `tuple` into the table, returning an error if it does not
+    /// conform to the table's schema or has the same primary key as another
+    /// tuple.
+    pub fn insert(&mut self, tuple: Tuple) -> DBResult<()> {
+        self.insert_with(tuple, OnConflict::Error).map(|_| ())
+    }
+
+    /// Inserts `tuple` into the table like `insert`, but handles a tuple
+    /// with the same primary key as another according to `on_conflict`.
+    /// Replaced tuples keep their position, so the key still refers to the
+    /// new tuple. Tables without a primary key have no conflicts.
+    pub fn insert_with(
+        &mut self,
+        tuple: Tuple,
+        on_conflict: OnConflict,
+    ) -> DBResult<InsertOutcome> {
+        self.schema.check_tuple(&tuple)?;
+        let row = self.tuples.len();
+        let Some(key) = &mut self.key else {
+            self.indexes
+                .iter_mut()
+                .for_each(|index| index.add(&tuple, row));
+            self.tuples.push(tuple);
+            return Ok(InsertOutcome::Inserted);
+        };
+
+        let values = key.key_of(&tuple)?;
+        match (key.rows.get(&values), on_conflict) {
+            (None, _) => {
+                key.rows.insert(values, row);
+                self.indexes
+                    .iter_mut()
+                    .for_each(|index| index.add(&tuple, row));
+                self.tuples.push(tuple);
+                Ok(InsertOutcome::Inserted)
+            }
+            (Some(_), OnConflict::Error) => Err(duplicate_key(&self.name, &values)),
+            (Some(_), OnConflict::Ignore) => Ok(InsertOutcome::Ignored),
+            (Some(&row), OnConflict::Replace) => {
+                self.replace(row, tuple);
+                Ok(InsertOutcome::Replaced)
+            }
+        }
+    }
+
+    /// Returns the version of the tuple at position `row`, or `None` if
+    /// there is no such tuple. A tuple is at version 0 when it is inserted,
+    /// and its version is incremented each time it is replaced or updated.
+    pub fn version(&self, row: usize) -> Option<u64> {
+        (row < self.tuples.len()).then(|| self.versions.get(&row).copied().unwrap_or(0))
+    }
+
+    /// Replaces the tuple at position `row` with `tuple` if it is still at
+    /// `expected_version`, and returns its new version. This lets a writer
+    /// detect that the tuple changed since it read it without holding a
+    /// lock. Returns a `VersionConflict` error if the version has changed,
+    /// or another error if there is no tuple at `row`, `tuple` does not
+    /// conform to the schema, or its primary key belongs to another tuple.
+    pub fn update_if_version(
+        &mut self,
+        row: usize,
+        expected_version: u64,
+        tuple: Tuple,
+    ) -> DBResult<u64> {
+        let actual = self.version(row).ok_or_else(|| {
+            DBError::from(StorageError::InvalidArgument(format!(
+                "row {} is out of range for a table with {} tuples.",
+                row,
+                self.tuples.len()
+            )))
+        })?;
+        if actual != expected_version {
+            return Err(DBError::from(StorageError::VersionConflict {
+                row,
+                expected: expected_version,
+                actual,
+            }));
+        }
+        self.schema.check_tuple(&tuple)?;
+        if let Some(key) = &mut self.key {
+            let new_key = key.key_of(&tuple)?;
+            match key.rows.get(&new_key) {
+                Some(&other) if other != row => return Err(duplicate_key(&self.name, &new_key)),
+                Some(_) => {}
+                None => {
+                    key.rows.remove(&key.key_of(&self.tuples[row])?);
+                    key.rows.insert(new_key, row);
+                }
+            }
+        }
+        Ok(self.replace(row, tuple))
+    }
+
+    /// Overwrites the tuple at position `row`, updating the partial indexes,
+    /// and returns its new version. The primary key must already refer to
+    /// `tuple`.
+    fn replace(&mut self, row: usize, tuple: Tuple) -> u64 {
+        for index in &mut self.indexes {
+            index.remove(&self.tuples[row], row);
+            index.add(&tuple, row);
+        }
+        self.tuples[row] = tuple;
+        let version = self.versions.entry(row).or_default();
+        *version += 1;
+        *version
+    }
+
+    /// Creates an index on `column` covering only the tuples which satisfy
+    /// `filter`, which is kept up to date as tuples are inserted. `lookup`
+    /// uses it for queries whose filters imply `filter`. Returns an error if
+    /// either column is out of range.
+    pub fn create_partial_index(&mut self, column: usize, filter: Comparison) -> DBResult<()> {
+        self.schema.column_type(column)?;
+        self.schema.column_type(filter.column)?;
+        let mut index = PartialIndex {
+            column,
+            filter,
+            rows: HashMap::new(),
+        };
+        for (row, tuple) in self.tuples.iter().enumerate() {
+            index.add(tuple, row);
+        }
+        self.indexes.push(index);
+        Ok(())
+    }
+
+    /// Returns the tuples whose `column` equals `value` and which satisfy
+    /// every one of `filters`, in insertion order. If the query implies the
+    /// filter of a partial index on `column`, only the tuples in the index
+    /// are checked; otherwise the whole table is scanned. Returns an error if
+    /// a column is out of range.
+    pub fn lookup(
+        &self,
+        column: usize,
+        value: &Value,
+        filters: &[Comparison],
+    ) -> DBResult<Vec<&Tuple>> {
+        self.schema.column_type(column)?;
+        for filter in filters {
+            self.schema.column_type(filter.column)?;
+        }
+        let matches = |tuple: &&Tuple| {
+            compare(&tuple.values[column], CompareOp::Eq, value)
+                && filters.iter().all(|filter| filter.matches(tuple))
+        };
+        Ok(match self.find_index(column, value, filters) {
+            Some(index) => index
+                .rows
+                .get(&Tuple::new(vec![value.clone()]))
+                .map_or(&[][..], Vec::as_slice)
+                .iter()
+                .map(|&row| &self.tuples[row])
+                .filter(matches)
+                .collect(),
+            None => self.tuples.iter().filter(matches).collect(),
+        })
+    }
+
+    /// Returns a partial index on `column` which covers every tuple matched
+    /// by a `lookup` of `value` with `filters`, if there is one.
+    fn find_index(
+        &self,
+        column: usize,
+        value: &Value,
+        filters: &[Comparison],
+    ) -> Option<&PartialIndex> {
+        let equality = Comparison::new(column, CompareOp::Eq, value.clone());
+        self.indexes.iter().find(|index| {
+            index.column == column
+                && std::iter::once(&equality)
+                    .chain(filters)
+                    .any(|filter| filter.implies(&index.filter))
+        })
+    }
+
+    /// Inserts each of `rows` into the table as in `insert`. Rows which are
+    /// rejected, such as for not conforming to the schema, are skipped and
+    /// recorded in the returned report instead of stopping the load.
+    pub fn load_from(&mut self, rows: impl Iterator<Item = Tuple>) -> LoadReport {
+        let mut report = LoadReport::default();
+        for (i, row) in rows.enumerate() {
+            match self.insert(row) {
+                Ok(()) => report.inserted += 1,
+                Err(e) => report.rejected.push((i, e)),
+            }
+        }
+        report
+    }
+
+    /// Returns an iterator over the tuples in the table, in insertion order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Tuple> {
+        self.tuples.iter()
+    }
+
+    /// Returns an iterator over the tuples in the table, most recently
+    /// inserted first.
+    pub fn iter_rev(&self) -> impl DoubleEndedIterator<Item = &Tuple> {
+        self.iter().rev()
+    }
+
+    /// Returns the number of tuples in the table.
+    pub fn len(&self) -> usize {
+        self.tuples.len()
+    }
+
+    /// Returns `true` if the table contains no tuples.
+    pub fn is_empty(&self) -> bool {
+        self.tuples.is_empty()
+    }
+
+    /// Returns the distinct tuples in the table, in the order they were first
+    /// inserted. Tuples are compared with `Tuple`'s equality, so nulls in
+    /// the same column count as equal, as they do for `DISTINCT` in SQL.
+    pub fn distinct(&self) -> Vec<&Tuple> {
+        let mut seen = HashSet::new();
+        self.tuples
+            .iter()
+            .filter(|tuple| seen.insert(*tuple))
+            .collect()
+    }
+
+    /// Returns the distinct values of `column`, with all nulls counted as one
+    /// value as in `distinct`, in the order they were first inserted. If
+    /// `column` is the table's whole primary key, the values are instead
+    /// read from the key index without looking at the tuples, in no
+    /// particular order. Returns an error if `column` is out of range.
+    pub fn distinct_values(&self, column: usize) -> DBResult<Vec<Value>> {
+        self.schema.column_type(column)?;
+        if let Some(key) = self.key.as_ref().filter(|key| key.columns == [column]) {
+            // Key values are unique and never null, so every key is distinct
+            return Ok(key.rows.keys().map(|key| key.values[0].clone()).collect());
+        }
+        let mut seen = HashSet::new();
+        Ok(self
+            .tuples
+            .iter()
+            .map(|tuple| &tuple.values[column])
+            .filter(|value| seen.insert(Tuple::new(vec![(*value).clone()])))
+            .cloned()
+            .collect())
+    }
+
+    /// Splits the table into the tuples for which `pred` returns `true` and
+    /// those for which it returns `false`, keeping their insertion order.
+    /// Both tables have the same schema as this one and are named after it
+    /// with a `_matched` or `_unmatched` suffix, and the same primary key if
+    /// it has one. Neither has column stats or partial indexes, so `analyze`
+    /// should be called on them if needed.
+    pub fn partition<F: Fn(&Tuple) -> bool>(self, pred: F) -> (Table, Table) {
+        let (matched, unmatched) = self.tuples.into_iter().partition(|tuple| pred(tuple));
+        let split = |suffix: &str, tuples: Vec<Tuple>| {
+            let name = format!("{}_{}", self.name, suffix);
+            // Keys cannot conflict, since they did not in the whole table
+            let key = self.key.as_ref().map(|key| {
+                PrimaryKey::build(key.columns.clone(), &tuples, &name)
+                    .expect("subset of a table violates its primary key")
+            });
+            Table {
+                name,
+                schema: self.schema.clone(),
+                tuples,
+                stats: Vec::new(),
+                key,
+                indexes: Vec::new(),
+                versions: HashMap::new(),
+            }
+        };
+        (split("matched", matched), split("unmatched", unmatched))
+    }
+
+    /// Returns a view of the tuples of this table for which `predicate`
+    /// returns `true`, with only the given `columns`, in that order. The view
+    /// borrows the table and filters and projects each tuple as it is
+    /// iterated. Returns an error if a column is out of range or is given
+    /// more than once.
+    pub fn view<P: Fn(&Tuple) -> bool>(
+        &self,
+        predicate: P,
+        columns: &[usize],
+    ) -> DBResult<TableView<'_, P>> {
+        let types = columns
+            .iter()
+            .map(|&column| self.schema.column_type(column).cloned())
+            .collect::<DBResult<_>>()?;
+        let names = columns
+            .iter()
+            .map(|&column| self.schema.names[column].clone());
+        let mut schema = Schema::with_names(names.collect(), types)?
+            .with_size_limits(self.schema.max_value_bytes, self.schema.max_tuple_bytes);
+        schema.checksummed = columns
+            .iter()
+            .map(|&column| self.schema.checksummed[column])
+            .collect();
+        Ok(TableView {
+            table: self,
+            predicate,
+            columns: columns.to_vec(),
+            schema,
+        })
+    }
+
+    /// Joins this table with `other` on the values of `self_key` in this
+    /// table and `other_key` in `other`, by merging the two tables in a
+    /// single pass. Both tables must already be sorted in ascending order by
+    /// their key, which is checked as they are merged; nulls, which never
+    /// match, may appear anywhere.
+    ///
+    /// The result has a tuple for every pair of tuples with equal keys,
+    /// holding the values of this table's tuple followed by those of
+    /// `other`'s, in order of their keys and then their positions in each
+    /// table. It is named `<self>_<other>`, and its columns are named after
+    /// their table and column, such as `people.id`. Returns an error if a key
+    /// column is out of range, the key columns have different types, the
+    /// tables have the same name, or either table is not sorted by its key.
+    /// Strings of the same type match whatever their maximum lengths.
+    pub fn merge_sorted(
+        &self,
+        other: &Table,
+        self_key: usize,
+        other_key: usize,
+    ) -> DBResult<Table> {
+        let self_type = self.schema.column_type(self_key)?;
+        let other_type = other.schema.column_type(other_key)?;
+        let same_type = match (self_type, other_type) {
+            (Type::Char(_), Type::Char(_)) | (Type::Varchar(_), Type::Varchar(_)) => true,
+            (a, b) => a == b,
+        };
+        if !same_type {
+            return Err(DBError::from(StorageError::TypeMismatch(format!(
+                "cannot join key column of type {:?} with key column of type {:?}.",
+                self_type, other_type
+            ))));
+        }
+        let qualify = |table: &Table| {
+            let names = table.schema.names.iter();
+            names
+                .map(|column| format!("{}.{}", table.name, column))
+                .collect::<Vec<_>>()
+        };
+        let names = [qualify(self), qualify(other)].concat();
+        let types = [&self.schema.types[..], &other.schema.types].concat();
+        let mut merged = Table::new(
+            format!("{}_{}", self.name, other.name),
+            Schema::with_names(names, types)?,
+        );
+
+        let left = key_groups(self, self_key)?;
+        let right = key_groups(other, other_key)?;
+        let (mut i, mut j) = (0, 0);
+        while i < left.len() && j < right.len() {
+            let ((left_key, left_rows), (right_key, right_rows)) = (&left[i], &right[j]);
+            match left_key.partial_cmp(right_key) {
+                Some(Ordering::Less) => i += 1,
+                Some(Ordering::Greater) => j += 1,
+                _ => {
+                    for &left_row in left_rows {
+                        for &right_row in right_rows {
+                            let values = [
+                                &self.tuples[left_row].values[..],
+                                &other.tuples[right_row].values,
+                            ]
+                            .concat();
+                            merged.tuples.push(Tuple::new(values));
+                        }
+                    }
+                    (i, j) = (i + 1, j + 1);
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Computes `aggregate` over the values of `column`. Returns an error if
+    /// the column does not exist or the aggregate cannot be computed over
+    /// its values.
+    pub fn aggregate(&self, column: usize, aggregate: Aggregate) -> DBResult<Value> {
+        self.schema.column_type(column)?;
+        let mut accumulator = Accumulator::new(aggregate);
+        for tuple in &self.tuples {
+            accumulator.update(&tuple.values[column])?;
+        }
+        Ok(accumulator.finish())
+    }
+
+    /// Renders the table as a bordered ASCII grid, with a header row of
+    /// column names followed by one row per tuple. Values are formatted with
+    /// their `Display` implementation, and cells longer than
+    /// `MAX_CELL_WIDTH` characters are truncated with an ellipsis.
+    pub fn to_ascii_table(&self) -> String {
+        let header = self.schema.names().iter().cloned().map(truncate_cell);
+        let rows: Vec<Vec<String>> = std::iter::once(header.collect())
+            .chain(self.tuples.iter().map(|tuple| {
+                tuple
+                    .values()
+                    .iter()
+                    .map(|value| truncate_cell(value.to_string()))
+                    .collect()
+            }))
+            .collect();
+
+        let widths: Vec<usize> = (0..self.schema.names().len())
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let border = widths.iter().fold("+".to_string(), |border, &width| {
+            border + &"-".repeat(width + 2) + "+"
+        });
+
+        let mut out = format!("{}\n", border);
+        for (i, row) in rows.iter().enumerate() {
+            out.push('|');
+            for (cell, &width) in row.iter().zip(&widths) {
+                // Writing to a `String` cannot fail
+                let _ = write!(out, " {:<width$} |", cell, width = width);
+            }
+            out.push('\n');
+            if i == 0 || i == rows.len() - 1 {
+                out.push_str(&border);
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Writes the table to a new file at `path`, which `import` can read back
+    /// into an identical table without the database it came from. The file
+    /// holds the table's name, schema, and primary key, followed by each
+    /// tuple serialized against the schema, all length-prefixed so that the
+    /// file does not depend on any page size. Returns an error if the table
+    /// has a `Blob` column, since blobs are stored outside their tuples.
+    pub fn export(&self, path: &str) -> DBResult<()> {
+        if self.schema.types.contains(&Type::Blob) {
+            return Err(DBError::from(StorageError::UnsupportedType(format!(
+                "cannot export table `{}` with a blob column.",
+                self.name
+            ))));
+        }
+
+        let mut out = EXPORT_MAGIC.to_vec();
+        write_export_str(&mut out, &self.name);
+        out.extend((self.schema.types.len() as u32).to_le_bytes());
+        for (column, ty) in self.schema.names.iter().zip(&self.schema.types) {
+            write_export_str(&mut out, column);
+            write_export_str(&mut out, &ty.to_string());
+        }
+        out.extend(self.schema.checksummed.iter().map(|&checked| checked as u8));
+        out.extend((self.schema.max_value_bytes as u64).to_le_bytes());
+        out.extend((self.schema.max_tuple_bytes as u64).to_le_bytes());
+        let key = self.key.as_ref().map_or(&[][..], |key| &key.columns);
+        out.extend((key.len() as u32).to_le_bytes());
+        for &column in key {
+            out.extend((column as u32).to_le_bytes());
+        }
+
+        out.extend((self.tuples.len() as u64).to_le_bytes());
+        for tuple in &self.tuples {
+            let bytes = serialize_tuple(tuple, &self.schema)?;
+            out.extend((bytes.len() as u32).to_le_bytes());
+            out.extend(bytes);
+        }
+        Ok(std::fs::write(path, out)?)
+    }
+
+    /// Reads a table from a file written by `export`, with the same name,
+    /// schema, primary key, and tuples in the same order. Returns an error
+    /// if the file was not written by `export` or is truncated.
+    pub fn import(path: &str) -> DBResult<Table> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = ExportReader {
+            bytes: &bytes,
+            path,
+        };
+        if reader.take(EXPORT_MAGIC.len())? != EXPORT_MAGIC {
+            return Err(reader.invalid());
+        }
+
+        let name = reader.read_str()?;
+        let (mut names, mut types) = (Vec::new(), Vec::new());
+        for _ in 0..reader.read_u32()? {
+            names.push(reader.read_str()?);
+            types.push(reader.read_str()?.parse()?);
+        }
+        let mut schema = Schema::with_names(names, types)?;
+        for column in 0..schema.types.len() {
+            schema.checksummed[column] = reader.read_u8()? != 0;
+        }
+        let max_value_bytes = reader.read_u64()? as usize;
+        let max_tuple_bytes = reader.read_u64()? as usize;
+        let schema = schema.with_size_limits(max_value_bytes, max_tuple_bytes);
+        let key: Vec<String> = (0..reader.read_u32()?)
+            .map(|_| {
+                let column = reader.read_u32()? as usize;
+                schema
+                    .names
+                    .get(column)
+                    .cloned()
+                    .ok_or_else(|| reader.invalid())
+            })
+            .collect::<DBResult<_>>()?;
+
+        let mut table = Table::new(name, schema);
+        for _ in 0..reader.read_u64()? {
+            let len = reader.read_u32()? as usize;
+            let tuple = deserialize_tuple(reader.take(len)?, &table.schema)?;
+            table.insert(tuple)?;
+        }
+        if !key.is_empty() {
+            let key: Vec<&str> = key.iter().map(String::as_str).collect();
+            table = table.with_primary_key(&key)?;
+        }
+        Ok(table)
+    }
+
+    /// Collects `ColumnStats` for every column whose values can be ordered,
+    /// replacing any previously collected stats. Stats are not updated as
+    /// tuples are inserted, so this should be called again after large
+    /// changes to the table.
+    pub fn analyze(&mut self) {
+        self.stats = (0..self.schema.types.len())
+            .map(|column| self.analyze_column(column))
+            .collect();
+    }
+
+    /// Returns the stats collected for `column` by the last call to
+    /// `analyze`, if any.
+    pub fn column_stats(&self, column: usize) -> Option<&ColumnStats> {
+        self.stats.get(column).and_then(Option::as_ref)
+    }
+
+    /// Estimates the fraction of tuples for which `column op value` is true,
+    /// between 0 and 1. Nulls never match. Uses the column's min and max to
+    /// interpolate range predicates and its distinct count for equality;
+    /// columns without stats get a fixed default estimate.
+    pub fn estimate_selectivity(&self, column: usize, op: CompareOp, value: &Value) -> f64 {
+        let stats = match (self.column_stats(column), self.schema.column_type(column)) {
+            (Some(stats), Ok(ty)) if value.check_type(ty).is_ok() => stats,
+            _ => {
+                return match op {
+                    CompareOp::Eq => DEFAULT_EQ_SELECTIVITY,
+                    CompareOp::Ne => 1.0 - DEFAULT_EQ_SELECTIVITY,
+                    _ => DEFAULT_RANGE_SELECTIVITY,
+                }
+            }
+        };
+
+        let (Some(min), Some(max)) = (&stats.min, &stats.max) else {
+            // The table is empty or the column is entirely null
+            return 0.0;
+        };
+        if value.is_null() {
+            return 0.0;
+        }
+
+        let non_null = (stats.row_count - stats.null_count) as f64 / stats.row_count as f64;
+        let eq = if value < min || value > max {
+            0.0
+        } else {
+            non_null / stats.distinct_count as f64
+        };
+
+        // Estimate where `value` falls between the min and max, assuming
+        // the values in between are evenly distributed
+        let below = match (min.as_f64(), max.as_f64(), value.as_f64()) {
+            (Some(lo), Some(hi), Some(v)) if hi > lo => (v - lo) / (hi - lo),
+            _ if value <= min => 0.0,
+            _ if value > max => 1.0,
+            _ => 0.5,
+        };
+        let lt = below.clamp(0.0, 1.0) * (non_null - eq);
+
+        let selectivity = match op {
+            CompareOp::Eq => eq,
+            CompareOp::Ne => non_null - eq,
+            CompareOp::Lt => lt,
+            CompareOp::Le => lt + eq,
+            CompareOp::Gt => non_null - lt - eq,
+            CompareOp::Ge => non_null - lt,
+        };
+        selectivity.clamp(0.0, 1.0)
+    }
+
+    /// Iterates over an `Int` column as native values, yielding `None` for
+    /// nulls. Returns an error if the column is not an `Int` column.
+    pub fn column_ints(&self, column: usize) -> DBResult<impl Iterator<Item = Option<i32>> + '_> {
+        self.typed_column(
+            column,
+            "Int",
+            |ty| *ty == Type::Int,
+            |value| match value {
+                Value::Int(v) => Some(*v),
+                _ => None,
+            },
+        )
+    }
+
+    /// Iterates over a `BigInt` column as native values, yielding `None` for
+    /// nulls. Returns an error if the column is not a `BigInt` column.
+    pub fn column_big_ints(
+        &self,
+        column: usize,
+    ) -> DBResult<impl Iterator<Item = Option<i64>> + '_> {
+        self.typed_column(
+            column,
+            "BigInt",
+            |ty| *ty == Type::BigInt,
+            |value| match value {
+                Value::BigInt(v) => Some(*v),
+                _ => None,
+            },
+        )
+    }
+
+    /// Iterates over a `Double` column as native values, yielding `None` for
+    /// nulls. Returns an error if the column is not a `Double` column.
+    pub fn column_doubles(
+        &self,
+        column: usize,
+    ) -> DBResult<impl Iterator<Item = Option<f64>> + '_> {
+        self.typed_column(
+            column,
+            "Double",
+            |ty| *ty == Type::Double,
+            |value| match value {
+                Value::Double(v) => Some(*v),
+                _ => None,
+            },
+        )
+    }
+
+    /// Iterates over a `Bool` column as native values, yielding `None` for
+    /// nulls. Returns an error if the column is not a `Bool` column.
+    pub fn column_bools(&self, column: usize) -> DBResult<impl Iterator<Item = Option<bool>> + '_> {
+        self.typed_column(
+            column,
+            "Bool",
+            |ty| *ty == Type::Bool,
+            |value| match value {
+                Value::Bool(v) => Some(*v),
+                _ => None,
+            },
+        )
+    }
+
+    /// Iterates over a `Char` or `Varchar` column as borrowed strings,
+    /// yielding `None` for nulls. Returns an error if the column is not a
+    /// `Char` or `Varchar` column.
+    pub fn column_strs(&self, column: usize) -> DBResult<impl Iterator<Item = Option<&str>> + '_> {
+        self.typed_column(
+            column,
+            "Char or Varchar",
+            |ty| matches!(ty, Type::Char(_) | Type::Varchar(_)),
+            |value| match value {
+                Value::Char(s) | Value::Varchar(s) => Some(s.as_str()),
+                _ => None,
+            },
+        )
+    }
+
+    /// Collects the stats of a single column, or returns `None` if its
+    /// values cannot be ordered.
+    fn analyze_column(&self, column: usize) -> Option<ColumnStats> {
+        if let Type::Blob | Type::Enum(_) | Type::Struct(_) = self.schema.types[column] {
+            return None;
+        }
+
+        let (nans, mut values): (Vec<&Value>, Vec<&Value>) = self
+            .tuples
+            .iter()
+            .map(|tuple| &tuple.values()[column])
+            .filter(|value| !value.is_null())
+            .partition(|value| match value {
+                Value::Float(v) => v.is_nan(),
+                Value::Double(v) => v.is_nan(),
+                _ => false,
+            });
+
+        // Without NaNs, the values of a column are totally ordered
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let null_count = self.tuples.len() - values.len() - nans.len();
+        let min = values.first().map(|&value| value.clone());
+        let max = values.last().map(|&value| value.clone());
+        values.dedup();
+
+        Some(ColumnStats {
+            row_count: self.tuples.len(),
+            null_count,
+            distinct_count: values.len() + usize::from(!nans.is_empty()),
+            min,
+            max,
+        })
+    }
+
+    /// Checks once that `column` has a type accepted by `is_type`, then
+    /// iterates over its values converted by `extract`. Since every tuple
+    /// conforms to the schema, `extract` only returns `None` for nulls.
+    fn typed_column<'a, T: 'a>(
+        &'a self,
+        column: usize,
+        type_name: &str,
+        is_type: fn(&Type) -> bool,
+        extract: fn(&'a Value) -> Option<T>,
+    ) -> DBResult<impl Iterator<Item = Option<T>> + 'a> {
+        let ty = self.schema.column_type(column)?;
+        if !is_type(ty) {
+            return Err(DBError::from(StorageError::TypeMismatch(format!(
+                "column {} has type {:?}, not {}.",
+                column, ty, type_name
+            ))));
+        }
+
+        Ok(self
+            .tuples
+            .iter()
+            .map(move |tuple| extract(&tuple.values()[column])))
+    }
+}
+
+/// Parses `s` as an integer of type `ty`, distinguishing values which are
+/// out of range, including negative values of unsigned types, from
+/// malformed ones in the error.
+fn parse_int<T: FromStr<Err = ParseIntError>>(s: &str, ty: &Type) -> DBResult<T> {
+    s.parse().map_err(|e: ParseIntError| {
+        let negative = s
+            .strip_prefix('-')
+            .is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()));
+        match e.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {}
+            IntErrorKind::InvalidDigit if negative => {}
+            _ => return parse_error(s, ty),
+        }
+        DBError::from(StorageError::ParseError(format!(
+            "`{}` is out of range for {:?}.",
+            s, ty
+        )))
+    })
+}
+
+/// Parses `s` as a decimal number with `scale` digits after the decimal
+/// point, returning it scaled to an integer.
+fn parse_decimal(s: &str, scale: u8) -> DBResult<i64> {
+    let ty = Type::Decimal(scale);
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let unsigned = whole.strip_prefix(['-', '+']).unwrap_or(whole);
+    let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if (unsigned.is_empty() && fraction.is_empty()) || !is_digits(unsigned) || !is_digits(fraction)
+    {
+        return Err(parse_error(s, &ty));
+    }
+    if fraction.len() > scale as usize {
+        return Err(DBError::from(StorageError::ParseError(format!(
+            "`{}` has more than {} digits after the decimal point.",
+            s, scale
+        ))));
+    }
+
+    // Parse the digits without the decimal point, padded to the full scale
+    let digits = format!(
+        "{}{}{:0<width$}",
+        &whole[..whole.len() - unsigned.len()],
+        if unsigned.is_empty() { "0" } else { unsigned },
+        fraction,
+        width = scale as usize
+    );
+    parse_int(&digits, &ty)
+}
+
+/// Number of microseconds in a day, the unit of `Timestamp` values.
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// Parses an ISO-8601 date of the form `YYYY-MM-DD`, returning the number of
+/// days since 1970-01-01.
+fn parse_date(s: &str) -> Option<i64> {
+    let parts: Vec<&str> = s.split('-').collect();
+    let lens: Vec<usize> = parts.iter().map(|part| part.len()).collect();
+    if lens != [4, 2, 2] || !parts.concat().bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year: i64 = parts[0].parse().ok()?;
+    let (month, day): (u32, u32) = (parts[1].parse().ok()?, parts[2].parse().ok()?);
+
+    let days = days_from_civil(year, month, 1);
+    let next_month = if month == 12 {
+        days_from_civil(year + 1, 1, 1)
+    } else {
+        days_from_civil(year, month + 1, 1)
+    };
+    let in_range = (1..=12).contains(&month) && day >= 1 && days + day as i64 <= next_month;
+    in_range.then_some(days + day as i64 - 1)
+}
+
+/// Parses an ISO-8601 timestamp in UTC, returning the number of
+/// microseconds since 1970-01-01 00:00:00. The date may be followed by `T`
+/// or a space and a time of the form `HH:MM:SS` with up to 6 fractional
+/// digits, and then an optional `Z`. A date alone is read as midnight.
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.strip_suffix('Z').unwrap_or(s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let days = parse_date(date)?;
+
+    let micros = match time {
+        Some(time) => {
+            let (hms, fraction) = time.split_once('.').unwrap_or((time, ""));
+            let fields: Vec<&str> = hms.split(':').collect();
+            let is_digits = |part: &str, max_len| {
+                !part.is_empty()
+                    && part.len() <= max_len
+                    && part.bytes().all(|b| b.is_ascii_digit())
+            };
+            if fields.len() != 3
+                || !fields
+                    .iter()
+                    .all(|field| field.len() == 2 && is_digits(field, 2))
+                || (time.contains('.') && !is_digits(fraction, 6))
+            {
+                return None;
+            }
+
+            let [hours, minutes, seconds] = [0, 1, 2].map(|i| fields[i].parse::<i64>().unwrap());
+            if hours > 23 || minutes > 59 || seconds > 59 {
+                return None;
+            }
+            let fraction: i64 = format!("{:0<6}", fraction).parse().unwrap();
+            ((hours * 60 + minutes) * 60 + seconds) * 1_000_000 + fraction
+        }
+        None => 0,
+    };
+    days.checked_mul(MICROS_PER_DAY)?.checked_add(micros)
+}
+
+/// Parses a hyphenated UUID such as `123e4567-e89b-12d3-a456-426614174000`,
+/// in either case.
+fn parse_uuid(s: &str) -> Option<[u8; 16]> {
+    let groups: Vec<&str> = s.split('-').collect();
+    let lens: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    if lens != [8, 4, 4, 4, 12] {
+        return None;
+    }
+
+    let hex = groups.concat();
+    let mut bytes = [0; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Returns the number of days from 1970-01-01 to the given date in the
+/// proleptic Gregorian calendar. `month` must be between 1 and 12.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Count years from March, so that leap days come at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the year, month, and day of the date `days` after 1970-01-01;
+/// the inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = era * 400 + year_of_era + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Returns the error for a string `s` which is not a valid value of `ty`.
+fn parse_error(s: &str, ty: &Type) -> DBError {
+    DBError::from(StorageError::ParseError(format!(
+        "`{}` is not a valid {:?}.",
+        s, ty
+    )))
+}
+
+/// Returns the error for a tuple whose primary `key` values are already in
+/// the table called `table`.
+fn duplicate_key(table: &str, key: &Tuple) -> DBError {
+    DBError::from(StorageError::DuplicateKey(format!(
+        "table `{}` already has a row with key ({}).",
+        table,
+        join_values(key.values())
+    )))
+}
+
+/// Formats `values` separated by commas.
+fn join_values(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns `cell` truncated with an ellipsis if it is longer than
+/// `MAX_CELL_WIDTH` characters.
+fn truncate_cell(cell: String) -> String {
+    if cell.chars().count() <= MAX_CELL_WIDTH {
+        return cell;
+    }
+    let mut truncated: String = cell.chars().take(MAX_CELL_WIDTH - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Returns the type which values of the integer types `a` and `b` are both
+/// promoted to for arithmetic: the wider of the two, if they have the same
+/// signedness.
+fn promote_integers(a: &Type, b: &Type) -> Option<Type> {
+    const SIGNED: [Type; 4] = [Type::Tiny, Type::Short, Type::Int, Type::BigInt];
+    const UNSIGNED: [Type; 4] = [
+        Type::UnsignedTiny,
+        Type::UnsignedShort,
+        Type::UnsignedInt,
+        Type::UnsignedBigInt,
+    ];
+
+    [SIGNED, UNSIGNED].into_iter().find_map(|widths| {
+        let a = widths.iter().position(|ty| ty == a)?;
+        let b = widths.iter().position(|ty| ty == b)?;
+        Some(widths[a.max(b)].clone())
+    })
+}
+
+/// Compares two lists of values as in `Tuple`'s equality, recursing into
+/// composite values.
+fn values_equal(a: &[Value], b: &[Value]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|pair| match pair {
+            (Value::Float(x), Value::Float(y)) => x == y || (x.is_nan() && y.is_nan()),
+            (Value::Double(x), Value::Double(y)) => x == y || (x.is_nan() && y.is_nan()),
+            (Value::Enum(x_tag, x), Value::Enum(y_tag, y)) => x_tag == y_tag && values_equal(x, y),
+            (Value::Struct(x), Value::Struct(y)) => values_equal(x, y),
+            (x, y) => x == y,
+        })
+}
+
+/// The bytes at the start of every file written by `Table::export`,
+/// followed by the version of its format.
+const EXPORT_MAGIC: &[u8] = b"SKIBITBL\0";
+
+/// Appends `s` to a file being written by `Table::export`, prefixed by its
+/// length.
+fn write_export_str(out: &mut Vec<u8>, s: &str) {
+    out.extend((s.len() as u32).to_le_bytes());
+    out.extend(s.as_bytes());
+}
+
+/// Reads the fields of a file written by `Table::export` in order.
+struct ExportReader<'a> {
+    bytes: &'a [u8],
+    path: &'a str,
+}
+
+impl<'a> ExportReader<'a> {
+    /// Returns the error for a file which was not written by `export`.
+    fn invalid(&self) -> DBError {
+        DBError::from(StorageError::DeserializationError(format!(
+            "`{}` is not a valid table export.",
+            self.path
+        )))
+    }
+
+    /// Reads the next `len` bytes.
+    fn take(&mut self, len: usize) -> DBResult<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(self.invalid());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn read_u8(&mut self) -> DBResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> DBResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> DBResult<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a string written by `write_export_str`.
+    fn read_str(&mut self) -> DBResult<String> {
+        let len = self.read_u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.invalid())
+    }
+}
+
+/// Groups the tuples of `table` by their value in the `key` column, skipping
+/// nulls, and returns each value and the positions of its tuples in order.
+/// Returns an error if the values are not in ascending order.
+fn key_groups(table: &Table, key: usize) -> DBResult<Vec<(&Value, Vec<usize>)>> {
+    let mut groups: Vec<(&Value, Vec<usize>)> = Vec::new();
+    for (row, tuple) in table.tuples.iter().enumerate() {
+        let value = &tuple.values[key];
+        if value.is_null() {
+            continue;
+        }
+        match groups.last_mut() {
+            Some((last, rows)) if *last == value => rows.push(row),
+            Some((last, _)) if (*last).partial_cmp(value) != Some(Ordering::Less) => {
+                return Err(DBError::from(StorageError::InvalidArgument(format!(
+                    "table `{}` is not sorted by column {}.",
+                    table.name, key
+                ))));
+            }
+            _ => groups.push((value, vec![row])),
+        }
+    }
+    Ok(groups)
+}
+
+/// Returns a copy of `items` with the item at position `i` taken from
+/// position `new_order[i]`. `new_order` must be a permutation of the
+/// positions of `items`.
+fn reorder<T: Clone>(items: &[T], new_order: &[usize]) -> Vec<T> {
+    new_order.iter().map(|&i| items[i].clone()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn people() -> DBResult<Table> {
+        let schema = Schema::new(vec![Type::Int, Type::Varchar(16)]);
+        let mut table = Table::new("people".to_string(), schema);
+        table.insert(Tuple::new(vec![
+            Value::Int(1),
+            Value::Varchar("alice".to_string()),
+        ]))?;
+        table.insert(Tuple::new(vec![Value::Int(2), Value::Null]))?;
+        table.insert(Tuple::new(vec![
+            Value::Null,
+            Value::Varchar("carol".to_string()),
+        ]))?;
+        Ok(table)
+    }
+
+    fn shape_catalog() -> DBResult<Catalog> {
+        use crate::catalog::{EnumDef, EnumVariant};
+
+        let mut catalog = Catalog::new();
+        catalog.define_enum(
+            "shape".to_string(),
+            EnumDef::new(vec![
+                EnumVariant::new("point".to_string(), vec![]),
+                EnumVariant::new("circle".to_string(), vec![Type::Double]),
+                EnumVariant::new("label".to_string(), vec![Type::Varchar(4), Type::Int]),
+            ])?,
+        )?;
+        Ok(catalog)
+    }
+
+    #[test]
+    fn test_validate_enum() -> DBResult<()> {
+        let catalog = shape_catalog()?;
+        assert_eq!(catalog.resolve_enum_variant("shape", 1)?, "circle");
+
+        Value::Enum(0, vec![]).validate_enum("shape", &catalog)?;
+        Value::Enum(1, vec![Value::Double(2.5)]).validate_enum("shape", &catalog)?;
+        Value::Enum(2, vec![Value::Varchar("ab".to_string()), Value::Null])
+            .validate_enum("shape", &catalog)?;
+
+        assert!(Value::Int(1).validate_enum("shape", &catalog).is_err());
+        assert!(Value::Enum(0, vec![])
+            .validate_enum("colour", &catalog)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_enum_out_of_range() -> DBResult<()> {
+        let catalog = shape_catalog()?;
+        assert!(catalog.resolve_enum_variant("shape", 3).is_err());
+        assert!(Value::Enum(3, vec![])
+            .validate_enum("shape", &catalog)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_enum_payload_mismatch() -> DBResult<()> {
+        let catalog = shape_catalog()?;
+
+        // Wrong type, wrong number of fields, and string too long
+        let values = [
+            Value::Enum(1, vec![Value::Int(2)]),
+            Value::Enum(1, vec![]),
+            Value::Enum(2, vec![Value::Varchar("abcde".to_string()), Value::Int(1)]),
+        ];
+        for value in values {
+            assert!(value.validate_enum("shape", &catalog).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_selectivity() -> DBResult<()> {
+        // 1000 rows cycling through 0..100, with every tenth row null
+        let schema = Schema::new(vec![Type::Int, Type::Varchar(8)]);
+        let mut table = Table::new("numbers".to_string(), schema);
+        for i in 0..1000 {
+            let value = if i % 10 == 0 {
+                Value::Null
+            } else {
+                Value::Int(i % 100)
+            };
+            table.insert(Tuple::new(vec![
+                value,
+                Value::Varchar(format!("{}", i % 4)),
+            ]))?;
+        }
+        table.analyze();
+
+        let stats = table.column_stats(0).unwrap();
+        assert_eq!(stats.null_count, 100);
+        assert_eq!(stats.distinct_count, 90);
+        assert_eq!(stats.min, Some(Value::Int(1)));
+        assert_eq!(stats.max, Some(Value::Int(99)));
+
+        let actual = |column: usize, op: CompareOp, value: &Value| {
+            let matches = table
+                .iter()
+                .filter(|tuple| {
+                    let v = &tuple.values()[column];
+                    *v != Value::Null
+                        && match op {
+                            CompareOp::Eq => v == value,
+                            CompareOp::Ne => v != value,
+                            CompareOp::Lt => v < value,
+                            CompareOp::Le => v <= value,
+                            CompareOp::Gt => v > value,
+                            CompareOp::Ge => v >= value,
+                        }
+                })
+                .count();
+            matches as f64 / table.len() as f64
+        };
+
+        let ops = [
+            CompareOp::Eq,
+            CompareOp::Ne,
+            CompareOp::Lt,
+            CompareOp::Le,
+            CompareOp::Gt,
+            CompareOp::Ge,
+        ];
+        for op in ops {
+            for v in [-5, 1, 25, 50, 99, 150] {
+                let value = Value::Int(v);
+                let estimate = table.estimate_selectivity(0, op, &value);
+                assert!((0.0..=1.0).contains(&estimate));
+                assert!(
+                    (estimate - actual(0, op, &value)).abs() < 0.05,
+                    "{:?} {}: estimated {} but was {}",
+                    op,
+                    v,
+                    estimate,
+                    actual(0, op, &value)
+                );
+            }
+        }
+
+        let value = Value::Varchar("2".to_string());
+        let estimate = table.estimate_selectivity(1, CompareOp::Eq, &value);
+        assert!((estimate - actual(1, CompareOp::Eq, &value)).abs() < 0.05);
+        Ok(())
+    }
+
+    #[test]
+    fn test_analyze_with_nans() -> DBResult<()> {
+        let schema = Schema::new(vec![Type::Double]);
+        let mut table = Table::new("readings".to_string(), schema);
+        for value in [f64::NAN, 2.0, -f64::NAN, 1.0, f64::NAN, 2.0] {
+            table.insert(Tuple::new(vec![Value::Double(value)]))?;
+        }
+        table.insert(Tuple::new(vec![Value::Null]))?;
+        table.analyze();
+
+        let stats = table.column_stats(0).unwrap();
+        assert_eq!(stats.row_count, 7);
+        assert_eq!(stats.null_count, 1);
+        assert_eq!(stats.distinct_count, 3);
+        assert_eq!(stats.min, Some(Value::Double(1.0)));
+        assert_eq!(stats.max, Some(Value::Double(2.0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_selectivity_without_stats() -> DBResult<()> {
+        let table = people()?;
+        let value = Value::Int(1);
+        assert_eq!(
+            table.estimate_selectivity(0, CompareOp::Eq, &value),
+            DEFAULT_EQ_SELECTIVITY
+        );
+        assert_eq!(
+            table.estimate_selectivity(0, CompareOp::Gt, &value),
+            DEFAULT_RANGE_SELECTIVITY
+        );
+        Ok(())
+    }
+
+    fn named_people() -> DBResult<Table> {
+        let schema = Schema::with_names(
+            vec!["id".to_string(), "name".to_string(), "active".to_string()],
+            vec![Type::Int, Type::Varchar(16), Type::Bool],
+        )?;
+        let mut table = Table::new("people".to_string(), schema);
+        table.insert(Tuple::new(vec![
+            Value::Int(1),
+            Value::Varchar("alice".to_string()),
+            Value::Bool(true),
+        ]))?;
+        table.insert(Tuple::new(vec![
+            Value::Int(2),
+            Value::Null,
+            Value::Bool(false),
+        ]))?;
+        Ok(table)
+    }
+
+    #[test]
+    fn test_to_ascii_table() -> DBResult<()> {
+        let table = named_people()?;
+        assert_eq!(
+            table.to_ascii_table(),
+            "+----+-------+--------+\n\
+             | id | name  | active |\n\
+             +----+-------+--------+\n\
+             | 1  | alice | true   |\n\
+             | 2  | NULL  | false  |\n\
+             +----+-------+--------+\n"
+        );
+
+        // Long values are cut off at the maximum width
+        let mut table = Table::new(
+            "notes".to_string(),
+            Schema::new(vec![Type::Int, Type::Varchar(64)]),
+        );
+        table.insert(Tuple::new(vec![
+            Value::Int(3),
+            Value::Varchar("x".repeat(40)),
+        ]))?;
+        let grid = table.to_ascii_table();
+        let truncated = format!("| {}… |", "x".repeat(MAX_CELL_WIDTH - 1));
+        assert!(grid.contains(&truncated));
+        assert!(grid.contains("| column_0 |"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_display() {
+        assert_eq!(Value::Decimal(12345, 2).to_string(), "123.45");
+        assert_eq!(Value::Decimal(-5, 2).to_string(), "-0.05");
+        assert_eq!(
+            Value::Enum(1, vec![Value::Double(2.5)]).to_string(),
+            "#1(2.5)"
+        );
+        assert_eq!(
+            Value::Struct(vec![Value::Int(1), Value::Null]).to_string(),
+            "{1, NULL}"
+        );
+    }
+
+    #[test]
+    fn test_schema_fingerprint() -> DBResult<()> {
+        let schema = |names: [&str; 2], types: [Type; 2]| {
+            Schema::with_names(names.map(String::from).to_vec(), types.to_vec())
+        };
+        let base = schema(["id", "name"], [Type::Int, Type::Varchar(16)])?;
+        assert_eq!(
+            base.fingerprint(),
+            schema(["id", "name"], [Type::Int, Type::Varchar(16)])?.fingerprint()
+        );
+        // Fingerprints must not change between releases
+        assert_eq!(base.fingerprint(), 0xa544_ab10_a262_be0f);
+
+        let mut reordered = base.clone();
+        reordered.reorder_columns(&[1, 0])?;
+        let changed = [
+            reordered,
+            schema(["id", "name"], [Type::Int, Type::Varchar(17)])?,
+            schema(["id", "name"], [Type::BigInt, Type::Varchar(16)])?,
+            schema(["id", "nam"], [Type::Int, Type::Varchar(16)])?,
+            base.clone().with_checksum("name")?,
+        ];
+        for schema in changed {
+            assert_ne!(schema.fingerprint(), base.fingerprint());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_tuple() -> DBResult<()> {
+        let schema = Schema::with_names(
+            ["id", "name", "price", "ok"].map(String::from).to_vec(),
+            vec![Type::Int, Type::Varchar(4), Type::Decimal(2), Type::Bool],
+        )?;
+        let valid = Tuple::new(vec![
+            Value::Int(1),
+            Value::Varchar("pen".to_string()),
+            Value::Null,
+            Value::Bool(true),
+        ]);
+        assert_eq!(schema.validate_tuple(&valid), Ok(()));
+
+        let invalid = Tuple::new(vec![
+            Value::BigInt(1),
+            Value::Varchar("pencil".to_string()),
+            Value::Decimal(150, 2),
+        ]);
+        let errors = schema.validate_tuple(&invalid).unwrap_err();
+        let columns: Vec<usize> = errors.iter().map(|error| error.column).collect();
+        assert_eq!(columns, [0, 1, 3]);
+        assert!(errors[1].message.contains("longer than the column maximum"));
+        assert!(errors[2].message.contains("`ok`"));
+
+        let extra = Tuple::new([valid.values(), &[Value::Int(5)]].concat());
+        let errors = schema.validate_tuple(&extra).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].column, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_diff() -> DBResult<()> {
+        let names = |names: &[&str]| names.iter().map(|s| s.to_string()).collect();
+        let old = Schema::with_names(
+            names(&["id", "name", "age", "email"]),
+            vec![Type::Int, Type::Varchar(16), Type::Short, Type::Varchar(32)],
+        )?;
+        assert!(old.diff(&old).is_empty());
+
+        // Widen `age`, drop `email`, rename `name`, and add `active`
+        let new = Schema::with_names(
+            names(&["id", "full_name", "age", "active"]),
+            vec![Type::Int, Type::Varchar(16), Type::Int, Type::Bool],
+        )?;
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, [("active".to_string(), Type::Bool)]);
+        assert_eq!(diff.removed, [("email".to_string(), Type::Varchar(32))]);
+        assert_eq!(
+            diff.renamed,
+            [("name".to_string(), "full_name".to_string())]
+        );
+        assert_eq!(
+            diff.type_changes,
+            [("age".to_string(), Type::Short, Type::Int)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_column() -> DBResult<()> {
+        let mut table = named_people()?;
+        table.rename_column("name", "full_name")?;
+        assert_eq!(table.schema().column_index("full_name"), Some(1));
+        assert_eq!(table.schema().column_index("name"), None);
+
+        // Renaming a column to its own name is allowed
+        table.rename_column("id", "id")?;
+        assert!(table.rename_column("missing", "other").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_column_collision() -> DBResult<()> {
+        let mut table = named_people()?;
+        assert!(table.rename_column("name", "id").is_err());
+        assert_eq!(table.schema().names(), ["id", "name", "active"]);
+
+        assert!(Schema::with_names(
+            vec!["a".to_string(), "a".to_string()],
+            vec![Type::Int, Type::Int]
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reorder_columns() -> DBResult<()> {
+        let mut table = named_people()?;
+        table.reorder_columns(&[2, 0, 1])?;
+
+        assert_eq!(table.schema().names(), ["active", "id", "name"]);
+        assert_eq!(
+            table.schema().types(),
+            [Type::Bool, Type::Int, Type::Varchar(16)]
+        );
+        let rows: Vec<&Tuple> = table.iter().collect();
+        assert_eq!(
+            rows[0].values(),
+            [
+                Value::Bool(true),
+                Value::Int(1),
+                Value::Varchar("alice".to_string())
+            ]
+        );
+        assert_eq!(
+            rows[1].values(),
+            [Value::Bool(false), Value::Int(2), Value::Null]
+        );
+
+        // Tuples in the new order are accepted
+        table.insert(Tuple::new(vec![
+            Value::Bool(true),
+            Value::Int(3),
+            Value::Null,
+        ]))?;
+
+        for new_order in [&[0, 1][..], &[0, 0, 1], &[0, 1, 3]] {
+            assert!(table.reorder_columns(new_order).is_err());
+        }
+        assert_eq!(table.schema().names(), ["active", "id", "name"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_insert_checks_schema() -> DBResult<()> {
+        let mut table = people()?;
+        assert!(table.insert(Tuple::new(vec![Value::Int(4)])).is_err());
+        assert!(table
+            .insert(Tuple::new(vec![Value::Bool(true), Value::Null]))
+            .is_err());
+        assert_eq!(table.len(), 3);
+        Ok(())
+    }
+
+    fn person(id: i32, name: &str, active: bool) -> Tuple {
+        Tuple::new(vec![
+            Value::Int(id),
+            Value::Varchar(name.to_string()),
+            Value::Bool(active),
+        ])
+    }
+
+    #[test]
+    fn test_load_from() -> DBResult<()> {
+        let mut table = named_people()?.with_primary_key(&["id"])?;
+        let rows = vec![
+            person(3, "carol", true),
+            Tuple::new(vec![Value::Int(4), Value::Bool(true)]),
+            person(1, "alice", false),
+            person(5, "dave", false),
+            Tuple::new(vec![Value::Bool(false), Value::Null, Value::Null]),
+        ];
+
+        let report = table.load_from(rows.into_iter());
+        assert_eq!(report.inserted, 2);
+        let indices: Vec<usize> = report.rejected.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indices, [1, 2, 4]);
+        assert!(matches!(
+            report.rejected[1].1,
+            DBError::StorageError(StorageError::DuplicateKey(_))
+        ));
+        assert_eq!(table.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_import() -> DBResult<()> {
+        let path = "table_test_export";
+        let schema = Schema::with_names(
+            vec!["id".to_string(), "name".to_string(), "price".to_string()],
+            vec![Type::Int, Type::Varchar(16), Type::Decimal(2)],
+        )?
+        .with_checksum("name")?
+        .with_size_limits(1024, 4096);
+        let mut table = Table::new("items".to_string(), schema).with_primary_key(&["id"])?;
+        for (id, name) in [(1, Some("pen")), (2, None), (3, Some("ink"))] {
+            table.insert(Tuple::new(vec![
+                Value::Int(id),
+                name.map_or(Value::Null, |name| Value::Varchar(name.to_string())),
+                Value::Decimal(id as i64 * 150, 2),
+            ]))?;
+        }
+        table.export(path)?;
+
+        let imported = Table::import(path)?;
+        assert_eq!(imported.name(), "items");
+        assert_eq!(imported.schema(), table.schema());
+        assert!(imported.iter().eq(table.iter()));
+        assert_eq!(
+            imported.get_by_key(&[Value::Int(3)]),
+            table.get_by_key(&[Value::Int(3)])
+        );
+
+        // Truncated files are rejected
+        let bytes = std::fs::read(path)?;
+        std::fs::write(path, &bytes[..bytes.len() - 1])?;
+        assert!(matches!(
+            Table::import(path),
+            Err(DBError::StorageError(StorageError::DeserializationError(_)))
+        ));
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_view() -> DBResult<()> {
+        let table = people()?;
+        let view = table.view(|tuple| !tuple.values()[1].is_null(), &[1, 0])?;
+        assert_eq!(view.schema().names(), ["column_1", "column_0"]);
+        assert_eq!(view.schema().types(), [Type::Varchar(16), Type::Int]);
+
+        let rows: Vec<Tuple> = view.iter().collect();
+        assert_eq!(
+            rows,
+            [
+                Tuple::new(vec![Value::Varchar("alice".to_string()), Value::Int(1)]),
+                Tuple::new(vec![Value::Varchar("carol".to_string()), Value::Null]),
+            ]
+        );
+        assert!(view.iter().eq(rows));
+
+        assert!(table.view(|_| true, &[2]).is_err());
+        assert!(table.view(|_| true, &[0, 0]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_sorted() -> DBResult<()> {
+        let table = |name: &str, rows: &[(Option<i32>, &str)]| -> DBResult<Table> {
+            let schema = Schema::with_names(
+                vec!["id".to_string(), "tag".to_string()],
+                vec![Type::Int, Type::Varchar(8)],
+            )?;
+            let mut table = Table::new(name.to_string(), schema);
+            for &(id, tag) in rows {
+                table.insert(Tuple::new(vec![
+                    id.map_or(Value::Null, Value::Int),
+                    Value::Varchar(tag.to_string()),
+                ]))?;
+            }
+            Ok(table)
+        };
+        let left = table(
+            "l",
+            &[
+                (Some(1), "a"),
+                (Some(2), "b"),
+                (None, "c"),
+                (Some(2), "d"),
+                (Some(4), "e"),
+            ],
+        )?;
+        let right = table(
+            "r",
+            &[
+                (None, "v"),
+                (Some(2), "w"),
+                (Some(2), "x"),
+                (Some(3), "y"),
+                (Some(4), "z"),
+            ],
+        )?;
+
+        let merged = left.merge_sorted(&right, 0, 0)?;
+        assert_eq!(merged.name(), "l_r");
+        assert_eq!(merged.schema().names(), ["l.id", "l.tag", "r.id", "r.tag"]);
+
+        // Matches a nested loop join, including every pair of duplicates
+        let expected: Vec<Tuple> = left
+            .iter()
+            .flat_map(|l| right.iter().map(move |r| (l, r)))
+            .filter(|(l, r)| !l.values()[0].is_null() && l.values()[0] == r.values()[0])
+            .map(|(l, r)| Tuple::new([l.values(), r.values()].concat()))
+            .collect();
+        assert_eq!(expected.len(), 5);
+        assert!(merged.iter().eq(expected.iter()));
+
+        let unsorted = table("u", &[(Some(3), "a"), (Some(1), "b")])?;
+        assert!(left.merge_sorted(&unsorted, 0, 0).is_err());
+        assert!(left.merge_sorted(&right, 2, 0).is_err());
+
+        // Keys of different types can never be equal
+        assert!(matches!(
+            left.merge_sorted(&right, 0, 1),
+            Err(DBError::StorageError(StorageError::TypeMismatch(_)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_conflicts() -> DBResult<()> {
+        let mut table = named_people()?.with_primary_key(&["id"])?;
+        let original = table.get_by_key(&[Value::Int(1)]).cloned();
+
+        let err = table.insert(person(1, "bob", true)).unwrap_err();
+        assert!(matches!(
+            err,
+            DBError::StorageError(StorageError::DuplicateKey(_))
+        ));
+        assert_eq!(
+            table.insert_with(person(1, "bob", true), OnConflict::Ignore)?,
+            InsertOutcome::Ignored
+        );
+        assert_eq!(table.get_by_key(&[Value::Int(1)]).cloned(), original);
+        assert_eq!(table.len(), 2);
+
+        // Replacing keeps the key pointing at the new tuple, even after the
+        // key column moves
+        assert_eq!(
+            table.insert_with(person(1, "bob", false), OnConflict::Replace)?,
+            InsertOutcome::Replaced
+        );
+        assert_eq!(table.len(), 2);
+        table.reorder_columns(&[2, 0, 1])?;
+        let replaced = table.get_by_key(&[Value::Int(1)]).unwrap();
+        assert_eq!(replaced.values()[2], Value::Varchar("bob".to_string()));
+        assert_eq!(
+            table.insert_with(
+                Tuple::new(vec![Value::Bool(true), Value::Int(3), Value::Null]),
+                OnConflict::Replace
+            )?,
+            InsertOutcome::Inserted
+        );
+        assert!(table.get_by_key(&[Value::Int(3)]).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_errs() -> DBResult<()> {
+        assert!(named_people()?.with_primary_key(&[]).is_err());
+        assert!(named_people()?.with_primary_key(&["missing"]).is_err());
+        // The second row's name is null
+        assert!(named_people()?.with_primary_key(&["name"]).is_err());
+
+        let mut table = named_people()?;
+        table.insert(person(1, "carol", true))?;
+        assert!(table.with_primary_key(&["id"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate() -> DBResult<()> {
+        // The first column of `people` is 1, 2, and null
+        let table = people()?;
+        let expected = [
+            (Aggregate::Count, Value::BigInt(2)),
+            (Aggregate::Sum, Value::BigInt(3)),
+            (Aggregate::Min, Value::Int(1)),
+            (Aggregate::Max, Value::Int(2)),
+            (Aggregate::Avg, Value::Double(1.5)),
+        ];
+        for (aggregate, value) in expected {
+            assert_eq!(table.aggregate(0, aggregate)?, value);
+        }
+        assert_eq!(
+            table.aggregate(1, Aggregate::Max)?,
+            Value::Varchar("carol".to_string())
+        );
+        assert!(table.aggregate(1, Aggregate::Sum).is_err());
+        assert!(table.aggregate(2, Aggregate::Count).is_err());
+
+        let mut sum = Accumulator::new(Aggregate::Sum);
+        assert_eq!(sum.finish(), Value::Null);
+        sum.update(&Value::UnsignedBigInt(u64::MAX))?;
+        assert!(sum.update(&Value::UnsignedTiny(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_distinct() -> DBResult<()> {
+        let mut table = people()?;
+        let rows: Vec<Tuple> = table.iter().cloned().collect();
+        // An exact duplicate, and a row differing only in its first column
+        table.insert(rows[0].clone())?;
+        table.insert(Tuple::new(vec![
+            Value::Int(3),
+            Value::Varchar("alice".to_string()),
+        ]))?;
+        // Nulls are equal to each other, so this duplicates the third row
+        table.insert(Tuple::new(vec![
+            Value::Null,
+            Value::Varchar("carol".to_string()),
+        ]))?;
+
+        let distinct = table.distinct();
+        assert_eq!(distinct.len(), 4);
+        assert_eq!(distinct[..3], [&rows[0], &rows[1], &rows[2]]);
+        assert_eq!(distinct[3].values()[0], Value::Int(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_distinct_values() -> DBResult<()> {
+        let mut table = people()?;
+        table.insert(Tuple::new(vec![Value::Int(3), Value::Null]))?;
+        table.insert(Tuple::new(vec![
+            Value::Int(4),
+            Value::Varchar("alice".to_string()),
+        ]))?;
+        assert_eq!(
+            table.distinct_values(1)?,
+            [
+                Value::Varchar("alice".to_string()),
+                Value::Null,
+                Value::Varchar("carol".to_string()),
+            ]
+        );
+        assert!(table.distinct_values(2).is_err());
+
+        // The key index holds the same values as a scan of the column
+        let rows: Vec<Tuple> = table.iter().skip(3).cloned().collect();
+        let mut keyed = Table::new("keyed".to_string(), table.schema().clone());
+        for tuple in rows.iter().rev() {
+            keyed.insert(tuple.clone())?;
+        }
+        let scanned = keyed.distinct_values(0)?;
+        assert_eq!(scanned, [Value::Int(4), Value::Int(3)]);
+        let mut keyed = keyed.with_primary_key(&["column_0"])?;
+        let mut indexed = keyed.distinct_values(0)?;
+        indexed.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(indexed, [Value::Int(3), Value::Int(4)]);
+
+        // The key path does not read the tuples, so it is unaffected by
+        // their values
+        for tuple in &mut keyed.tuples {
+            tuple.values[0] = Value::Null;
+        }
+        let mut unread = keyed.distinct_values(0)?;
+        unread.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(unread, indexed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_index() -> DBResult<()> {
+        // Rows with an id and whether they are active
+        let schema = Schema::new(vec![Type::Int, Type::Bool]);
+        let mut table =
+            Table::new("accounts".to_string(), schema).with_primary_key(&["column_0"])?;
+        let row = |id: i32, active: bool| Tuple::new(vec![Value::Int(id), Value::Bool(active)]);
+        table.insert(row(1, true))?;
+        table.insert(row(2, false))?;
+        let active = Comparison::new(1, CompareOp::Eq, Value::Bool(true));
+        table.create_partial_index(0, active.clone())?;
+        table.insert(row(3, true))?;
+        table.insert(row(4, false))?;
+        assert!(table.create_partial_index(2, active.clone()).is_err());
+
+        // Only active rows are indexed, including when a row is replaced
+        table.insert_with(row(2, true), OnConflict::Replace)?;
+        table.insert_with(row(3, false), OnConflict::Replace)?;
+        let mut indexed: Vec<usize> = table.indexes[0].rows.values().flatten().copied().collect();
+        indexed.sort_unstable();
+        assert_eq!(indexed, [0, 1]);
+
+        // A lookup of active rows uses the index
+        let id = |n| Value::Int(n);
+        let only_active = [active];
+        assert!(table.find_index(0, &id(2), &only_active).is_some());
+        assert_eq!(table.lookup(0, &id(2), &only_active)?, [&row(2, true)]);
+        assert!(table.lookup(0, &id(3), &only_active)?.is_empty());
+
+        // Other lookups fall back to a scan and still find inactive rows
+        for filters in [
+            vec![],
+            vec![Comparison::new(1, CompareOp::Ne, Value::Bool(true))],
+        ] {
+            assert!(table.find_index(0, &id(3), &filters).is_none());
+            assert_eq!(table.lookup(0, &id(3), &filters)?, [&row(3, false)]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_if_version() -> DBResult<()> {
+        let mut table = named_people()?.with_primary_key(&["id"])?;
+        let row = |id: i32, name: &str| {
+            Tuple::new(vec![
+                Value::Int(id),
+                Value::Varchar(name.to_string()),
+                Value::Bool(true),
+            ])
+        };
+        assert_eq!(table.version(0), Some(0));
+        assert_eq!(table.version(table.len()), None);
+
+        // Each successful write increments the version, including a change
+        // of key
+        assert_eq!(table.update_if_version(0, 0, row(1, "alicia"))?, 1);
+        assert_eq!(table.update_if_version(0, 1, row(7, "alicia"))?, 2);
+        assert_eq!(table.get_by_key(&[Value::Int(7)]), Some(&row(7, "alicia")));
+        assert_eq!(table.get_by_key(&[Value::Int(1)]), None);
+        table.insert_with(row(7, "ally"), OnConflict::Replace)?;
+        assert_eq!(table.version(0), Some(3));
+
+        // A writer holding a stale version is rejected
+        assert!(matches!(
+            table.update_if_version(0, 2, row(7, "al")),
+            Err(DBError::StorageError(StorageError::VersionConflict {
+                row: 0,
+                expected: 2,
+                actual: 3
+            }))
+        ));
+        assert!(table.update_if_version(0, 3, row(2, "al")).is_err());
+        assert_eq!(table.version(0), Some(3));
+        assert_eq!(table.version(1), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparison_implies() {
+        let cmp = |op, n| Comparison::new(0, op, Value::Int(n));
+        let implied = [
+            (cmp(CompareOp::Eq, 5), cmp(CompareOp::Ge, 5)),
+            (cmp(CompareOp::Gt, 5), cmp(CompareOp::Gt, 5)),
+            (cmp(CompareOp::Ge, 6), cmp(CompareOp::Gt, 5)),
+            (cmp(CompareOp::Lt, 5), cmp(CompareOp::Ne, 5)),
+            (cmp(CompareOp::Le, 4), cmp(CompareOp::Le, 4)),
+            (cmp(CompareOp::Ne, 3), cmp(CompareOp::Ne, 3)),
+        ];
+        let not_implied = [
+            (cmp(CompareOp::Eq, 5), cmp(CompareOp::Gt, 5)),
+            (cmp(CompareOp::Ge, 5), cmp(CompareOp::Gt, 5)),
+            (cmp(CompareOp::Le, 5), cmp(CompareOp::Ne, 5)),
+            (cmp(CompareOp::Lt, 5), cmp(CompareOp::Gt, 1)),
+            (cmp(CompareOp::Ne, 3), cmp(CompareOp::Ne, 4)),
+            (
+                Comparison::new(1, CompareOp::Eq, Value::Int(5)),
+                cmp(CompareOp::Eq, 5),
+            ),
+        ];
+        for (query, filter) in implied {
+            assert!(query.implies(&filter), "{:?} => {:?}", query, filter);
+        }
+        for (query, filter) in not_implied {
+            assert!(!query.implies(&filter), "{:?} => {:?}", query, filter);
+        }
+    }
+
+    #[test]
+    fn test_tuple_eq_hashes_floats() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |tuple: &Tuple| {
+            let mut hasher = DefaultHasher::new();
+            tuple.hash(&mut hasher);
+            hasher.finish()
+        };
+        let pairs = [
+            (Value::Double(f64::NAN), Value::Double(-f64::NAN)),
+            (Value::Float(0.0), Value::Float(-0.0)),
+            (
+                Value::Struct(vec![Value::Double(f64::NAN), Value::Null]),
+                Value::Struct(vec![Value::Double(f64::NAN), Value::Null]),
+            ),
+        ];
+        for (a, b) in pairs {
+            let (a, b) = (Tuple::new(vec![a]), Tuple::new(vec![b]));
+            assert_eq!(a, b);
+            assert_eq!(hash(&a), hash(&b));
+        }
+        assert_ne!(
+            Tuple::new(vec![Value::Int(1)]),
+            Tuple::new(vec![Value::BigInt(1)])
+        );
+    }
+
+    #[test]
+    fn test_composite_eq_hash() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |tuple: &Tuple| {
+            let mut hasher = DefaultHasher::new();
+            tuple.hash(&mut hasher);
+            hasher.finish()
+        };
+        let nested = |x: f64, tag: u8| {
+            Tuple::new(vec![Value::Struct(vec![
+                Value::Int(1),
+                Value::Struct(vec![Value::Double(x), Value::Enum(tag, vec![Value::Null])]),
+            ])])
+        };
+
+        // Equal nested values compare and hash the same at every level
+        let (a, b) = (nested(0.0, 1), nested(-0.0, 1));
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_eq!(nested(f64::NAN, 1), nested(f64::NAN, 1));
+        assert_ne!(nested(0.0, 1), nested(1.0, 1));
+
+        // Enums with the same payload are distinguished by discriminant
+        assert_ne!(nested(0.0, 1), nested(0.0, 2));
+        assert_ne!(hash(&nested(0.0, 1)), hash(&nested(0.0, 2)));
+        let payload = vec![Value::Varchar("x".to_string())];
+        assert_ne!(
+            Tuple::new(vec![Value::Enum(0, payload.clone())]),
+            Tuple::new(vec![Value::Enum(1, payload.clone())])
+        );
+        assert_ne!(
+            Tuple::new(vec![Value::Enum(0, payload.clone())]),
+            Tuple::new(vec![Value::Struct(payload)])
+        );
+    }
+
+    #[test]
+    fn test_partition() -> DBResult<()> {
+        let table = people()?;
+        let tuples: Vec<Tuple> = table.iter().cloned().collect();
+        let (matched, unmatched) =
+            table.partition(|tuple| matches!(tuple.values()[1], Value::Varchar(_)));
+
+        assert_eq!(matched.name(), "people_matched");
+        assert_eq!(unmatched.name(), "people_unmatched");
+        assert_eq!(matched.iter().collect::<Vec<_>>(), [&tuples[0], &tuples[2]]);
+        assert_eq!(unmatched.iter().collect::<Vec<_>>(), [&tuples[1]]);
+        for part in [&matched, &unmatched] {
+            assert_eq!(part.schema(), people()?.schema());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_table() -> DBResult<()> {
+        let mut table = people()?;
+        table.rename("users".to_string());
+        assert_eq!(table.name(), "users");
+
+        let (matched, unmatched) = table.partition(|_| true);
+        assert_eq!(matched.name(), "users_matched");
+        assert_eq!(unmatched.name(), "users_unmatched");
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_rev() -> DBResult<()> {
+        let table = people()?;
+        let mut forward: Vec<&Tuple> = table.iter().collect();
+        forward.reverse();
+        assert_eq!(table.iter_rev().collect::<Vec<_>>(), forward);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() -> DBResult<()> {
+        assert_eq!(Value::parse("-128", &Type::Tiny)?, Value::Tiny(-128));
+        assert_eq!(
+            Value::parse("4294967295", &Type::UnsignedInt)?,
+            Value::UnsignedInt(u32::MAX)
+        );
+        assert_eq!(Value::parse("1", &Type::Bool)?, Value::Bool(true));
+        assert_eq!(Value::parse("FALSE", &Type::Bool)?, Value::Bool(false));
+        assert_eq!(Value::parse("2.5", &Type::Double)?, Value::Double(2.5));
+        assert_eq!(
+            Value::parse("-1.5", &Type::Decimal(2))?,
+            Value::Decimal(-150, 2)
+        );
+        assert_eq!(
+            Value::parse(".25", &Type::Decimal(2))?,
+            Value::Decimal(25, 2)
+        );
+        assert_eq!(
+            Value::parse("abc", &Type::Varchar(3))?,
+            Value::Varchar("abc".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_and_display_temporal_types() -> DBResult<()> {
+        let cases = [
+            ("1970-01-01", Type::Date, Value::Date(0)),
+            ("2024-02-29", Type::Date, Value::Date(19_782)),
+            ("1969-12-31", Type::Date, Value::Date(-1)),
+            (
+                "2024-02-29T12:34:56.789Z",
+                Type::Timestamp,
+                Value::Timestamp(19_782 * MICROS_PER_DAY + 45_296_789_000),
+            ),
+            (
+                "1969-12-31T23:59:59Z",
+                Type::Timestamp,
+                Value::Timestamp(-1_000_000),
+            ),
+            (
+                "123e4567-e89b-12d3-a456-426614174000",
+                Type::Uuid,
+                Value::Uuid([
+                    0x12, 0x3e, 0x45, 0x67, 0xe8, 0x9b, 0x12, 0xd3, 0xa4, 0x56, 0x42, 0x66, 0x14,
+                    0x17, 0x40, 0x00,
+                ]),
+            ),
+        ];
+        for (s, ty, value) in cases.clone() {
+            assert_eq!(Value::parse(s, &ty)?, value);
+            let expected = s.replace(".789Z", ".789000Z");
+            assert_eq!(value.to_string(), expected);
+        }
+
+        // Other accepted forms
+        assert_eq!(
+            Value::parse("1970-01-02 00:00:01", &Type::Timestamp)?,
+            Value::Timestamp(MICROS_PER_DAY + 1_000_000)
+        );
+        assert_eq!(
+            Value::parse("1970-01-02", &Type::Timestamp)?,
+            Value::Timestamp(MICROS_PER_DAY)
+        );
+        assert_eq!(
+            Value::parse("123E4567-E89B-12D3-A456-426614174000", &Type::Uuid)?,
+            cases[5].2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp_ordering() -> DBResult<()> {
+        let mut timestamps: Vec<Value> = [
+            "2024-01-01T00:00:00.000001Z",
+            "1969-07-20T20:17:40Z",
+            "2024-01-01T00:00:00Z",
+            "1999-12-31T23:59:59.999999Z",
+        ]
+        .iter()
+        .map(|s| Value::parse(s, &Type::Timestamp))
+        .collect::<DBResult<_>>()?;
+        timestamps.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let sorted: Vec<String> = timestamps.iter().map(Value::to_string).collect();
+        assert_eq!(
+            sorted,
+            [
+                "1969-07-20T20:17:40Z",
+                "1999-12-31T23:59:59.999999Z",
+                "2024-01-01T00:00:00Z",
+                "2024-01-01T00:00:00.000001Z",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_helpers() {
+        assert!(Value::Null.is_null());
+        assert!(!Value::Int(0).is_null());
+        assert_eq!(Value::Null.unwrap_or(Value::Int(1)), Value::Int(1));
+        assert_eq!(Value::Int(2).unwrap_or(Value::Int(1)), Value::Int(2));
+
+        let values = [Value::Null, Value::Int(3), Value::Int(4)];
+        assert_eq!(Value::coalesce(&values), Value::Int(3));
+        assert_eq!(Value::coalesce(&[Value::Null, Value::Null]), Value::Null);
+        assert_eq!(Value::coalesce(&[]), Value::Null);
+    }
+
+    #[test]
+    fn test_type_display_round_trip() -> DBResult<()> {
+        let types = [
+            Type::Char(8),
+            Type::Varchar(16),
+            Type::Int,
+            Type::UnsignedBigInt,
+            Type::Decimal(2),
+            Type::Timestamp,
+            Type::Enum("mood".to_string()),
+            Type::Struct("point".to_string()),
+        ];
+        for ty in types {
+            assert_eq!(ty.to_string().parse::<Type>()?, ty);
+        }
+        assert_eq!(Type::Varchar(16).to_string(), "Varchar(16)");
+        assert_eq!(Type::Enum("mood".to_string()).to_string(), "Enum(mood)");
+
+        for s in [
+            "Integer",
+            "Varchar",
+            "Varchar(x)",
+            "Int(4)",
+            "Enum()",
+            "Decimal(2",
+        ] {
+            assert!(s.parse::<Type>().is_err(), "{}", s);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errs() {
+        let out_of_range = [("300", Type::Tiny), ("-1", Type::UnsignedShort)];
+        for (s, ty) in out_of_range {
+            let err = Value::parse(s, &ty).unwrap_err().to_string();
+            assert!(err.contains("out of range"), "{}", err);
+        }
+
+        let malformed = [
+            ("12a", Type::Int),
+            ("", Type::BigInt),
+            ("yes", Type::Bool),
+            ("1.2.3", Type::Decimal(2)),
+            ("-", Type::Decimal(2)),
+            ("1.234", Type::Decimal(2)),
+            ("one", Type::Float),
+            ("abcd", Type::Varchar(3)),
+            ("2023-02-29", Type::Date),
+            ("2024-13-01", Type::Date),
+            ("24-01-01", Type::Date),
+            ("2024-01-01T24:00:00", Type::Timestamp),
+            ("2024-01-01T12:00", Type::Timestamp),
+            ("2024-01-01T12:00:00.1234567", Type::Timestamp),
+            ("123e4567e89b12d3a456426614174000", Type::Uuid),
+            ("123e4567-e89b-12d3-a456-42661417400g", Type::Uuid),
+        ];
+        for (s, ty) in malformed {
+            assert!(Value::parse(s, &ty).is_err(), "{:?} parsed as {:?}", s, ty);
+        }
+        assert!(Value::parse("1", &Type::Blob).is_err());
+    }
+
+    #[test]
+    fn test_integer_arithmetic() -> DBResult<()> {
+        assert_eq!(Value::Int(40).add(&Value::Int(2))?, Value::Int(42));
+        assert_eq!(Value::Short(7).rem(&Value::Short(4))?, Value::Short(3));
+        assert_eq!(Value::Int(5).mul(&Value::Null)?, Value::Null);
+
+        // The narrower operand is promoted to the wider type
+        assert_eq!(
+            Value::Tiny(100).mul(&Value::BigInt(1 << 40))?,
+            Value::BigInt(100 << 40)
+        );
+        assert!(Value::Int(1).add(&Value::UnsignedInt(1)).is_err());
+        assert!(Value::Int(1).add(&Value::Double(1.0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_integer_arithmetic_errs() {
+        let is_arithmetic_error = |result: DBResult<Value>| {
+            matches!(
+                result,
+                Err(DBError::StorageError(StorageError::ArithmeticError(_)))
+            )
+        };
+        assert!(is_arithmetic_error(
+            Value::Tiny(i8::MAX).add(&Value::Tiny(1))
+        ));
+        assert!(is_arithmetic_error(
+            Value::UnsignedInt(0).sub(&Value::UnsignedInt(1))
+        ));
+        assert!(is_arithmetic_error(
+            Value::Int(i32::MIN).div(&Value::Int(-1))
+        ));
+        assert!(is_arithmetic_error(Value::BigInt(1).div(&Value::BigInt(0))));
+        assert!(is_arithmetic_error(Value::Short(1).rem(&Value::Tiny(0))));
+    }
+
+    #[test]
+    fn test_column_ints() -> DBResult<()> {
+        let table = people()?;
+        let ids: Vec<Option<i32>> = table.column_ints(0)?.collect();
+        assert_eq!(ids, vec![Some(1), Some(2), None]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_strs() -> DBResult<()> {
+        let table = people()?;
+        let names: Vec<Option<&str>> = table.column_strs(1)?.collect();
+        assert_eq!(names, vec![Some("alice"), None, Some("carol")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_column_errs() -> DBResult<()> {
+        let table = people()?;
+        assert!(table.column_ints(1).is_err());
+        assert!(table.column_strs(0).is_err());
+        assert!(table.column_ints(2).is_err());
+        Ok(())
+    }
 }
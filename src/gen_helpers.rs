@@ -1,2 +1,33 @@
 //! General helper functions and macros for internal use;
 //! these should not be exposed externally.
+
+/// Emits a `tracing` event when the `tracing` feature is enabled, and
+/// compiles to nothing otherwise. Takes the `tracing` macro to use (such as
+/// `debug` or `warn`) followed by that macro's usual arguments.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
+/// Evaluates `$body` and, when the `tracing` feature is enabled, emits a
+/// `debug` event with the given `page_id`, the time taken in microseconds as
+/// `elapsed_us`, and `$message`. The event is only emitted if `$body`
+/// evaluates to `Ok`; errors are left to the caller to report.
+macro_rules! trace_timed {
+    ($page_id:expr, $message:literal, $body:expr) => {{
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+        let result = $body;
+        #[cfg(feature = "tracing")]
+        if result.is_ok() {
+            tracing::debug!(
+                page_id = $page_id,
+                elapsed_us = start.elapsed().as_micros() as u64,
+                $message
+            );
+        }
+        result
+    }};
+}
@@ -1,9 +1,12 @@
+#[macro_use]
+mod gen_helpers;
+
+pub mod catalog;
+pub mod database;
 pub mod datatypes;
+pub mod row;
 pub mod storage;
 
 mod error;
 pub use error::DBError;
 pub use error::DBResult;
-
-#[macro_use]
-mod gen_helpers;
@@ -0,0 +1,254 @@
+//! Conversions between Rust types and tuples, so that application code can
+//! store its own structs as rows of a table.
+//!
+//! A struct with named fields can implement `Row` with `#[derive(SkibiRow)]`
+//! from the `skibidb-derive` crate, as long as the type of each of its fields
+//! implements `ColumnValue`. Each field becomes a column with the same name,
+//! in declaration order.
+//!
+//! Tuples can also be built with the `tuple!` macro and converted into Rust
+//! tuples of `ColumnValue`s with `TryFrom`, which keeps tests short.
+
+use crate::datatypes::{Schema, Tuple, Type, Value};
+use crate::storage::error::StorageError;
+use crate::storage::BlobHandle;
+use crate::{DBError, DBResult};
+
+/// A `Row` is a Rust type which can be stored as a tuple of a table with the
+/// schema given by `Row::schema`.
+pub trait Row: Sized {
+    /// Returns the schema of tuples representing this type.
+    fn schema() -> Schema;
+
+    /// Converts this value into a tuple conforming to `Row::schema`.
+    fn to_tuple(&self) -> Tuple;
+
+    /// Converts a tuple conforming to `Row::schema` back into this type.
+    /// Returns an error if the tuple has the wrong number of values or any
+    /// value does not match its field.
+    fn from_tuple(tuple: &Tuple) -> DBResult<Self>;
+}
+
+/// A `ColumnValue` is a Rust type which can be stored as a single `Value`,
+/// and so used as a field of a `Row`.
+///
+/// `Option<T>` is stored as `Null` when it is `None`, and like `T`
+/// otherwise. Every other type returns an error when converted from `Null`.
+pub trait ColumnValue: Sized {
+    /// Returns the type of the column which stores this type.
+    fn column_type() -> Type;
+
+    /// Converts this value into a `Value` of type `column_type`.
+    fn to_value(&self) -> Value;
+
+    /// Converts a `Value` of type `column_type` back into this type.
+    fn from_value(value: &Value) -> DBResult<Self>;
+}
+
+/// Implements `ColumnValue` for Rust types which are stored in the `Value`
+/// variant of the same name as their `Type`.
+macro_rules! impl_column_value {
+    ($($rust:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl ColumnValue for $rust {
+                fn column_type() -> Type {
+                    Type::$variant
+                }
+
+                fn to_value(&self) -> Value {
+                    Value::$variant(*self)
+                }
+
+                fn from_value(value: &Value) -> DBResult<Self> {
+                    match value {
+                        Value::$variant(v) => Ok(*v),
+                        other => Err(mismatch(&Type::$variant, other)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_column_value! {
+    i8 => Tiny,
+    i16 => Short,
+    i32 => Int,
+    i64 => BigInt,
+    u8 => UnsignedTiny,
+    u16 => UnsignedShort,
+    u32 => UnsignedInt,
+    u64 => UnsignedBigInt,
+    f32 => Float,
+    f64 => Double,
+    bool => Bool,
+    BlobHandle => Blob,
+}
+
+/// Strings are stored as `Varchar`s of the largest possible length. Values
+/// read from `Char` columns are also accepted.
+impl ColumnValue for String {
+    fn column_type() -> Type {
+        Type::Varchar(u16::MAX)
+    }
+
+    fn to_value(&self) -> Value {
+        Value::Varchar(self.clone())
+    }
+
+    fn from_value(value: &Value) -> DBResult<Self> {
+        match value {
+            Value::Varchar(s) | Value::Char(s) => Ok(s.clone()),
+            other => Err(mismatch(&Self::column_type(), other)),
+        }
+    }
+}
+
+impl<T: ColumnValue> ColumnValue for Option<T> {
+    fn column_type() -> Type {
+        T::column_type()
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Some(v) => v.to_value(),
+            None => Value::Null,
+        }
+    }
+
+    fn from_value(value: &Value) -> DBResult<Self> {
+        match value {
+            Value::Null => Ok(None),
+            v => T::from_value(v).map(Some),
+        }
+    }
+}
+
+/// Values are built from Rust values as by `ColumnValue::to_value`.
+impl<T: ColumnValue> From<T> for Value {
+    fn from(value: T) -> Self {
+        value.to_value()
+    }
+}
+
+/// String slices are stored as `Varchar`s, like `String`s.
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Varchar(value.to_string())
+    }
+}
+
+/// Builds a `Tuple` from a list of values which can each be converted into a
+/// `Value`, such as Rust integers, strings, and `Option`s of them.
+///
+/// ```
+/// use skibidb::datatypes::{Tuple, Value};
+/// use skibidb::tuple;
+///
+/// let row = tuple![1i32, "alice", None::<bool>];
+/// let expected = vec![Value::Int(1), Value::Varchar("alice".to_string()), Value::Null];
+/// assert_eq!(row, Tuple::new(expected));
+/// ```
+#[macro_export]
+macro_rules! tuple {
+    ($($value:expr),* $(,)?) => {
+        $crate::datatypes::Tuple::new(vec![$($crate::datatypes::Value::from($value)),*])
+    };
+}
+
+/// Implements conversions from tuples into Rust tuples of `ColumnValue`s
+/// with each of the given lists of type parameters.
+macro_rules! impl_from_tuple {
+    ($(($($field:ident),+)),* $(,)?) => {
+        $(
+            /// Returns an error if the tuple has the wrong number of values or
+            /// any value does not match its position's type.
+            impl<$($field: ColumnValue),+> TryFrom<&Tuple> for ($($field,)+) {
+                type Error = DBError;
+
+                fn try_from(tuple: &Tuple) -> DBResult<Self> {
+                    let len = [$(stringify!($field)),+].len();
+                    let mut values = check_row_len(tuple, len)?.iter();
+                    Ok(($($field::from_value(values.next().unwrap())?,)+))
+                }
+            }
+
+            /// Converts the tuple like `TryFrom<&Tuple>`.
+            impl<$($field: ColumnValue),+> TryFrom<Tuple> for ($($field,)+) {
+                type Error = DBError;
+
+                fn try_from(tuple: Tuple) -> DBResult<Self> {
+                    Self::try_from(&tuple)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_tuple! {
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F),
+    (A, B, C, D, E, F, G),
+    (A, B, C, D, E, F, G, H),
+}
+
+/// Returns an error if `tuple` does not have exactly `len` values, as
+/// required by a `Row` with `len` fields. Used by `#[derive(SkibiRow)]`.
+pub fn check_row_len(tuple: &Tuple, len: usize) -> DBResult<&[Value]> {
+    if tuple.values().len() != len {
+        return Err(DBError::from(StorageError::TypeMismatch(format!(
+            "expected a tuple of {} values but got {}.",
+            len,
+            tuple.values().len()
+        ))));
+    }
+    Ok(tuple.values())
+}
+
+/// Returns the error for a `value` which cannot be converted to a Rust type
+/// stored in columns of type `expected`.
+fn mismatch(expected: &Type, value: &Value) -> DBError {
+    DBError::from(StorageError::TypeMismatch(format!(
+        "expected a value of type {:?} but got {:?}.",
+        expected, value
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tuple_macro_round_trip() -> DBResult<()> {
+        let row = crate::tuple![7i32, "hi", true, Some(2.5f64), None::<u8>];
+        assert_eq!(
+            row.values(),
+            [
+                Value::Int(7),
+                Value::Varchar("hi".to_string()),
+                Value::Bool(true),
+                Value::Double(2.5),
+                Value::Null,
+            ]
+        );
+
+        let (id, name, active, score, missing): (i32, String, bool, Option<f64>, Option<u8>) =
+            (&row).try_into()?;
+        assert_eq!((id, name.as_str(), active), (7, "hi", true));
+        assert_eq!((score, missing), (Some(2.5), None));
+
+        // Types and lengths must line up
+        let mismatched: DBResult<(i64, String, bool, f64, Option<u8>)> = row.clone().try_into();
+        assert!(matches!(
+            mismatched,
+            Err(DBError::StorageError(StorageError::TypeMismatch(_)))
+        ));
+        let short: DBResult<(i32, String)> = row.try_into();
+        assert!(short.is_err());
+        Ok(())
+    }
+}
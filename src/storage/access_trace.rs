@@ -0,0 +1,163 @@
+//! Recording of the calls made to a `FileManager`, so that a workload can be
+//! replayed deterministically against another `FileManager`, such as to
+//! reproduce an eviction bug in a test.
+
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+use super::FileManager;
+
+/// A single successful call made to a `FileManager`, and the id of the page
+/// it accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessEvent {
+    Read(u64),
+    Write(u64),
+    Pin(u64),
+    Unpin(u64),
+    Allocate(u64),
+    Deallocate(u64),
+}
+
+/// A ring buffer holding the most recent `capacity` `AccessEvent`s.
+///
+/// Events are stored in a `Vec` of up to twice the capacity, and the oldest
+/// half is discarded when it fills up, so that the retained events can be
+/// borrowed as one contiguous slice.
+#[derive(Debug, Clone)]
+pub(crate) struct AccessTrace {
+    events: Vec<AccessEvent>,
+    capacity: usize,
+}
+
+impl AccessTrace {
+    /// Creates a new empty trace which retains up to `capacity` events.
+    pub(crate) fn new(capacity: usize) -> Self {
+        AccessTrace {
+            events: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Adds `event` to the trace, discarding old events if necessary.
+    pub(crate) fn push(&mut self, event: AccessEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == 2 * self.capacity {
+            self.events.drain(..self.capacity);
+        }
+        self.events.push(event);
+    }
+
+    /// Returns the retained events, oldest first.
+    pub(crate) fn events(&self) -> &[AccessEvent] {
+        &self.events[self.events.len().saturating_sub(self.capacity)..]
+    }
+}
+
+/// Makes the calls recorded in `trace` against `fm`, in order. Pages are
+/// written with zeroed data, since the trace does not record page contents.
+///
+/// For the replay to be faithful, `fm` should have the same configuration as
+/// the `FileManager` the trace was recorded from, and should be opened on a
+/// copy of the file as it was when recording started. An error is returned
+/// if any call fails or an allocation returns a different page than it did
+/// when it was recorded.
+pub fn replay(fm: &mut FileManager, trace: &[AccessEvent]) -> DBResult<()> {
+    for &event in trace {
+        match event {
+            AccessEvent::Read(page_id) => {
+                fm.read_page(page_id)?;
+            }
+            AccessEvent::Write(page_id) => {
+                let zeros = vec![0; fm.usable_page_size()];
+                fm.write_page_to_pool(page_id, &zeros)?;
+            }
+            AccessEvent::Pin(page_id) => fm.pin_page(page_id)?,
+            AccessEvent::Unpin(page_id) => {
+                fm.unpin_page(page_id);
+            }
+            AccessEvent::Allocate(page_id) => {
+                let allocated = fm.allocate_page()?;
+                if allocated != page_id {
+                    return Err(DBError::from(StorageError::InvalidArgument(format!(
+                        "replayed allocation returned page {} but the trace recorded page {}.",
+                        allocated, page_id
+                    ))));
+                }
+            }
+            AccessEvent::Deallocate(page_id) => fm.deallocate_page(page_id)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{copy, remove_file};
+
+    use super::*;
+
+    #[test]
+    fn test_access_trace_ring_buffer() {
+        let mut trace = AccessTrace::new(3);
+        for page_id in 0..10 {
+            trace.push(AccessEvent::Read(page_id));
+        }
+        assert_eq!(trace.events(), [7, 8, 9].map(AccessEvent::Read).as_slice());
+    }
+
+    #[test]
+    fn test_unpin_of_missing_page_is_not_recorded() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 2)?.with_access_trace(8);
+        fm.allocate_pages(3)?;
+        fm.flush_all_pages()?;
+        fm.read_page(3)?;
+        let before = fm.access_trace().len();
+
+        assert_eq!(fm.unpin_page(1), None);
+        assert_eq!(fm.access_trace().len(), before);
+        assert_eq!(fm.unpin_page(3), Some(0));
+        assert_eq!(fm.access_trace().last(), Some(&AccessEvent::Unpin(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_reproduces_pool_state() -> DBResult<()> {
+        let path = "trace_test_record.db";
+        let replay_path = "trace_test_replay.db";
+        {
+            let mut fm = FileManager::new(path, 64, 16)?;
+            for _ in 0..6 {
+                fm.allocate_page()?;
+            }
+            fm.flush_all_pages()?;
+        }
+        copy(path, replay_path)?;
+
+        let mut fm = FileManager::new(path, 64, 3)?.with_access_trace(64);
+        fm.pin_page(1)?;
+        for page_id in [2, 3, 4, 2, 5, 6] {
+            fm.read_page(page_id)?;
+        }
+        fm.write_page_to_pool(3, &[9; 64])?;
+        fm.unpin_page(1);
+        fm.read_page(4)?;
+        fm.deallocate_page(5)?;
+        assert_eq!(fm.allocate_page()?, 5);
+        let trace = fm.access_trace().to_vec();
+        assert_eq!(trace.len(), 12);
+
+        let mut replayed = FileManager::new(replay_path, 64, 3)?;
+        replay(&mut replayed, &trace)?;
+        assert_eq!(replayed.pool_state(), fm.pool_state());
+        assert_eq!(replayed.stats(), fm.stats());
+
+        drop(fm);
+        drop(replayed);
+        remove_file(path)?;
+        remove_file(replay_path)?;
+        Ok(())
+    }
+}
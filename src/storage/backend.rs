@@ -0,0 +1,277 @@
+//! The storage that a `FileManager` reads pages from and writes pages to:
+//! either a file on disk or a buffer in memory.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::fd_pool::PooledFile;
+
+/// The alignment in bytes of the buffers, offsets, and lengths of reads and
+/// writes to a file opened for direct I/O. This is the largest logical block
+/// size of common filesystems.
+#[cfg(target_os = "linux")]
+pub(super) const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// The storage behind a `FileManager`. An in-memory backend behaves exactly
+/// like an empty file, except that nothing is ever written to disk. A pooled
+/// backend is a file which an `FdPool` may close between accesses, and a
+/// direct backend is a file opened with `O_DIRECT`, which bypasses the
+/// operating system's page cache.
+pub(super) enum Backend {
+    File(File),
+    Pooled(PooledFile),
+    #[cfg(target_os = "linux")]
+    Direct(File),
+    Memory(Vec<u8>),
+}
+
+impl Backend {
+    /// Opens the file at `path` for reading and writing, creating it if it
+    /// does not exist. If `direct` is `true`, the file is opened for direct
+    /// I/O, which is only supported on Linux.
+    pub(super) fn open(path: &str, direct: bool) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        match direct {
+            true => open_direct(options, path),
+            false => Ok(Backend::File(options.open(path)?)),
+        }
+    }
+
+    /// Returns `true` if this backend is a file opened for direct I/O.
+    pub(super) fn is_direct(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Backend::Direct(_) = self {
+            return true;
+        }
+        false
+    }
+
+    /// Returns the length in bytes of the stored data.
+    pub(super) fn len(&self) -> io::Result<u64> {
+        match self {
+            Backend::Memory(data) => Ok(data.len() as u64),
+            _ => self.with_file(|file| Ok(file.metadata()?.len())),
+        }
+    }
+
+    /// Returns `true` if this backend is in memory rather than on disk.
+    pub(super) fn is_memory(&self) -> bool {
+        matches!(self, Backend::Memory(_))
+    }
+
+    /// Reads exactly `buf.len()` bytes starting at `offset`.
+    pub(super) fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Backend::Direct(file) => {
+                let (start, mut blocks) = DirectBlocks::covering(offset, buf.len());
+                if blocks.read(file, start)? < blocks.len() {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+                let skip = (offset - start) as usize;
+                buf.copy_from_slice(&blocks.as_mut()[skip..skip + buf.len()]);
+                Ok(())
+            }
+            Backend::Memory(data) => {
+                let src = usize::try_from(offset)
+                    .ok()
+                    .and_then(|start| data.get(start..start.checked_add(buf.len())?))
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                buf.copy_from_slice(src);
+                Ok(())
+            }
+            _ => self.with_file(|mut file| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(buf)
+            }),
+        }
+    }
+
+    /// Writes all of `buf` starting at `offset`, growing the stored data if
+    /// necessary.
+    pub(super) fn write_at(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Backend::Direct(file) => {
+                // Blocks which are only partly overwritten are read first
+                let (start, mut blocks) = DirectBlocks::covering(offset, buf.len());
+                let skip = (offset - start) as usize;
+                if skip != 0 || buf.len() != blocks.len() {
+                    blocks.read(file, start)?;
+                }
+                blocks.as_mut()[skip..skip + buf.len()].copy_from_slice(buf);
+                let mut file: &File = file;
+                file.seek(SeekFrom::Start(start))?;
+                file.write_all(blocks.as_mut())
+            }
+            Backend::Memory(data) => {
+                let start = offset as usize;
+                let end = start + buf.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[start..end].copy_from_slice(buf);
+                Ok(())
+            }
+            _ => self.with_file(|mut file| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(buf)
+            }),
+        }
+    }
+
+    /// Extends the stored data with zeros to at least `len` bytes. Data
+    /// which is already longer is left unchanged.
+    pub(super) fn grow_to(&mut self, len: u64) -> io::Result<()> {
+        if self.len()? >= len {
+            return Ok(());
+        }
+        match self {
+            Backend::Memory(data) => {
+                data.resize(len as usize, 0);
+                Ok(())
+            }
+            _ => self.with_file(|file| file.set_len(len)),
+        }
+    }
+
+    /// Makes sure all written data has reached the disk. Does nothing for an
+    /// in-memory backend.
+    pub(super) fn sync(&mut self) -> io::Result<()> {
+        match self {
+            Backend::Memory(_) => Ok(()),
+            _ => self.with_file(File::sync_all),
+        }
+    }
+
+    /// Releases the storage backing `len` bytes at `offset` without changing
+    /// the length of the stored data, so the range reads back as zeros.
+    /// Files on filesystems which do not support this are left unchanged.
+    pub(super) fn punch_hole(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        match self {
+            Backend::Memory(data) => {
+                let start = (offset as usize).min(data.len());
+                let end = (offset as usize + len as usize).min(data.len());
+                data[start..end].fill(0);
+                Ok(())
+            }
+            _ => self.with_file(|file| punch_file_hole(file, offset, len)),
+        }
+    }
+
+    /// Runs `op` on the file of a file or pooled backend, reopening a pooled
+    /// file if its pool has closed it. Must not be called on an in-memory
+    /// backend.
+    fn with_file<T>(&self, op: impl FnOnce(&File) -> io::Result<T>) -> io::Result<T> {
+        match self {
+            Backend::File(file) => op(file),
+            Backend::Pooled(pooled) => pooled.with_file(op),
+            #[cfg(target_os = "linux")]
+            Backend::Direct(file) => op(file),
+            Backend::Memory(_) => unreachable!("in-memory backends have no file"),
+        }
+    }
+}
+
+/// Opens the file at `path` with `options` and `O_DIRECT`.
+#[cfg(target_os = "linux")]
+fn open_direct(mut options: OpenOptions, path: &str) -> io::Result<Backend> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    options.custom_flags(libc::O_DIRECT);
+    Ok(Backend::Direct(options.open(path)?))
+}
+
+/// Direct I/O is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+fn open_direct(_options: OpenOptions, _path: &str) -> io::Result<Backend> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "direct I/O is only supported on Linux",
+    ))
+}
+
+/// A buffer aligned to `DIRECT_IO_ALIGNMENT` holding whole blocks of a file
+/// opened for direct I/O, through which its reads and writes are made.
+#[cfg(target_os = "linux")]
+struct DirectBlocks {
+    buf: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl DirectBlocks {
+    /// Returns the offset of the first block containing any of the `len`
+    /// bytes at `offset`, and a zeroed buffer for all of those blocks.
+    fn covering(offset: u64, len: usize) -> (u64, Self) {
+        let align = DIRECT_IO_ALIGNMENT as u64;
+        let first = offset / align * align;
+        let end = (offset + len as u64).div_ceil(align) * align;
+        let len = (end - first) as usize;
+
+        // Over-allocate so that an aligned range of `len` bytes fits
+        let buf = vec![0; len + DIRECT_IO_ALIGNMENT];
+        let start = buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        (first, DirectBlocks { buf, start, len })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.start + self.len]
+    }
+
+    /// Reads the blocks starting at `offset` from `file`, returning the
+    /// number of bytes read, which is less than `len` at the end of the file.
+    /// Blocks past the end are left zeroed.
+    fn read(&mut self, mut file: &File, offset: u64) -> io::Result<usize> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < self.len {
+            match file.read(&mut self.as_mut()[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Punches a hole in `file` with `fallocate`.
+#[cfg(target_os = "linux")]
+fn punch_file_hole(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: `fallocate` only operates on the open file descriptor and does
+    // not access any memory owned by this process.
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if result == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) => Ok(()),
+        _ => Err(err),
+    }
+}
+
+/// Hole punching is only supported on Linux; elsewhere freed pages keep
+/// their disk blocks.
+#[cfg(not(target_os = "linux"))]
+fn punch_file_hole(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,233 @@
+//! Storage of large binary objects (blobs) in chains of overflow pages,
+//! separately from the tuples that refer to them.
+//!
+//! The payload of each page in a blob's chain begins with a header
+//! containing the id of the next page in the chain (0 for the last page) and
+//! the number of blob bytes stored in the page, followed by the bytes
+//! themselves:
+//!
+//! ```text
+//! [next_page_id: u64][len: u32][data...]
+//! ```
+
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+use super::FileManager;
+
+/// Size in bytes of the header at the start of each page of a blob.
+const BLOB_PAGE_HEADER_SIZE: usize = 12;
+
+/// A `BlobHandle` points to a blob stored in overflow pages. It is small and
+/// fixed-size, so it can be stored inside a tuple in place of the blob's
+/// bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobHandle {
+    first_page: u64,
+    len: u64,
+}
+
+impl BlobHandle {
+    /// Size in bytes of a serialized `BlobHandle`.
+    pub const SERIALIZED_SIZE: usize = 16;
+
+    /// Returns the length in bytes of the blob this handle points to.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the blob this handle points to is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Serializes this handle as the id of the blob's first page followed by
+    /// its length, both as little-endian `u64`s.
+    pub fn to_bytes(&self) -> [u8; Self::SERIALIZED_SIZE] {
+        let mut bytes = [0; Self::SERIALIZED_SIZE];
+        bytes[..8].copy_from_slice(&self.first_page.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.len.to_le_bytes());
+        bytes
+    }
+
+    /// Deserializes a handle from the start of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> DBResult<Self> {
+        if bytes.len() < Self::SERIALIZED_SIZE {
+            return Err(DBError::from(StorageError::DeserializationError(format!(
+                "expected {} bytes for a BlobHandle but got {}.",
+                Self::SERIALIZED_SIZE,
+                bytes.len()
+            ))));
+        }
+
+        Ok(BlobHandle {
+            first_page: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            len: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        })
+    }
+}
+
+impl FileManager {
+    /// Stores `bytes` as a blob in newly allocated pages and returns a handle
+    /// to it. An empty blob does not use any pages.
+    ///
+    /// If any page cannot be allocated, the pages allocated so far are freed
+    /// and an error is returned.
+    pub fn store_blob(&mut self, bytes: &[u8]) -> DBResult<BlobHandle> {
+        let capacity = self.blob_page_capacity()?;
+        let num_pages = bytes.len().div_ceil(capacity);
+
+        let mut page_ids = Vec::with_capacity(num_pages);
+        for _ in 0..num_pages {
+            match self.allocate_page() {
+                Ok(page_id) => page_ids.push(page_id),
+                Err(e) => {
+                    self.free_blob_pages(&page_ids);
+                    return Err(e);
+                }
+            }
+        }
+
+        for (i, chunk) in bytes.chunks(capacity).enumerate() {
+            let next_page = page_ids.get(i + 1).copied().unwrap_or(0);
+
+            let mut data = vec![0; self.usable_page_size()];
+            data[..8].copy_from_slice(&next_page.to_le_bytes());
+            data[8..12].copy_from_slice(&(chunk.len() as u32).to_le_bytes());
+            data[BLOB_PAGE_HEADER_SIZE..BLOB_PAGE_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+
+            if let Err(e) = self.write_page_to_pool(page_ids[i], &data) {
+                self.free_blob_pages(&page_ids);
+                return Err(e);
+            }
+        }
+
+        Ok(BlobHandle {
+            first_page: page_ids.first().copied().unwrap_or(0),
+            len: bytes.len() as u64,
+        })
+    }
+
+    /// Loads the bytes of the blob that `handle` points to.
+    pub fn load_blob(&mut self, handle: BlobHandle) -> DBResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(handle.len as usize);
+        for page_id in self.blob_page_ids(handle)? {
+            let data = self.read_page(page_id)?;
+            let len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+            bytes.extend_from_slice(&data[BLOB_PAGE_HEADER_SIZE..BLOB_PAGE_HEADER_SIZE + len]);
+        }
+        Ok(bytes)
+    }
+
+    /// Deletes the blob that `handle` points to, freeing all of its pages.
+    /// `handle` must not be used after this.
+    pub fn delete_blob(&mut self, handle: BlobHandle) -> DBResult<()> {
+        for page_id in self.blob_page_ids(handle)? {
+            self.deallocate_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of blob bytes that fit in a single page.
+    fn blob_page_capacity(&self) -> DBResult<usize> {
+        if self.usable_page_size() <= BLOB_PAGE_HEADER_SIZE {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "usable page size of {} bytes is too small to store blobs.",
+                self.usable_page_size()
+            ))));
+        }
+        Ok(self.usable_page_size() - BLOB_PAGE_HEADER_SIZE)
+    }
+
+    /// Follows the chain of pages of the blob that `handle` points to and
+    /// returns their ids in order, checking that the chain is consistent with
+    /// the handle's length.
+    pub(super) fn blob_page_ids(&mut self, handle: BlobHandle) -> DBResult<Vec<u64>> {
+        let capacity = self.blob_page_capacity()?;
+        let expected_pages = (handle.len as usize).div_ceil(capacity);
+
+        let mut page_ids = Vec::with_capacity(expected_pages);
+        let mut remaining = handle.len as usize;
+        let mut page_id = handle.first_page;
+        while page_id != 0 {
+            if page_ids.len() == expected_pages {
+                return Err(DBError::from(StorageError::DeserializationError(
+                    "blob page chain is longer than the blob's length.".to_string(),
+                )));
+            }
+
+            let data = self.read_page(page_id)?;
+            let next_page = u64::from_le_bytes(data[..8].try_into().unwrap());
+            let len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+            if len > capacity || len > remaining {
+                return Err(DBError::from(StorageError::DeserializationError(format!(
+                    "invalid blob page length of {} bytes in page {}.",
+                    len, page_id
+                ))));
+            }
+
+            page_ids.push(page_id);
+            remaining -= len;
+            page_id = next_page;
+        }
+
+        if remaining != 0 {
+            return Err(DBError::from(StorageError::DeserializationError(format!(
+                "blob page chain ended with {} bytes missing.",
+                remaining
+            ))));
+        }
+
+        Ok(page_ids)
+    }
+
+    /// Frees the pages of a partially stored blob, ignoring errors since the
+    /// caller is already returning one.
+    fn free_blob_pages(&mut self, page_ids: &[u64]) {
+        for &page_id in page_ids {
+            let _ = self.deallocate_page(page_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_blob_store_load_delete() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+
+        // 200 bytes take 4 pages of 52 bytes each
+        let bytes: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let handle = fm.store_blob(&bytes)?;
+        assert_eq!(handle.len(), 200);
+
+        let handle = BlobHandle::from_bytes(&handle.to_bytes())?;
+        assert_eq!(fm.load_blob(handle)?, bytes);
+
+        let blob_pages: HashSet<u64> = fm.blob_page_ids(handle)?.into_iter().collect();
+        assert_eq!(blob_pages.len(), 4);
+        fm.delete_blob(handle)?;
+
+        // The freed pages are handed out again before the file grows
+        let reused: HashSet<u64> = (0..4)
+            .map(|_| fm.allocate_page())
+            .collect::<DBResult<_>>()?;
+        assert_eq!(reused, blob_pages);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_blob() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+
+        let handle = fm.store_blob(&[])?;
+        assert!(handle.is_empty());
+        assert_eq!(fm.load_blob(handle)?, Vec::<u8>::new());
+        fm.delete_blob(handle)?;
+        Ok(())
+    }
+}
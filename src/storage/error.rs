@@ -9,15 +9,81 @@ pub enum StorageError {
     #[error("I/O error: {0}")]
     IOError(#[from] io::Error),
 
+    #[error("I/O error on page {page_id} at offset {offset}: {source}")]
+    PageIo {
+        page_id: u64,
+        offset: u64,
+        #[source]
+        source: io::Error,
+    },
+
     #[error("invalid function argument: {0}")]
     InvalidArgument(String),
 
     #[error("could not find page with page ID: {0}")]
     UnknownPage(u64),
 
+    #[error("offset of page {0} is too large to be represented")]
+    PageOffsetOverflow(u64),
+
     #[error("buffer pool is full ({0} pages in pool) and no page can be evicted")]
     BufferPoolFull(u64),
 
     #[error("cannot delete file while pages are still pinned")]
     DeleteFileWhilePagesPinned,
+
+    #[error("cannot deallocate page {0} while it is pinned")]
+    DeallocatePinnedPage(u64),
+
+    #[error("a thread panicked while holding the FileManager lock")]
+    LockPoisoned,
+
+    #[error("not enough free space in page for a tuple of {0} bytes")]
+    PageFull(usize),
+
+    #[error("could not find tuple in slot: {0}")]
+    UnknownSlot(u16),
+
+    #[error("type mismatch: {0}")]
+    TypeMismatch(String),
+
+    #[error("could not deserialize data: {0}")]
+    DeserializationError(String),
+
+    #[error("unsupported type: {0}")]
+    UnsupportedType(String),
+
+    #[error("checksum mismatch in page {0}")]
+    ChecksumMismatch(u64),
+
+    #[error("arithmetic error: {0}")]
+    ArithmeticError(String),
+
+    #[error("could not parse value: {0}")]
+    ParseError(String),
+
+    #[error("duplicate key: {0}")]
+    DuplicateKey(String),
+
+    #[error("could not find table named `{0}`")]
+    UnknownTable(String),
+
+    #[error("a table named `{0}` already exists")]
+    DuplicateTable(String),
+
+    #[error("unsupported format version {0}; this library supports up to version {max}", max = super::FORMAT_VERSION)]
+    UnsupportedFormatVersion(u8),
+
+    #[error("page {0} did not read back from disk as it was written")]
+    WriteVerificationFailed(u64),
+
+    #[error("tuple {row} is at version {actual}, not the expected version {expected}")]
+    VersionConflict {
+        row: usize,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("value of {size} bytes is larger than the limit of {limit} bytes")]
+    ValueTooLarge { size: usize, limit: usize },
 }
@@ -0,0 +1,132 @@
+//! A budget of open file handles shared between `FileManager`s, so that an
+//! application with many database files does not run out of file
+//! descriptors.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+
+/// An `FdPool` limits how many files the `FileManager`s registered with it
+/// by `FileManager::with_fd_pool` keep open at once. When a file must be
+/// opened and the limit has been reached, the least recently used file is
+/// closed; it is reopened the next time its `FileManager` accesses it.
+///
+/// Clones of an `FdPool` share the same budget. Each file access locks the
+/// pool for its duration, so `FileManager`s sharing a pool do not perform
+/// I/O concurrently.
+#[derive(Clone)]
+pub struct FdPool {
+    inner: Arc<Mutex<FdPoolState>>,
+}
+
+struct FdPoolState {
+    max_open: usize,
+    next_id: u64,
+    /// Open files and the ids of the `PooledFile`s they belong to, from least
+    /// to most recently used.
+    open: Vec<(u64, File)>,
+    reopens: u64,
+}
+
+/// A file registered with an `FdPool`, which may or may not be open. The file
+/// is closed and removed from the pool when this is dropped.
+pub(super) struct PooledFile {
+    pool: FdPool,
+    id: u64,
+    path: String,
+}
+
+impl FdPool {
+    /// Creates a new `FdPool` which keeps at most `max_open` files open.
+    /// Returns an error if `max_open` is 0.
+    pub fn new(max_open: usize) -> DBResult<Self> {
+        if max_open == 0 {
+            return Err(DBError::from(StorageError::InvalidArgument(
+                "an FdPool must allow at least one open file.".to_string(),
+            )));
+        }
+        Ok(FdPool {
+            inner: Arc::new(Mutex::new(FdPoolState {
+                max_open,
+                next_id: 0,
+                open: Vec::new(),
+                reopens: 0,
+            })),
+        })
+    }
+
+    /// Returns the number of files currently open.
+    pub fn open_files(&self) -> usize {
+        self.lock().open.len()
+    }
+
+    /// Returns the number of times a file was reopened after being closed to
+    /// stay within the budget.
+    pub fn reopens(&self) -> u64 {
+        self.lock().reopens
+    }
+
+    /// Registers `file`, which was opened from `path`, with the pool. If the
+    /// pool is already at its limit, the least recently used file is closed.
+    pub(super) fn register(&self, file: File, path: &str) -> PooledFile {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.insert(id, file);
+        PooledFile {
+            pool: self.clone(),
+            id,
+            path: path.to_string(),
+        }
+    }
+
+    /// Locks the pool. The state is kept consistent between every statement
+    /// that can panic, so a poisoned lock is still usable.
+    fn lock(&self) -> MutexGuard<'_, FdPoolState> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl FdPoolState {
+    /// Adds `file` as the most recently used, closing the least recently used
+    /// files until the pool is within its limit.
+    fn insert(&mut self, id: u64, file: File) {
+        while self.open.len() >= self.max_open {
+            self.open.remove(0);
+        }
+        self.open.push((id, file));
+    }
+}
+
+impl PooledFile {
+    /// Runs `op` on the open file, reopening it first if it was closed, and
+    /// marks it as the most recently used.
+    pub(super) fn with_file<T>(&self, op: impl FnOnce(&File) -> io::Result<T>) -> io::Result<T> {
+        let mut state = self.pool.lock();
+        let file = match state.open.iter().position(|(id, _)| *id == self.id) {
+            Some(i) => state.open.remove(i).1,
+            None => {
+                let file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+                state.reopens += 1;
+                file
+            }
+        };
+        state.insert(self.id, file);
+        op(&state.open.last().unwrap().1)
+    }
+
+    /// Returns the pool this file is registered with.
+    pub(super) fn pool(&self) -> &FdPool {
+        &self.pool
+    }
+}
+
+impl Drop for PooledFile {
+    fn drop(&mut self) {
+        self.pool.lock().open.retain(|(id, _)| *id != self.id);
+    }
+}
@@ -1,30 +1,251 @@
-use std::collections::HashMap;
-use std::fs::{remove_file, rename, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{remove_file, rename};
+use std::io::{self, Write};
+use std::mem;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use std::time::Duration;
 
 use crate::{DBError, DBResult};
 
+use super::access_trace::AccessTrace;
+use super::backend::Backend;
 use super::error::StorageError;
+use super::fd_pool::FdPool;
+use super::page_frame::{self, FRAME_HEADER_SIZE};
+use super::{AccessEvent, PageLayout, PageSource, RecordId, Replacer};
 
 /// A `Page` is a portion of a database file which can be read from a database
 /// file, modified in main memory, and written back to the database file.
 /// It consists of a vector of bytes, which has a maximum length of `page_size`
 /// specified in `FileManager`.
+///
+/// The bytes are shared with any `PinnedPage`s for the page, and copied
+/// before being modified while they are shared.
 struct Page {
-    data: Vec<u8>,
+    data: Arc<Vec<u8>>,
     dirty: bool,
+    dirty_range: Range<usize>,
     pin_count: u16,
+    uses: u64,
+    /// Value of the `FileManager`'s use clock at the last use of the page.
+    last_use: u64,
+    /// Whether the page has been used again since it was loaded or the clock
+    /// hand last passed it.
+    ref_bit: bool,
+}
+
+impl Page {
+    /// Returns `true` if the page is pinned by `pin_page` or a `PinnedPage`,
+    /// so it cannot be evicted or deallocated.
+    fn is_pinned(&self) -> bool {
+        self.pin_count > 0 || Arc::strong_count(&self.data) > 1
+    }
+}
+
+/// The payload of a page read by `FileManager::read_page_pinned`. The page
+/// stays pinned in the buffer pool until the `PinnedPage` is dropped, and
+/// its bytes can be read through `Deref` in the meantime, whatever else is
+/// done with the `FileManager`.
+///
+/// The bytes are those of the page when it was read: if the page is
+/// written to while it is pinned, the `PinnedPage` keeps the old bytes.
+pub struct PinnedPage {
+    page_id: u64,
+    data: Arc<Vec<u8>>,
+    payload: Range<usize>,
+}
+
+impl PinnedPage {
+    /// Returns the id of the pinned page.
+    pub fn page_id(&self) -> u64 {
+        self.page_id
+    }
+}
+
+impl Deref for PinnedPage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[self.payload.clone()]
+    }
+}
+
+/// Detects a consistent stride between the pages passed to consecutive
+/// `read_page` calls, so that the pages further along it can be read ahead.
+#[derive(Debug, Clone, Copy)]
+struct ReadAhead {
+    depth: usize,
+    last_page: Option<u64>,
+    stride: Option<i64>,
+}
+
+impl ReadAhead {
+    /// Records a read of `page_id`, and returns the stride to read ahead
+    /// along if it is the same as the stride of the previous read.
+    fn record(&mut self, page_id: u64) -> Option<i64> {
+        let last_page = self.last_page.replace(page_id)?;
+        let stride = (page_id as i64).checked_sub(last_page as i64)?;
+        if stride == 0 {
+            // Rereading a page says nothing about the access pattern
+            return None;
+        }
+        (self.stride.replace(stride) == Some(stride)).then_some(stride)
+    }
+}
+
+/// Identifies the metadata page at the start of a database file. It is
+/// followed by the byte giving the file's format version.
+const METADATA_MAGIC: [u8; 7] = *b"SKIBIDB";
+
+/// The version of the on-disk format of pages and tuples which this library
+/// reads and writes. Files written in a newer format are rejected when they
+/// are opened, rather than being misread.
+pub const FORMAT_VERSION: u8 = 0;
+
+/// Size in bytes of the fixed part of the metadata page: the magic bytes and
+/// format version, the page count, and the number of free pages, which are
+/// listed after it.
+const METADATA_HEADER_SIZE: usize = 24;
+
+/// How a `FileManager` chooses which unpinned page to evict when its buffer
+/// pool is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// The unpinned page with the lowest id is evicted, so that evictions
+    /// are deterministic. This is the default.
+    #[default]
+    LowestId,
+    /// The least frequently used unpinned page is evicted, breaking ties by
+    /// lowest id. Each read, write, or pin of a page in the buffer pool
+    /// counts as a use. After every `aging_interval` uses of any page, the
+    /// use counts of all pages are halved, so that pages which were once hot
+    /// can eventually be evicted.
+    Lfu { aging_interval: u64 },
+    /// The least recently used unpinned page is evicted. Each read, write,
+    /// or pin of a page in the buffer pool counts as a use, and pages which
+    /// were read ahead but have not been used yet are evicted first.
+    Lru,
+    /// Pages are evicted by the clock (second chance) algorithm: a hand
+    /// sweeps over the unpinned pages in order of id, wrapping around, and
+    /// evicts the first page which has not been used again since it was
+    /// loaded or the hand last passed it. This approximates `Lru` while only
+    /// setting a flag on each use.
+    Clock,
+}
+
+/// How a `FileManager` and the structures stored in it handle corrupt data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Corrupt data is reported as an error. This is the default.
+    #[default]
+    Strict,
+    /// Corrupt pages and tuples are skipped and recorded instead, so that a
+    /// recovery tool can salvage everything that is still readable. The
+    /// skipped items are listed by `FileManager::corrupt_items`.
+    Recover,
+}
+
+/// An item which was skipped because it was corrupt while a `FileManager`
+/// was in `OpenMode::Recover`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptItem {
+    /// The metadata page could not be read, so the page count was taken
+    /// from the file length and the free list was lost.
+    Metadata,
+    /// A page could not be read or decoded, so all of its tuples were lost.
+    Page(u64),
+    /// A single tuple could not be decoded.
+    Tuple(RecordId),
+}
+
+/// A problem with an entry of the free list, as found by
+/// `FileManager::validate_free_list`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeListIssue {
+    /// The page is listed more than once, so it could be allocated twice.
+    Duplicate(u64),
+    /// The page is the metadata page or past the last allocated page.
+    OutOfRange(u64),
+    /// The page is still referenced, so reusing it would overwrite live data.
+    Referenced(u64),
+}
+
+/// Counters describing how the buffer pool of a `FileManager` has been used
+/// since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Number of `read_page` calls for pages already in the buffer pool.
+    pub hits: u64,
+    /// Number of `read_page` calls which had to read the page from disk.
+    pub misses: u64,
+    /// Number of pages evicted from the buffer pool.
+    pub evictions: u64,
+    /// Number of dirty pages written to disk.
+    pub flushes: u64,
+    /// Number of times the file was synced to disk by `flush_all_pages`.
+    pub syncs: u64,
+}
+
+/// A summary of how full the buffer pool of a `FileManager` is and how often
+/// it has had to evict pages, as returned by `FileManager::pool_pressure`,
+/// for deciding whether the pool should be larger.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolPressure {
+    /// Number of pages currently in the buffer pool.
+    pub occupancy: usize,
+    /// Largest number of pages the buffer pool has held at once.
+    pub peak_occupancy: usize,
+    /// Number of pages evicted from the buffer pool.
+    pub evictions: u64,
+    /// Fraction of `read_page` calls which evicted a page to make room for
+    /// the page being read, or 0 if there have been no reads.
+    pub eviction_rate: f64,
 }
 
 /// A `FileManager` manages reads and writes to a database file through a
 /// `buffer_pool` of pages.
 pub struct FileManager {
     file_path: String,
-    file: File,
+    file: Backend,
     buffer_pool: HashMap<u64, Page>,
     page_size: usize,
+    layout: PageLayout,
+    frame: Option<Range<usize>>,
     max_pages_in_pool: usize,
+    max_pool_bytes: Option<usize>,
+    /// Total size of the pages in the buffer pool which count towards its
+    /// limits, which excludes resident pages.
+    pool_bytes: usize,
     num_pages: u64,
+    free_pages: Vec<u64>,
+    eviction_policy: EvictionPolicy,
+    replacer: Option<Box<dyn Replacer>>,
+    prefer_clean: bool,
+    uses_since_aging: u64,
+    use_clock: u64,
+    /// Id of the page at which the next sweep of `EvictionPolicy::Clock`
+    /// starts.
+    clock_hand: u64,
+    resident_pages: HashSet<u64>,
+    eviction_timeout: Option<Duration>,
+    group_commit: Option<(Duration, usize)>,
+    punch_holes: bool,
+    track_dirty_regions: bool,
+    open_mode: OpenMode,
+    corrupt_items: Vec<CorruptItem>,
+    access_trace: Option<AccessTrace>,
+    read_ahead: Option<ReadAhead>,
+    flush_on_drop: bool,
+    io_retries: Option<(u32, Duration)>,
+    verify_writes: bool,
+    page_source: Option<Box<dyn PageSource>>,
+    #[cfg(test)]
+    corrupt_writes: bool,
+    stats: BufferPoolStats,
+    peak_occupancy: usize,
+    evicting_reads: u64,
 }
 
 impl FileManager {
@@ -34,6 +255,29 @@ impl FileManager {
     /// the maximum number of pages that can be held in the buffer pool.
     /// `max_pages_in_pool` must be at least 1.
     pub fn new(path: &str, page_size: usize, max_pages_in_pool: usize) -> DBResult<Self> {
+        Self::open(path, page_size, max_pages_in_pool, OpenMode::Strict)
+    }
+
+    /// Creates a new `FileManager` like `new`, which handles corrupt data
+    /// according to `mode`.
+    pub fn open(
+        path: &str,
+        page_size: usize,
+        max_pages_in_pool: usize,
+        mode: OpenMode,
+    ) -> DBResult<Self> {
+        Self::open_with(path, page_size, max_pages_in_pool, mode, false)
+    }
+
+    /// Creates a new `FileManager` like `open`, opening the file for direct
+    /// I/O if `direct` is `true`.
+    fn open_with(
+        path: &str,
+        page_size: usize,
+        max_pages_in_pool: usize,
+        mode: OpenMode,
+        direct: bool,
+    ) -> DBResult<Self> {
         // ------------------- FIRST: CHECKING ALL ARGS ------------------- //
         if path.is_empty() {
             return Err(DBError::from(StorageError::InvalidArgument(
@@ -41,78 +285,628 @@ impl FileManager {
             )));
         }
 
-        if page_size < 1 {
-            return Err(DBError::from(StorageError::InvalidArgument(
-                "invalid FileManager page size: must be at least 1 byte.".to_string(),
-            )));
-        }
+        check_pool_args(page_size, max_pages_in_pool)?;
 
-        if max_pages_in_pool < 1 {
-            return Err(DBError::from(StorageError::InvalidArgument(
-                "invalid FileManager `max_pages_in_pool`: must be at least 1.".to_string(),
-            )));
+        // --------------- NOW: ACTUALLY CREATING THE THING --------------- //
+        let file = Backend::open(path, direct)?;
+        Self::with_backend(path, file, page_size, max_pages_in_pool, mode)
+    }
+
+    /// Creates a new `FileManager` like `new`, which opens its file with
+    /// `O_DIRECT` so that reads and writes bypass the operating system's page
+    /// cache, for applications which do their own caching. Direct I/O needs
+    /// block-aligned buffers and offsets, so `page_size` must be a multiple
+    /// of 4096; partial page writes read and rewrite the blocks around them.
+    /// Not every filesystem supports direct I/O, in which case opening the
+    /// file fails.
+    #[cfg(target_os = "linux")]
+    pub fn new_direct(path: &str, page_size: usize, max_pages_in_pool: usize) -> DBResult<Self> {
+        if !page_size.is_multiple_of(super::backend::DIRECT_IO_ALIGNMENT) {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "page size {} must be a multiple of {} bytes for direct I/O.",
+                page_size,
+                super::backend::DIRECT_IO_ALIGNMENT
+            ))));
         }
+        Self::open_with(path, page_size, max_pages_in_pool, OpenMode::Strict, true)
+    }
 
-        // --------------- NOW: ACTUALLY CREATING THE THING --------------- //
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
+    /// Creates a new `FileManager` whose pages are stored in memory instead
+    /// of a file, so nothing is ever written to disk. It behaves like a
+    /// `FileManager` for a new, empty file: flushing a page clears its dirty
+    /// flag and stores its bytes in memory, so that it can be evicted and
+    /// read again. All pages are lost when the `FileManager` is dropped.
+    pub fn in_memory(page_size: usize, max_pages_in_pool: usize) -> DBResult<Self> {
+        check_pool_args(page_size, max_pages_in_pool)?;
+        Self::with_backend(
+            ":memory:",
+            Backend::Memory(Vec::new()),
+            page_size,
+            max_pages_in_pool,
+            OpenMode::Strict,
+        )
+    }
 
-        let num_pages = file.metadata()?.len() / (page_size as u64);
+    /// Creates a new `FileManager` on top of `file`, restoring the page
+    /// count and free list from its metadata page if it has one.
+    fn with_backend(
+        path: &str,
+        mut file: Backend,
+        page_size: usize,
+        max_pages_in_pool: usize,
+        mode: OpenMode,
+    ) -> DBResult<Self> {
+        // Restore the page count and free list from the metadata page; files
+        // without one use the file length instead. Files in a newer format
+        // are not corrupt, so they are never recovered from
+        let mut corrupt_items = Vec::new();
+        let metadata = match read_metadata(&mut file, page_size) {
+            Err(e @ DBError::StorageError(StorageError::UnsupportedFormatVersion(_))) => {
+                return Err(e)
+            }
+            Err(e) if mode == OpenMode::Recover => {
+                log_corruption(CorruptItem::Metadata, &e);
+                corrupt_items.push(CorruptItem::Metadata);
+                None
+            }
+            result => result?,
+        };
+        let (num_pages, free_pages) = match metadata {
+            Some(metadata) => metadata,
+            None => (file.len()? / (page_size as u64), Vec::new()),
+        };
 
         Ok(FileManager {
             file_path: path.to_string(),
             file,
             buffer_pool: HashMap::new(),
             page_size,
+            layout: PageLayout::new(page_size),
+            frame: None,
             max_pages_in_pool,
+            max_pool_bytes: None,
+            pool_bytes: 0,
             num_pages,
+            free_pages,
+            eviction_policy: EvictionPolicy::default(),
+            replacer: None,
+            prefer_clean: false,
+            uses_since_aging: 0,
+            use_clock: 0,
+            clock_hand: 0,
+            resident_pages: HashSet::new(),
+            eviction_timeout: None,
+            group_commit: None,
+            punch_holes: false,
+            track_dirty_regions: false,
+            open_mode: mode,
+            corrupt_items,
+            access_trace: None,
+            read_ahead: None,
+            flush_on_drop: true,
+            io_retries: None,
+            verify_writes: false,
+            page_source: None,
+            #[cfg(test)]
+            corrupt_writes: false,
+            stats: BufferPoolStats::default(),
+            peak_occupancy: 0,
+            evicting_reads: 0,
         })
     }
 
-    /// Reads and returns a page of bytes from the file given its `page_id`.
+    /// Sets how this `FileManager` chooses which page to evict when its buffer
+    /// pool is full.
+    pub fn with_eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    /// Returns the current eviction policy.
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.eviction_policy
+    }
+
+    /// Makes this `FileManager` ask `replacer` which page to evict when its
+    /// buffer pool is full, instead of following its eviction policy; see
+    /// `Replacer`. This should be set before any pages are read, so that the
+    /// replacer sees every access.
+    pub fn with_replacer(mut self, replacer: Box<dyn Replacer>) -> Self {
+        self.replacer = Some(replacer);
+        self
+    }
+
+    /// Makes eviction choose among clean unpinned pages before dirty ones,
+    /// whatever the eviction policy, since evicting a dirty page means
+    /// flushing it first. A dirty page is only evicted when every unpinned
+    /// page is dirty, and is chosen among them by the eviction policy.
+    pub fn with_clean_page_preference(mut self) -> Self {
+        self.prefer_clean = true;
+        self
+    }
+
+    /// Sets how long callers sharing this `FileManager` between threads
+    /// should keep retrying an operation that failed because every page in
+    /// the buffer pool is pinned, giving other threads a chance to unpin a
+    /// page before `BufferPoolFull` is returned. By default, no retries
+    /// are made.
+    ///
+    /// Retries are made by `SharedFileManager`; a `FileManager` used from a
+    /// single thread cannot have pages unpinned while it is waiting, so it
+    /// always fails immediately.
+    pub fn with_eviction_timeout(mut self, timeout: Duration) -> Self {
+        self.eviction_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the eviction timeout set by `with_eviction_timeout`, if any.
+    pub fn eviction_timeout(&self) -> Option<Duration> {
+        self.eviction_timeout
+    }
+
+    /// Makes `flush_page` read every page back from disk right after writing
+    /// it, and return a `WriteVerificationFailed` error if the bytes differ
+    /// from those written or, with checksums enabled, if the page's checksum
+    /// does not verify. This catches layout and checksum bugs at the write
+    /// which caused them rather than when the page is next read, at the cost
+    /// of a read for every write, so it is meant for debugging.
+    pub fn with_write_verification(mut self) -> Self {
+        self.verify_writes = true;
+        self
+    }
+
+    /// Makes this `FileManager` load pages which are not in the buffer pool
+    /// from `source`, and store flushed pages in it, instead of reading and
+    /// writing them in its file; see `PageSource`. This should be set before
+    /// any pages are read, since pages already in the buffer pool may have
+    /// been read from the file. Retries set by `with_io_retries` and write
+    /// verification only apply to the file.
+    pub fn set_page_source(&mut self, source: Box<dyn PageSource>) {
+        self.page_source = Some(source);
+    }
+
+    /// Registers the file of this `FileManager` with `pool`, which may close
+    /// it while other files registered with the pool are in use, and reopens
+    /// it when it is next accessed. This has no effect on an in-memory
+    /// `FileManager`, one using direct I/O, or one which is already
+    /// registered with a pool.
+    pub fn with_fd_pool(mut self, pool: &FdPool) -> Self {
+        let file = std::mem::replace(&mut self.file, Backend::Memory(Vec::new()));
+        self.file = match file {
+            Backend::File(file) => Backend::Pooled(pool.register(file, &self.file_path)),
+            other => other,
+        };
+        self
+    }
+
+    /// Makes reading a page from disk in `read_page` and writing one in
+    /// `flush_page` retry up to `retries` times when they fail with an error
+    /// which may be transient: `Interrupted`, `WouldBlock`, or `TimedOut`.
+    /// The first retry waits for `backoff`, and each one after it waits
+    /// twice as long as the last. Other errors, and the last error once
+    /// every retry has failed, are returned immediately. By default, no
+    /// retries are made.
+    pub fn with_io_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.io_retries = Some((retries, backoff));
+        self
+    }
+
+    /// Returns the number of retries and initial backoff set by
+    /// `with_io_retries`, if any.
+    pub fn io_retries(&self) -> Option<(u32, Duration)> {
+        self.io_retries
+    }
+
+    /// Makes `SharedFileManager::commit` batch commits from different threads
+    /// into a single `flush_all_pages`. The first commit of a batch waits up
+    /// to `window` for other commits to join it, or until `max_batch`
+    /// commits are pending, and then flushes and syncs once for all of them.
+    /// Every commit is still durable by the time it returns.
+    pub fn with_group_commit(mut self, window: Duration, max_batch: usize) -> Self {
+        self.group_commit = Some((window, max_batch.max(1)));
+        self
+    }
+
+    /// Returns the window and maximum batch size set by `with_group_commit`,
+    /// if any.
+    pub fn group_commit(&self) -> Option<(Duration, usize)> {
+        self.group_commit
+    }
+
+    /// Makes `deallocate_page` punch a hole in the file over each freed page,
+    /// so that the filesystem releases its disk blocks instead of keeping
+    /// them until the page is reused. This is only supported on Linux, and
+    /// only by some filesystems; elsewhere, freed pages are just added to
+    /// the free list as usual.
+    pub fn with_hole_punching(mut self) -> Self {
+        self.punch_holes = true;
+        self
+    }
+
+    /// Makes this `FileManager` track which bytes of each page have changed
+    /// since it was last flushed, so that `flush_page` only writes the range
+    /// from the first to the last changed byte. Pages which were not read
+    /// from disk before being written are still flushed in full.
+    ///
+    /// This has no effect on files with checksums and compression enabled,
+    /// which always write whole pages.
+    pub fn with_dirty_region_tracking(mut self) -> Self {
+        self.track_dirty_regions = true;
+        self
+    }
+
+    /// Sets whether dropping this `FileManager` flushes all of its pages, as
+    /// it does by default. When disabled, changes which were not flushed by
+    /// then are discarded, along with any changes to the page count and free
+    /// list since the metadata page was last written.
+    pub fn set_flush_on_drop(&mut self, enabled: bool) {
+        self.flush_on_drop = enabled;
+    }
+
+    /// Returns `true` if dropping this `FileManager` flushes all of its
+    /// pages; see `set_flush_on_drop`.
+    pub fn flush_on_drop(&self) -> bool {
+        self.flush_on_drop
+    }
+
+    /// Makes `read_page` read up to `depth` pages ahead into the buffer pool
+    /// once the last three calls to it were for pages a constant stride
+    /// apart, such as every page or every other page of a scan. Pages are
+    /// read ahead along that stride, so the stride can also be negative.
+    /// Nothing is read ahead while pages are read in no consistent order.
+    ///
+    /// Pages read ahead may evict other unpinned pages, so `depth` should be
+    /// well below `max_pages_in_pool`; it is capped at one less than it.
+    pub fn with_read_ahead(mut self, depth: usize) -> Self {
+        self.read_ahead = Some(ReadAhead {
+            depth,
+            last_page: None,
+            stride: None,
+        });
+        self
+    }
+
+    /// Makes this `FileManager` record the most recent `capacity` calls to
+    /// `read_page`, `write_page_to_pool`, `pin_page`, `unpin_page`,
+    /// `allocate_page`, and `deallocate_page` that succeed. The recorded
+    /// calls are returned by `access_trace`, and can be made again against
+    /// another `FileManager` with `replay`.
+    pub fn with_access_trace(mut self, capacity: usize) -> Self {
+        self.access_trace = Some(AccessTrace::new(capacity));
+        self
+    }
+
+    /// Returns the calls recorded since `with_access_trace` was used, oldest
+    /// first. This is empty if calls are not being recorded.
+    pub fn access_trace(&self) -> &[AccessEvent] {
+        self.access_trace.as_ref().map_or(&[], AccessTrace::events)
+    }
+
+    /// Returns the maximum number of pages that can be held in the buffer
+    /// pool.
+    pub fn max_pages_in_pool(&self) -> usize {
+        self.max_pages_in_pool
+    }
+
+    /// Limits the buffer pool by the total size in bytes of the pages it
+    /// holds instead of by their number, so that a page is evicted before
+    /// loading another would take the pool over `max_bytes`. Resident pages
+    /// do not count towards the limit. Since every page in the pool is
+    /// `page_size` bytes, this currently allows `max_bytes / page_size`
+    /// pages.
+    ///
+    /// The two limits are exclusive: this replaces `max_pages_in_pool` until
+    /// `set_max_pages_in_pool` is called. Returns an error if `max_bytes` is
+    /// smaller than a single page.
+    pub fn with_max_pool_bytes(mut self, max_bytes: usize) -> DBResult<Self> {
+        if max_bytes < self.page_size {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "invalid FileManager `max_pool_bytes`: must be at least the page size of {} bytes.",
+                self.page_size
+            ))));
+        }
+        self.max_pool_bytes = Some(max_bytes);
+        Ok(self)
+    }
+
+    /// Returns the limit set by `with_max_pool_bytes`, if the buffer pool is
+    /// limited by bytes instead of pages.
+    pub fn max_pool_bytes(&self) -> Option<usize> {
+        self.max_pool_bytes
+    }
+
+    /// Changes the maximum number of pages that can be held in the buffer
+    /// pool, replacing any limit set by `with_max_pool_bytes`. If the pool
+    /// holds more than `new_max` pages, unpinned pages are evicted until it
+    /// fits.
+    ///
+    /// Returns an error, without evicting anything, if `new_max` is 0 or
+    /// more than `new_max` pages are pinned.
+    pub fn set_max_pages_in_pool(&mut self, new_max: usize) -> DBResult<()> {
+        if new_max < 1 {
+            return Err(DBError::from(StorageError::InvalidArgument(
+                "invalid FileManager `max_pages_in_pool`: must be at least 1.".to_string(),
+            )));
+        }
+
+        let pinned = self
+            .buffer_pool
+            .values()
+            .filter(|page| page.is_pinned())
+            .count();
+        if pinned > new_max {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "cannot shrink the buffer pool to {} pages while {} pages are pinned.",
+                new_max, pinned
+            ))));
+        }
+
+        while self.counted_pages() > new_max {
+            self.evict_page()?;
+        }
+        self.max_pages_in_pool = new_max;
+        self.max_pool_bytes = None;
+        Ok(())
+    }
+
+    /// Returns the size in bytes of each page managed by this `FileManager`.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Returns the number of pages which have been allocated in the file,
+    /// including pages which have since been freed. Allocated page ids run
+    /// from 1 to `num_pages`; page 0 holds the file's metadata.
+    pub fn num_pages(&self) -> u64 {
+        self.num_pages
+    }
+
+    /// Returns the layout of the pages managed by this `FileManager`.
+    pub fn page_layout(&self) -> &PageLayout {
+        &self.layout
+    }
+
+    /// Returns the number of bytes of each page which can be read and
+    /// written by callers; this is the page size minus any reserved header.
+    pub fn usable_page_size(&self) -> usize {
+        self.layout.payload_len()
+    }
+
+    /// Reserves `len` bytes of the header of every page; see
+    /// `PageLayout::reserve`. This can only be done while the buffer pool is
+    /// empty, since it changes how pages are interpreted.
+    pub fn reserve_header_bytes(&mut self, len: usize) -> DBResult<Range<usize>> {
+        if !self.buffer_pool.is_empty() {
+            return Err(DBError::from(StorageError::InvalidArgument(
+                "cannot change the page layout while pages are in the buffer pool.".to_string(),
+            )));
+        }
+        self.layout.reserve(len)
+    }
+
+    /// Makes this `FileManager` checksum and compress every page it writes
+    /// to disk, and verify and decompress every page it reads, using the
+    /// frame described in `page_frame`. The metadata page is not framed.
+    ///
+    /// The frame header takes up reserved bytes in each page, as with
+    /// `reserve_header_bytes`, so this must be enabled before any pages are
+    /// in the buffer pool, and every `FileManager` for the file must enable
+    /// it the same way.
+    pub fn with_checksums_and_compression(mut self) -> DBResult<Self> {
+        if self.frame.is_some() {
+            return Err(DBError::from(StorageError::InvalidArgument(
+                "checksums and compression are already enabled.".to_string(),
+            )));
+        }
+        self.frame = Some(self.reserve_header_bytes(FRAME_HEADER_SIZE)?);
+        Ok(self)
+    }
+
+    /// Returns how this `FileManager` handles corrupt data.
+    pub fn open_mode(&self) -> OpenMode {
+        self.open_mode
+    }
+
+    /// Returns the items skipped so far because they were corrupt, in the
+    /// order they were found. This is always empty in `OpenMode::Strict`.
+    pub fn corrupt_items(&self) -> &[CorruptItem] {
+        &self.corrupt_items
+    }
+
+    /// Handles `error`, which was caused by `item` being corrupt. In
+    /// `OpenMode::Recover`, the item is recorded and `Ok` is returned so the
+    /// caller can skip it; otherwise, `error` is returned.
+    pub(crate) fn recover_from(&mut self, item: CorruptItem, error: DBError) -> DBResult<()> {
+        if self.open_mode != OpenMode::Recover {
+            return Err(error);
+        }
+
+        log_corruption(item, &error);
+        self.corrupt_items.push(item);
+        Ok(())
+    }
+
+    /// Returns counters describing how the buffer pool has been used.
+    pub fn stats(&self) -> BufferPoolStats {
+        self.stats
+    }
+
+    /// Returns how full the buffer pool is and has been, and how often
+    /// reading pages has had to evict others, to help tune
+    /// `max_pages_in_pool`.
+    pub fn pool_pressure(&self) -> PoolPressure {
+        let reads = self.stats.hits + self.stats.misses;
+        PoolPressure {
+            occupancy: self.buffer_pool.len(),
+            peak_occupancy: self.peak_occupancy,
+            evictions: self.stats.evictions,
+            eviction_rate: match reads {
+                0 => 0.0,
+                reads => self.evicting_reads as f64 / reads as f64,
+            },
+        }
+    }
+
+    /// Reads and returns the payload of a page from the file given its
+    /// `page_id`; its length is `usable_page_size`.
     /// If the page is not currently in the buffer pool, it will be loaded into
     /// the buffer pool and another page will be evicted. If a page cannot be
-    /// evicted, then this function will return an error.
+    /// evicted, then this function will return an error, as it will if
+    /// `page_id` is not the id of an allocated page.
     pub fn read_page(&mut self, page_id: u64) -> DBResult<&[u8]> {
-        // Add the page to the buffer pool if it is not already present
+        self.check_page_id(page_id)?;
+        self.load_page(page_id)?;
+        self.record(AccessEvent::Read(page_id));
+
+        // Pin the page so that reading ahead cannot evict it to make room
+        self.buffer_pool.get_mut(&page_id).unwrap().pin_count += 1;
+        self.read_ahead_of(page_id);
+        self.buffer_pool.get_mut(&page_id).unwrap().pin_count -= 1;
+        Ok(&self.buffer_pool.get(&page_id).unwrap().data[self.layout.payload_range()])
+
+        // Look! a wonderful field of flowers!
+        // ❃✿❀❃✿❀❃✿
+        // ❀❃✿❀❃✿❀❃
+        // ✿❀❃✿❀❃✿❀
+    }
+
+    /// Reads the payload of a page like `read_page`, and returns it as a
+    /// `PinnedPage` which keeps the page pinned, and so in the buffer pool,
+    /// until it is dropped. Unlike the slice returned by `read_page`, the
+    /// `PinnedPage` can be held while this `FileManager` is used for other
+    /// pages.
+    pub fn read_page_pinned(&mut self, page_id: u64) -> DBResult<PinnedPage> {
+        self.read_page(page_id)?;
+        Ok(PinnedPage {
+            page_id,
+            data: Arc::clone(&self.buffer_pool[&page_id].data),
+            payload: self.layout.payload_range(),
+        })
+    }
+
+    /// Returns the payload of a page like `read_page`, but only if it is
+    /// already in the buffer pool. Returns `None` otherwise, without reading
+    /// from disk or evicting any page.
+    pub fn try_read_page(&mut self, page_id: u64) -> Option<&[u8]> {
         if !self.buffer_pool.contains_key(&page_id) {
-            // If buffer pool is full, evict a page
-            while self.buffer_pool.len() >= self.max_pages_in_pool {
-                self.evict_page()?;
+            return None;
+        }
+
+        self.stats.hits += 1;
+        self.count_use(page_id);
+        self.record(AccessEvent::Read(page_id));
+        Some(&self.buffer_pool.get(&page_id).unwrap().data[self.layout.payload_range()])
+    }
+
+    /// Reads the payload of a page through the buffer pool as in `read_page`
+    /// and writes it to `out`, retrying until every byte has been written.
+    pub fn write_page_to<W: Write>(&mut self, page_id: u64, out: &mut W) -> DBResult<()> {
+        let data = self.read_page(page_id)?;
+        out.write_all(data)?;
+        Ok(())
+    }
+
+    /// Adds the page with the given `page_id` to the buffer pool if it is
+    /// not already present, evicting another page if the pool is full.
+    fn load_page(&mut self, page_id: u64) -> DBResult<()> {
+        // Add the page to the buffer pool if it is not already present
+        if self.buffer_pool.contains_key(&page_id) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            let evictions = self.stats.evictions;
+            self.read_from_disk(page_id)?;
+            if self.stats.evictions > evictions {
+                self.evicting_reads += 1;
             }
+        }
 
-            // Read page from disk
-            let mut page_data = vec![0; self.page_size];
-            self.file
-                .seek(SeekFrom::Start(page_id * (self.page_size as u64)))?;
-            self.file.read_exact(&mut page_data)?;
+        self.count_use(page_id);
+        Ok(())
+    }
 
-            // Add page to buffer pool
-            self.buffer_pool.insert(
+    /// Reads the page with the given `page_id` from disk into the buffer
+    /// pool, evicting another page if the pool is full.
+    fn read_from_disk(&mut self, page_id: u64) -> DBResult<()> {
+        // If buffer pool is full, evict a page
+        while self.pool_is_full() {
+            self.evict_page()?;
+        }
+
+        // Read page from disk
+        let mut page_data = vec![0; self.page_size];
+        let offset = page_offset(page_id, self.page_size)?;
+        match &self.page_source {
+            Some(source) => source.load(page_id, &mut page_data)?,
+            None => trace_timed!(
                 page_id,
-                Page {
-                    data: page_data,
-                    dirty: false,
-                    pin_count: 0,
-                },
-            );
+                "read page from disk",
+                retry_io(self.io_retries, || self
+                    .file
+                    .read_at(offset, &mut page_data))
+            )
+            .map_err(page_io(page_id, offset))?,
+        }
+        if let (Some(reserved), true) = (&self.frame, page_id != 0) {
+            page_data = page_frame::decode_page(&page_data, reserved, page_id)?;
         }
 
-        Ok(&self.buffer_pool.get(&page_id).unwrap().data)
+        // Add page to buffer pool
+        self.pool_bytes += page_data.len();
+        self.buffer_pool.insert(
+            page_id,
+            Page {
+                data: Arc::new(page_data),
+                dirty: false,
+                dirty_range: 0..0,
+                pin_count: 0,
+                uses: 0,
+                last_use: 0,
+                ref_bit: false,
+            },
+        );
+        self.peak_occupancy = self.peak_occupancy.max(self.buffer_pool.len());
+        Ok(())
+    }
 
-        // Look! a wonderful field of flowers!
-        // ❃✿❀❃✿❀❃✿
-        // ❀❃✿❀❃✿❀❃
-        // ✿❀❃✿❀❃✿❀
+    /// Reads the pages after `page_id` along the stride detected by
+    /// `with_read_ahead` into the buffer pool, if there is one. Pages which
+    /// are already in the pool or past the end of the file are skipped.
+    /// Reading ahead is only a hint, so it stops at the first page which
+    /// cannot be read, such as when no page can be evicted or a page is
+    /// corrupt, and the error is left for an actual read of that page.
+    fn read_ahead_of(&mut self, page_id: u64) {
+        let Some(read_ahead) = &mut self.read_ahead else {
+            return;
+        };
+        let Some(stride) = read_ahead.record(page_id) else {
+            return;
+        };
+        let capacity = match self.max_pool_bytes {
+            Some(max_bytes) => max_bytes / self.page_size,
+            None => self.max_pages_in_pool,
+        };
+        let depth = read_ahead.depth.min(capacity - 1);
+
+        let ahead = (1..=depth as i64)
+            .map_while(|i| (page_id as i64).checked_add(stride.checked_mul(i)?))
+            .take_while(|&id| id > 0 && (id as u64) <= self.num_pages);
+        for id in ahead.map(|id| id as u64).collect::<Vec<_>>() {
+            if self.buffer_pool.contains_key(&id) {
+                continue;
+            }
+            if self.read_from_disk(id).is_err() {
+                break;
+            }
+            trace_event!(debug, page_id = id, "read page ahead");
+        }
     }
 
-    /// Given bytes `data`, write to the page with the given `page_id` in the
-    /// `FileManager`'s buffer pool. All data in the page will be overwritten,
-    /// and the page will be marked dirty. `data` must have a length exactly
-    /// equal to the `page_size` specified when creating this `FileManager`.
+    /// Given bytes `data`, write to the payload of the page with the given
+    /// `page_id` in the `FileManager`'s buffer pool. All data in the payload
+    /// will be overwritten, and the page will be marked dirty; the reserved
+    /// header is left untouched. `data` must have a length exactly equal to
+    /// `usable_page_size`.
     ///
     /// If the page with the given `page_id` is not in the buffer pool, it is
     /// added to the buffer pool; if the pool is full and no page can be
@@ -123,44 +917,88 @@ impl FileManager {
     ///
     /// **NOTE:** This does **not** write the data to disk. In order to do
     /// that, call `flush_page` with the given `page_id`.
-    fn write_page_to_pool(&mut self, page_id: u64, data: &[u8]) -> DBResult<()> {
+    pub fn write_page_to_pool(&mut self, page_id: u64, data: &[u8]) -> DBResult<()> {
+        self.check_page_id(page_id)?;
+        self.write_payload(page_id, data)?;
+        self.record(AccessEvent::Write(page_id));
+        Ok(())
+    }
+
+    /// Writes `new` to the payload of the page with the given `page_id` like
+    /// `write_page_to_pool`, but only if its current payload, read through
+    /// the buffer pool, is equal to `expected`. Returns whether `new` was
+    /// written; if it was not, the page is left untouched.
+    pub fn compare_and_write_page(
+        &mut self,
+        page_id: u64,
+        expected: &[u8],
+        new: &[u8],
+    ) -> DBResult<bool> {
+        if self.read_page(page_id)? != expected {
+            return Ok(false);
+        }
+        self.write_page_to_pool(page_id, new)?;
+        Ok(true)
+    }
+
+    /// Writes `data` to the payload of a page in the buffer pool; see
+    /// `write_page_to_pool`.
+    fn write_payload(&mut self, page_id: u64, data: &[u8]) -> DBResult<()> {
         // Reject incorrect size data
-        if data.len() != self.page_size {
+        if data.len() != self.usable_page_size() {
             return Err(DBError::from(StorageError::InvalidArgument(format!(
                 "length of data to write to page: {} did not match expected page size of {} bytes.",
                 data.len(),
-                self.page_size
+                self.usable_page_size()
             ))));
         }
 
         // Add page to buffer pool if it is not present
-        if !self.buffer_pool.contains_key(&page_id) {
+        let loaded = self.buffer_pool.contains_key(&page_id);
+        if !loaded {
             // If the buffer pool is full, evict items until it has space
-            while self.buffer_pool.len() >= self.max_pages_in_pool {
+            while self.pool_is_full() {
                 self.evict_page()?;
             }
 
             // Add page to buffer pool
+            self.pool_bytes += self.page_size;
             self.buffer_pool.insert(
                 page_id,
                 Page {
-                    data: vec![0; self.page_size],
+                    data: Arc::new(vec![0; self.page_size]),
                     dirty: false,
+                    dirty_range: 0..0,
                     pin_count: 0,
+                    uses: 0,
+                    last_use: 0,
+                    ref_bit: false,
                 },
             );
+            self.peak_occupancy = self.peak_occupancy.max(self.buffer_pool.len());
         }
 
         // Unwrapping is safe because the item was just added to the pool
         let page = self.buffer_pool.get_mut(&page_id).unwrap();
 
-        page.data.copy_from_slice(data);
+        let payload = self.layout.payload_range();
+        let changed = if self.track_dirty_regions && loaded {
+            let changed = changed_range(&page.data[payload.clone()], data);
+            changed.start + payload.start..changed.end + payload.start
+        } else {
+            0..self.page_size
+        };
+        Arc::make_mut(&mut page.data)[payload].copy_from_slice(data);
         page.dirty = true;
+        page.dirty_range = union(&page.dirty_range, &changed);
+        trace_event!(debug, page_id, "wrote page to buffer pool");
 
+        self.count_use(page_id);
         Ok(())
     }
 
-    /// Allocate a new page in the buffer pool.
+    /// Allocate a new page in the buffer pool. Pages freed by
+    /// `deallocate_page` are reused before the file is grown.
     ///
     /// If the buffer pool is full and no pages can be evicted, then an error
     /// will be returned.
@@ -169,95 +1007,485 @@ impl FileManager {
     /// disk, then the page will be removed from the buffer pool as if this
     /// function was never called.
     pub fn allocate_page(&mut self) -> DBResult<u64> {
-        while self.num_pages >= (self.max_pages_in_pool as u64) {
+        // Reuse a freed page if there is one
+        if let Some(page_id) = self.free_pages.pop() {
+            let zeros = vec![0; self.usable_page_size()];
+            if let Err(e) = self.write_payload(page_id, &zeros) {
+                self.free_pages.push(page_id);
+                return Err(e);
+            }
+            self.record(AccessEvent::Allocate(page_id));
+            return Ok(page_id);
+        }
+
+        // The pool only caches pages, so make room in it for the new page,
+        // whatever the size of the file
+        while self.pool_is_full() {
             self.evict_page()?;
         }
 
         let page_id = self.num_pages + 1;
 
-        let zeros = vec![0; self.page_size];
-        self.write_page_to_pool(page_id, &zeros)?;
+        let zeros = vec![0; self.usable_page_size()];
+        self.write_payload(page_id, &zeros)?;
 
         // If everything was successful, increase `num_pages`
         self.num_pages += 1;
+        self.record(AccessEvent::Allocate(page_id));
         Ok(self.num_pages)
     }
 
-    /// Pins a page in memory; a page can only be removed from the buffer
-    /// pool if no threads have pinned it.
-    pub fn pin_page(&mut self, page_id: u64) -> DBResult<()> {
-        if let Some(page) = self.buffer_pool.get_mut(&page_id) {
-            page.pin_count += 1;
-        } else {
-            // Load the page into memory
-            self.read_page(page_id)?;
-            if let Some(page) = self.buffer_pool.get_mut(&page_id) {
-                page.pin_count += 1;
+    /// Allocates `count` pages with consecutive ids and returns the range of
+    /// their ids. If `count` pages freed by `deallocate_page` have
+    /// consecutive ids, the lowest such run is reused; otherwise the pages
+    /// are added at the end of the file, which is grown once for all of
+    /// them. The pages are not added to the buffer pool, so this is much
+    /// faster than calling `allocate_page` `count` times.
+    ///
+    /// The new pages read as zeros.
+    pub fn allocate_pages(&mut self, count: u64) -> DBResult<Range<u64>> {
+        if let Some(run) = free_run(&self.free_pages, count) {
+            if let Some(source) = &self.page_source {
+                let zeros = vec![0; self.page_size];
+                for page_id in run.clone() {
+                    source.store(page_id, &zeros)?;
+                }
             } else {
-                return Err(DBError::from(StorageError::UnknownPage(page_id)));
+                let offset = page_offset(run.start, self.page_size)?;
+                let zeros = vec![0; count as usize * self.page_size];
+                self.file
+                    .write_at(offset, &zeros)
+                    .map_err(page_io(run.start, offset))?;
+            }
+            self.free_pages.retain(|page_id| !run.contains(page_id));
+            for page_id in run.clone() {
+                self.record(AccessEvent::Allocate(page_id));
             }
+            return Ok(run);
         }
 
-        Ok(())
-    }
+        let start = self.num_pages + 1;
+        let end = start
+            .checked_add(count)
+            .ok_or(StorageError::PageOffsetOverflow(start))?;
+        let offset = page_offset(start, self.page_size)?;
+        self.file
+            .grow_to(page_offset(end, self.page_size)?)
+            .map_err(page_io(start, offset))?;
 
-    /// Unpins a page in the buffer pool and returns the number of pins the
-    /// page has after unpinning. If the page is not present in the buffer
-    /// pool, the function does nothing and returns `None`.
-    pub fn unpin_page(&mut self, page_id: u64) -> Option<u16> {
-        if let Some(page) = self.buffer_pool.get_mut(&page_id) {
-            if page.pin_count > 0 {
-                page.pin_count -= 1;
-            }
-            Some(page.pin_count)
-        } else {
-            None
+        self.num_pages += count;
+        for page_id in start..end {
+            self.record(AccessEvent::Allocate(page_id));
         }
+        Ok(start..end)
     }
 
-    /// Flushes a specific page to disk if it is dirty.
-    pub fn flush_page(&mut self, page_id: u64) -> DBResult<()> {
-        if let Some(page) = self.buffer_pool.get_mut(&page_id) {
-            if page.dirty {
-                self.file
-                    .seek(SeekFrom::Start(page_id * self.page_size as u64))?;
-                self.file.write_all(&page.data)?;
-                page.dirty = false;
+    /// Frees the page with the given `page_id` so that it can be reused by a
+    /// later call to `allocate_page`. The page is removed from the buffer
+    /// pool without being flushed, since its contents are no longer needed.
+    ///
+    /// Returns an error if the page is pinned, has not been allocated, or
+    /// has already been freed.
+    ///
+    /// The set of freed pages is persisted in the metadata page by
+    /// `flush_all_pages`.
+    pub fn deallocate_page(&mut self, page_id: u64) -> DBResult<()> {
+        self.check_page_id(page_id)?;
+        if self.free_pages.contains(&page_id) {
+            return Err(DBError::from(StorageError::UnknownPage(page_id)));
+        }
+
+        if let Some(page) = self.buffer_pool.get(&page_id) {
+            if page.is_pinned() {
+                return Err(DBError::from(StorageError::DeallocatePinnedPage(page_id)));
             }
+            self.remove_from_pool(page_id);
+        }
+
+        if self.punch_holes {
+            let offset = page_offset(page_id, self.page_size)?;
+            self.file
+                .punch_hole(offset, self.page_size as u64)
+                .map_err(page_io(page_id, offset))?;
         }
+
+        self.free_pages.push(page_id);
+        self.record(AccessEvent::Deallocate(page_id));
         Ok(())
     }
 
-    /// Flushes all pages in the buffer pool to disk if they are dirty.
-    /// This should be used with caution, especially when writing concurrently,
-    /// because it may disrupt ACID guarantees.
-    pub fn flush_all_pages(&mut self) -> DBResult<()> {
-        let page_ids: Vec<u64> = self.buffer_pool.keys().copied().collect();
+    /// Returns the ids of allocated pages which are neither free nor in
+    /// `reachable`, in increasing order. `reachable` should contain every
+    /// page referenced by the database's tables, indexes, and blobs; the
+    /// returned pages have leaked, such as after a crash, and can be freed
+    /// with `reclaim_orphans`.
+    pub fn find_orphaned_pages(&self, reachable: &[u64]) -> Vec<u64> {
+        let reachable: HashSet<u64> = reachable.iter().copied().collect();
+        let free: HashSet<u64> = self.free_pages.iter().copied().collect();
+        (1..=self.num_pages)
+            .filter(|page_id| !reachable.contains(page_id) && !free.contains(page_id))
+            .collect()
+    }
+
+    /// Frees each of the orphaned pages with the given `ids`, as found by
+    /// `find_orphaned_pages`, so they can be reused. Stops at the first page
+    /// which cannot be freed and returns its error.
+    pub fn reclaim_orphans(&mut self, ids: &[u64]) -> DBResult<()> {
+        for &page_id in ids {
+            self.deallocate_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the free list against `reachable`, which should contain every
+    /// page referenced by the database as for `find_orphaned_pages`, and
+    /// returns every problem found, in free list order. An empty list means
+    /// the free list is consistent.
+    pub fn validate_free_list(&self, reachable: &[u64]) -> Vec<FreeListIssue> {
+        let reachable: HashSet<u64> = reachable.iter().copied().collect();
+        let mut seen = HashSet::new();
+        self.free_pages
+            .iter()
+            .filter_map(|&page_id| {
+                if page_id == 0 || page_id > self.num_pages {
+                    Some(FreeListIssue::OutOfRange(page_id))
+                } else if !seen.insert(page_id) {
+                    Some(FreeListIssue::Duplicate(page_id))
+                } else if reachable.contains(&page_id) {
+                    Some(FreeListIssue::Referenced(page_id))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Replaces the free list with every allocated page which is not in
+    /// `reachable`, such as after `validate_free_list` found problems with
+    /// it. Unreachable pages in the buffer pool are removed from it without
+    /// being flushed, as in `deallocate_page`, but no holes are punched.
+    ///
+    /// Returns an error, without changing anything, if an unreachable page
+    /// is pinned.
+    pub fn rebuild_free_list(&mut self, reachable: &[u64]) -> DBResult<()> {
+        let reachable: HashSet<u64> = reachable.iter().copied().collect();
+        let free: Vec<u64> = (1..=self.num_pages)
+            .filter(|page_id| !reachable.contains(page_id))
+            .collect();
+        if let Some(&page_id) = free.iter().find(|page_id| {
+            self.buffer_pool
+                .get(page_id)
+                .is_some_and(|page| page.is_pinned())
+        }) {
+            return Err(DBError::from(StorageError::DeallocatePinnedPage(page_id)));
+        }
+
+        for &page_id in &free {
+            self.remove_from_pool(page_id);
+        }
+        self.free_pages = free;
+        Ok(())
+    }
+
+    /// Returns an error if `page_id` is not the id of a data page in the
+    /// file: page 0 is the metadata page, and pages past `num_pages` have
+    /// not been allocated.
+    fn check_page_id(&self, page_id: u64) -> DBResult<()> {
+        if page_id == 0 || page_id > self.num_pages {
+            return Err(DBError::from(StorageError::UnknownPage(page_id)));
+        }
+        Ok(())
+    }
+
+    /// Reads the pages with the given `page_ids` into the buffer pool ahead
+    /// of time so that later reads of them are hits, and returns how many of
+    /// them are resident afterwards. Pages already in the pool count as
+    /// loaded.
+    ///
+    /// Other unpinned pages may be evicted to make room, but pages loaded by
+    /// this call are not evicted to make room for later ones. Once the pool
+    /// is full of pinned or prewarmed pages, the remaining ids which are not
+    /// already in the pool are skipped rather than returning an error.
+    pub fn prewarm(&mut self, page_ids: &[u64]) -> DBResult<usize> {
+        // Pages are pinned while prewarming so they are not evicted to make
+        // room for each other, then unpinned at the end
+        let mut pinned = Vec::new();
+        let mut full = false;
+        let mut result = Ok(());
+        for &page_id in page_ids {
+            if pinned.contains(&page_id) || (full && !self.buffer_pool.contains_key(&page_id)) {
+                continue;
+            }
+            match self.pin_page(page_id) {
+                Ok(()) => pinned.push(page_id),
+                Err(DBError::StorageError(StorageError::BufferPoolFull(_))) => full = true,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        for &page_id in &pinned {
+            self.unpin_page(page_id);
+        }
+        result.map(|_| pinned.len())
+    }
+
+    /// Pins a page in memory; a page can only be removed from the buffer
+    /// pool if no threads have pinned it.
+    pub fn pin_page(&mut self, page_id: u64) -> DBResult<()> {
+        self.check_page_id(page_id)?;
+        if let Some(page) = self.buffer_pool.get_mut(&page_id) {
+            page.pin_count += 1;
+        } else {
+            // Load the page into memory
+            self.load_page(page_id)?;
+            if let Some(page) = self.buffer_pool.get_mut(&page_id) {
+                page.pin_count += 1;
+            } else {
+                return Err(DBError::from(StorageError::UnknownPage(page_id)));
+            }
+        }
+
+        self.record(AccessEvent::Pin(page_id));
+        Ok(())
+    }
+
+    /// Returns `true` if the page with the given `page_id` is in the buffer
+    /// pool, so that reading it will not read from disk.
+    pub fn is_resident(&self, page_id: u64) -> bool {
+        self.buffer_pool.contains_key(&page_id)
+    }
+
+    /// Returns the ids of all pages in the buffer pool, in increasing order.
+    pub fn resident_page_ids(&self) -> Vec<u64> {
+        let mut page_ids: Vec<u64> = self.buffer_pool.keys().copied().collect();
+        page_ids.sort_unstable();
+        page_ids
+    }
+
+    /// Marks the page with the given `page_id` as resident, loading it into
+    /// the buffer pool if necessary, or unmarks it. A resident page is never
+    /// evicted, however many times it is pinned and unpinned.
+    ///
+    /// Resident pages do not count towards `max_pages_in_pool`, so marking
+    /// pages as resident never leaves the pool without room for other pages.
+    /// At most `max_pages_in_pool` pages can be marked as resident at once,
+    /// and a page which leaves the pool, such as by being deallocated, is no
+    /// longer marked.
+    pub fn set_resident(&mut self, page_id: u64, resident: bool) -> DBResult<()> {
+        self.check_page_id(page_id)?;
+        if resident {
+            if !self.resident_pages.contains(&page_id)
+                && self.resident_pages.len() >= self.max_pages_in_pool
+            {
+                return Err(DBError::from(StorageError::InvalidArgument(format!(
+                    "cannot mark more than {} pages as resident.",
+                    self.max_pages_in_pool
+                ))));
+            }
+            self.load_page(page_id)?;
+            if self.resident_pages.insert(page_id) {
+                self.pool_bytes -= self.buffer_pool[&page_id].data.len();
+            }
+        } else if self.resident_pages.remove(&page_id) {
+            self.pool_bytes += self.buffer_pool[&page_id].data.len();
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the page with the given `page_id` is marked as
+    /// resident by `set_resident`.
+    pub fn is_marked_resident(&self, page_id: u64) -> bool {
+        self.resident_pages.contains(&page_id)
+    }
+
+    /// Pins each page in `page_ids` as in `pin_page`, loading them into the
+    /// buffer pool as needed. Pages which appear more than once are pinned
+    /// once for each time they appear.
+    ///
+    /// Either every page is pinned or none are: if any page cannot be pinned,
+    /// such as when the pool is full, the pages pinned so far are unpinned
+    /// before the error is returned.
+    pub fn pin_pages(&mut self, page_ids: &[u64]) -> DBResult<()> {
+        for (i, &page_id) in page_ids.iter().enumerate() {
+            if let Err(e) = self.pin_page(page_id) {
+                self.unpin_pages(&page_ids[..i]);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpins each page in `page_ids` as in `unpin_page`, such as after
+    /// pinning them with `pin_pages`. Pages not in the buffer pool are
+    /// skipped.
+    pub fn unpin_pages(&mut self, page_ids: &[u64]) {
+        for &page_id in page_ids {
+            self.unpin_page(page_id);
+        }
+    }
+
+    /// Unpins a page in the buffer pool and returns the number of pins the
+    /// page has after unpinning. If the page is not present in the buffer
+    /// pool, the function does nothing and returns `None`.
+    pub fn unpin_page(&mut self, page_id: u64) -> Option<u16> {
+        let page = self.buffer_pool.get_mut(&page_id)?;
+        if page.pin_count > 0 {
+            page.pin_count -= 1;
+        }
+        let pin_count = page.pin_count;
+        self.record(AccessEvent::Unpin(page_id));
+        Some(pin_count)
+    }
+
+    /// Flushes a specific page to disk if it is dirty.
+    pub fn flush_page(&mut self, page_id: u64) -> DBResult<()> {
+        if let Some(page) = self.buffer_pool.get_mut(&page_id) {
+            if page.dirty {
+                let range = match (&self.frame, &self.page_source) {
+                    (None, None) => page.dirty_range.clone(),
+                    _ => 0..self.page_size,
+                };
+                let offset = page_offset(page_id, self.page_size)? + range.start as u64;
+                let bytes = match &self.frame {
+                    Some(reserved) if page_id != 0 => {
+                        Cow::Owned(page_frame::encode_page(&page.data, reserved))
+                    }
+                    _ => Cow::Borrowed(&page.data[range]),
+                };
+                let written = Cow::Borrowed(&bytes[..]);
+                // Tests can corrupt writes to check that verification works
+                #[cfg(test)]
+                let written = match self.corrupt_writes {
+                    true => Cow::Owned(bytes.iter().map(|byte| !byte).collect()),
+                    false => written,
+                };
+                if let Some(source) = &self.page_source {
+                    source.store(page_id, &written)?;
+                } else {
+                    trace_timed!(
+                        page_id,
+                        "flushed page to disk",
+                        retry_io(self.io_retries, || self.file.write_at(offset, &written))
+                    )
+                    .map_err(page_io(page_id, offset))?;
+                    if self.verify_writes {
+                        verify_write(&mut self.file, page_id, offset, &bytes, &self.frame)?;
+                    }
+                }
+                page.dirty = false;
+                page.dirty_range = 0..0;
+                self.stats.flushes += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes all pages in the buffer pool to disk if they are dirty, and
+    /// writes the file's metadata page.
+    /// This should be used with caution, especially when writing concurrently,
+    /// because it may disrupt ACID guarantees.
+    pub fn flush_all_pages(&mut self) -> DBResult<()> {
+        self.flush_all_pages_with_progress(|_, _| {})
+    }
 
-        for page_id in page_ids {
+    /// Flushes all pages like `flush_all_pages`, calling `progress` with the
+    /// number of dirty pages flushed so far and the total number of dirty
+    /// pages after each one is written, so that long flushes can report
+    /// their progress. If no pages are dirty, `progress` is called once with
+    /// `(0, 0)`. Pages are flushed in order of their ids.
+    pub fn flush_all_pages_with_progress(
+        &mut self,
+        mut progress: impl FnMut(usize, usize),
+    ) -> DBResult<()> {
+        let mut page_ids: Vec<u64> = self
+            .buffer_pool
+            .iter()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&page_id, _)| page_id)
+            .collect();
+        page_ids.sort_unstable();
+
+        if page_ids.is_empty() {
+            progress(0, 0);
+        }
+        for (i, &page_id) in page_ids.iter().enumerate() {
             self.flush_page(page_id)?;
+            progress(i + 1, page_ids.len());
+        }
+
+        self.write_metadata()?;
+        self.file.sync()?;
+        self.stats.syncs += 1;
+        Ok(())
+    }
+
+    /// Writes the page count and free list to the metadata page, directly to
+    /// disk. If the free list does not fit in the page, only the pages which
+    /// fit are recorded; the rest are no longer reused after reopening.
+    ///
+    /// Nothing is written if pages are too small to hold the metadata
+    /// header, in which case the page count is recomputed from the file
+    /// length when the file is reopened.
+    fn write_metadata(&mut self) -> DBResult<()> {
+        if self.page_size < METADATA_HEADER_SIZE {
+            return Ok(());
+        }
+
+        let capacity = (self.page_size - METADATA_HEADER_SIZE) / 8;
+        let free_pages = &self.free_pages[..self.free_pages.len().min(capacity)];
+
+        let mut data = vec![0; self.page_size];
+        data[..7].copy_from_slice(&METADATA_MAGIC);
+        data[7] = FORMAT_VERSION;
+        data[8..16].copy_from_slice(&self.num_pages.to_le_bytes());
+        data[16..24].copy_from_slice(&(free_pages.len() as u64).to_le_bytes());
+        for (i, page_id) in free_pages.iter().enumerate() {
+            let pos = METADATA_HEADER_SIZE + i * 8;
+            data[pos..pos + 8].copy_from_slice(&page_id.to_le_bytes());
         }
 
-        self.file.sync_all()?;
+        self.file.write_at(0, &data).map_err(page_io(0, 0))?;
         Ok(())
     }
 
     /// Evicts a page from the buffer pool. This can only be done if there
     /// is some page in the pool with 0 pins.
     fn evict_page(&mut self) -> DBResult<()> {
-        // Find an unpinned page to evict
-        if let Some((&page_id, page)) = self
-            .buffer_pool
-            .iter()
-            .find(|(_, page)| page.pin_count == 0)
-        {
+        let victim = match (&self.replacer, self.eviction_policy) {
+            (Some(_), _) => self.ask_replacer(),
+            (None, EvictionPolicy::Clock) => self.sweep_clock(),
+            (None, policy) => {
+                // Find an unpinned page to evict, breaking ties by lowest id
+                // so that evictions are deterministic
+                let unpinned = self.buffer_pool.iter().filter(|(page_id, page)| {
+                    !page.is_pinned() && !self.resident_pages.contains(page_id)
+                });
+                let dirty_rank = |page: &Page| self.prefer_clean && page.dirty;
+                let victim = match policy {
+                    EvictionPolicy::Lfu { .. } => unpinned
+                        .min_by_key(|(&page_id, page)| (dirty_rank(page), page.uses, page_id)),
+                    EvictionPolicy::Lru => unpinned
+                        .min_by_key(|(&page_id, page)| (dirty_rank(page), page.last_use, page_id)),
+                    _ => unpinned.min_by_key(|(&page_id, page)| (dirty_rank(page), page_id)),
+                };
+                victim.map(|(&page_id, _)| page_id)
+            }
+        };
+        if let Some(page_id) = victim {
+            let dirty = self.buffer_pool[&page_id].dirty;
+            trace_event!(debug, page_id, dirty, "evicting page");
+
             // Flush if dirty
-            if page.dirty {
+            if dirty {
                 self.flush_page(page_id)?;
             }
 
             // Remove from buffer pool
-            self.buffer_pool.remove(&page_id);
+            self.remove_from_pool(page_id);
+            self.stats.evictions += 1;
         } else {
             return Err(DBError::from(StorageError::BufferPoolFull(self.num_pages)));
         }
@@ -265,14 +1493,186 @@ impl FileManager {
         Ok(())
     }
 
+    /// Returns the ids of the pages which can be evicted, in ascending order.
+    /// If clean pages are preferred, only clean pages are included unless
+    /// there are none.
+    fn eviction_candidates(&self) -> Vec<u64> {
+        let evictable = |page_id: &u64, page: &Page| {
+            !page.is_pinned() && !self.resident_pages.contains(page_id)
+        };
+        let only_clean = self.prefer_clean
+            && self
+                .buffer_pool
+                .iter()
+                .any(|(page_id, page)| evictable(page_id, page) && !page.dirty);
+        let mut candidates: Vec<u64> = self
+            .buffer_pool
+            .iter()
+            .filter(|(page_id, page)| evictable(page_id, page) && !(only_clean && page.dirty))
+            .map(|(&page_id, _)| page_id)
+            .collect();
+        candidates.sort_unstable();
+        candidates
+    }
+
+    /// Asks the replacer set by `with_replacer` for a page to evict among the
+    /// eviction candidates. Returns `None` if it does not choose one of them.
+    fn ask_replacer(&mut self) -> Option<u64> {
+        let candidates = self.eviction_candidates();
+
+        let victim = self.replacer.as_mut()?.evict(&candidates)?;
+        candidates.binary_search(&victim).is_ok().then_some(victim)
+    }
+
+    /// Removes a page from the buffer pool, telling the replacer if there is
+    /// one, and unmarks it as resident.
+    fn remove_from_pool(&mut self, page_id: u64) -> Option<Page> {
+        if let Some(replacer) = &mut self.replacer {
+            replacer.remove(page_id);
+        }
+        let page = self.buffer_pool.remove(&page_id)?;
+        if !self.resident_pages.remove(&page_id) {
+            self.pool_bytes -= page.data.len();
+        }
+        Some(page)
+    }
+
+    /// Sweeps the clock hand over the eviction candidates for
+    /// `EvictionPolicy::Clock`, clearing the reference bit of each page it
+    /// passes, and returns the first page whose bit was already clear. The
+    /// hand is left just after that page. Returns `None` if no page can be
+    /// evicted.
+    fn sweep_clock(&mut self) -> Option<u64> {
+        let candidates = self.eviction_candidates();
+
+        // Every bit is clear after one rotation, so two always find a page
+        let start = candidates.partition_point(|&page_id| page_id < self.clock_hand);
+        let rotation = candidates[start..].iter().chain(&candidates[..start]);
+        for &page_id in rotation.clone().chain(rotation) {
+            let page = self.buffer_pool.get_mut(&page_id).unwrap();
+            if !mem::take(&mut page.ref_bit) {
+                self.clock_hand = page_id + 1;
+                return Some(page_id);
+            }
+        }
+        None
+    }
+
+    /// Returns the number of pages in the buffer pool which count towards
+    /// `max_pages_in_pool`, which excludes resident pages.
+    fn counted_pages(&self) -> usize {
+        // Every page in the pool is `page_size` bytes
+        self.pool_bytes / self.page_size
+    }
+
+    /// Returns `true` if another page cannot be added to the buffer pool
+    /// without evicting one first, according to `max_pool_bytes` if it is set
+    /// and `max_pages_in_pool` otherwise.
+    fn pool_is_full(&self) -> bool {
+        match self.max_pool_bytes {
+            Some(max_bytes) => self.pool_bytes + self.page_size > max_bytes,
+            None => self.counted_pages() >= self.max_pages_in_pool,
+        }
+    }
+
+    /// Counts a use of a page in the buffer pool, aging the use counts of all
+    /// pages if the eviction policy calls for it.
+    fn count_use(&mut self, page_id: u64) {
+        if let Some(replacer) = &mut self.replacer {
+            replacer.record_access(page_id);
+        }
+        self.use_clock += 1;
+        if let Some(page) = self.buffer_pool.get_mut(&page_id) {
+            page.uses += 1;
+            // The first use is the one which loaded the page, so it does
+            // not earn a second chance
+            page.ref_bit = page.last_use != 0;
+            page.last_use = self.use_clock;
+        }
+
+        if let EvictionPolicy::Lfu { aging_interval } = self.eviction_policy {
+            self.uses_since_aging += 1;
+            if self.uses_since_aging >= aging_interval {
+                for page in self.buffer_pool.values_mut() {
+                    page.uses /= 2;
+                }
+                self.uses_since_aging = 0;
+            }
+        }
+    }
+
+    /// Records `event` in the access trace, if calls are being recorded.
+    fn record(&mut self, event: AccessEvent) {
+        if let Some(trace) = &mut self.access_trace {
+            trace.push(event);
+        }
+    }
+
+    /// Returns the id, pin count, and dirtiness of every page in the buffer
+    /// pool, sorted by id, for comparing the states of two pools in tests.
+    #[cfg(test)]
+    pub(crate) fn pool_state(&self) -> Vec<(u64, u16, bool)> {
+        let mut state: Vec<_> = self
+            .buffer_pool
+            .iter()
+            .map(|(&page_id, page)| (page_id, page.pin_count, page.dirty))
+            .collect();
+        state.sort_unstable();
+        state
+    }
+
+    /// Overwrites the byte at `offset` in the stored bytes of the page with
+    /// the given `page_id`, as they are on disk including any frame, with
+    /// `value`. The page is dropped from the buffer pool without being
+    /// flushed, and so is no longer resident, so that the next read sees the
+    /// corrupt byte.
+    #[cfg(test)]
+    pub(crate) fn corrupt_page_byte(
+        &mut self,
+        page_id: u64,
+        offset: usize,
+        value: u8,
+    ) -> DBResult<()> {
+        assert!(offset < self.page_size, "offset is outside of the page");
+        self.remove_from_pool(page_id);
+        let offset = page_offset(page_id, self.page_size)? + offset as u64;
+        self.file
+            .write_at(offset, &[value])
+            .map_err(page_io(page_id, offset))?;
+        Ok(())
+    }
+
+    /// Drops this `FileManager` without flushing anything, as if the process
+    /// had crashed: pages which were not flushed, and changes to the page
+    /// count and free list since the metadata page was last written, are
+    /// lost.
+    #[cfg(test)]
+    pub(crate) fn simulate_crash(mut self) {
+        self.flush_on_drop = false;
+    }
+
+    /// Returns an error if this `FileManager` is in memory, and so has no
+    /// file to `operation`.
+    fn check_not_in_memory(&self, operation: &str) -> DBResult<()> {
+        if self.file.is_memory() {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "cannot {} the file of an in-memory FileManager.",
+                operation
+            ))));
+        }
+        Ok(())
+    }
+
     /// Closes and deletes a database file. If any page is still pinned, the
     /// function will do nothing and return an error. An IO error may also be
     /// returned. This function does NOT drop the `FileManager`; this is
     /// because if some pages are still pinned, the `FileManager` should still
     /// continue to exist. Dropping can be handled by the caller scope.
     pub fn delete_file(&mut self) -> DBResult<()> {
+        self.check_not_in_memory("delete")?;
+
         // Check for pinned pages
-        if self.buffer_pool.values().any(|page| page.pin_count > 0) {
+        if self.buffer_pool.values().any(|page| page.is_pinned()) {
             return Err(DBError::from(StorageError::DeleteFileWhilePagesPinned));
         }
 
@@ -290,36 +1690,265 @@ impl FileManager {
     /// the new path. No pages can be currently pinned; if any page is
     /// pinned, the function will return an error.
     pub fn rename_file(mut self, new_path: &str) -> DBResult<FileManager> {
+        self.check_not_in_memory("rename")?;
+
         // Check for pinned pages
-        if self.buffer_pool.values().any(|page| page.pin_count > 0) {
+        if self.buffer_pool.values().any(|page| page.is_pinned()) {
             return Err(DBError::from(StorageError::DeleteFileWhilePagesPinned));
         }
 
         self.flush_all_pages()?;
+        // Nothing is left to flush, and a pooled file could not be reopened
+        // at the old path
+        let flush_on_drop = mem::replace(&mut self.flush_on_drop, false);
 
         rename(&self.file_path, new_path)?;
 
-        let new_fm = Self::new(new_path, self.page_size, self.max_pages_in_pool)?;
+        let mut new_fm = Self::open_with(
+            new_path,
+            self.page_size,
+            self.max_pages_in_pool,
+            self.open_mode,
+            self.file.is_direct(),
+        )?;
+        new_fm.layout = self.layout.clone();
+        new_fm.frame = self.frame.clone();
+        new_fm.eviction_policy = self.eviction_policy;
+        // The new `FileManager` starts with an empty buffer pool
+        new_fm.replacer = self.replacer.take().map(|mut replacer| {
+            self.buffer_pool
+                .keys()
+                .for_each(|&page_id| replacer.remove(page_id));
+            replacer
+        });
+        new_fm.prefer_clean = self.prefer_clean;
+        new_fm.eviction_timeout = self.eviction_timeout;
+        new_fm.group_commit = self.group_commit;
+        new_fm.punch_holes = self.punch_holes;
+        new_fm.track_dirty_regions = self.track_dirty_regions;
+        new_fm.access_trace = self.access_trace.take();
+        new_fm.read_ahead = self.read_ahead;
+        new_fm.max_pool_bytes = self.max_pool_bytes;
+        new_fm.flush_on_drop = flush_on_drop;
+        new_fm.io_retries = self.io_retries;
+        new_fm.verify_writes = self.verify_writes;
+        new_fm.page_source = self.page_source.take();
+        if let Backend::Pooled(pooled) = &self.file {
+            new_fm = new_fm.with_fd_pool(pooled.pool());
+        }
+        for &page_id in &self.resident_pages {
+            new_fm.set_resident(page_id, true)?;
+        }
         Ok(new_fm)
     }
 }
 
+/// Logs that `item` is being skipped during recovery because of `error`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn log_corruption(item: CorruptItem, error: &DBError) {
+    trace_event!(warn, item = ?item, error = %error, "skipping corrupt item");
+}
+
+/// Checks the arguments shared by all of `FileManager`'s constructors.
+fn check_pool_args(page_size: usize, max_pages_in_pool: usize) -> DBResult<()> {
+    if page_size < 1 {
+        return Err(DBError::from(StorageError::InvalidArgument(
+            "invalid FileManager page size: must be at least 1 byte.".to_string(),
+        )));
+    }
+
+    if max_pages_in_pool < 1 {
+        return Err(DBError::from(StorageError::InvalidArgument(
+            "invalid FileManager `max_pages_in_pool`: must be at least 1.".to_string(),
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the range from the first to the last byte which differs between
+/// `old` and `new`, which must have the same length. The range is empty if
+/// they are equal.
+fn changed_range(old: &[u8], new: &[u8]) -> Range<usize> {
+    let differs = |i: &usize| old[*i] != new[*i];
+    match (0..old.len()).find(differs) {
+        Some(start) => start..(0..old.len()).rfind(differs).unwrap() + 1,
+        None => 0..0,
+    }
+}
+
+/// Returns the smallest range containing both `a` and `b`, ignoring empty
+/// ranges.
+fn union(a: &Range<usize>, b: &Range<usize>) -> Range<usize> {
+    if a.is_empty() {
+        b.clone()
+    } else if b.is_empty() {
+        a.clone()
+    } else {
+        a.start.min(b.start)..a.end.max(b.end)
+    }
+}
+
+/// Returns the lowest range of `count` consecutive page ids which are all in
+/// `free_pages`, if there is one.
+fn free_run(free_pages: &[u64], count: u64) -> Option<Range<u64>> {
+    if count == 0 {
+        return None;
+    }
+    let mut free = free_pages.to_vec();
+    free.sort_unstable();
+    free.dedup();
+
+    let mut start = 0;
+    for (i, &page_id) in free.iter().enumerate() {
+        if i > 0 && page_id != free[i - 1] + 1 {
+            start = i;
+        }
+        if (i - start + 1) as u64 == count {
+            return Some(free[start]..page_id + 1);
+        }
+    }
+    None
+}
+
+/// Returns the offset in the file of the page with the given `page_id`, or an
+/// error if it does not fit in a `u64`.
+fn page_offset(page_id: u64, page_size: usize) -> DBResult<u64> {
+    page_id
+        .checked_mul(page_size as u64)
+        .ok_or_else(|| DBError::from(StorageError::PageOffsetOverflow(page_id)))
+}
+
+/// Returns a function which adds the page and offset being accessed to an
+/// I/O error.
+fn page_io(page_id: u64, offset: u64) -> impl FnOnce(io::Error) -> DBError {
+    move |source| {
+        DBError::from(StorageError::PageIo {
+            page_id,
+            offset,
+            source,
+        })
+    }
+}
+
+/// Reads back the `expected` bytes just written at `offset` for the page with
+/// the given `page_id`, and checks that they match and, if the page is
+/// framed, that its checksum verifies; see
+/// `FileManager::with_write_verification`.
+fn verify_write(
+    file: &mut Backend,
+    page_id: u64,
+    offset: u64,
+    expected: &[u8],
+    frame: &Option<Range<usize>>,
+) -> DBResult<()> {
+    let mut written = vec![0; expected.len()];
+    file.read_at(offset, &mut written)
+        .map_err(page_io(page_id, offset))?;
+    let failed = || DBError::from(StorageError::WriteVerificationFailed(page_id));
+    if written != expected {
+        return Err(failed());
+    }
+    if let (Some(reserved), true) = (frame, page_id != 0) {
+        page_frame::decode_page(&written, reserved, page_id).map_err(|_| failed())?;
+    }
+    Ok(())
+}
+
+/// Runs `op` until it succeeds, retrying errors which may be transient as
+/// configured by `FileManager::with_io_retries`.
+fn retry_io<T>(
+    retries: Option<(u32, Duration)>,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let (retries, backoff) = retries.unwrap_or_default();
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                std::thread::sleep(backoff.saturating_mul(2u32.saturating_pow(attempt)));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns `true` if `error` may not happen again if the operation which
+/// caused it is retried.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Reads the page count and free list from the metadata page at the start
+/// of `file`. Returns `None` if the file does not start with a metadata page,
+/// such as a new file or one written before metadata pages were added, or an
+/// error if the file's format version is newer than `FORMAT_VERSION`.
+fn read_metadata(file: &mut Backend, page_size: usize) -> DBResult<Option<(u64, Vec<u64>)>> {
+    if page_size < METADATA_HEADER_SIZE || file.len()? < page_size as u64 {
+        return Ok(None);
+    }
+
+    let mut data = vec![0; page_size];
+    file.read_at(0, &mut data).map_err(page_io(0, 0))?;
+    if data[..7] != METADATA_MAGIC {
+        return Ok(None);
+    }
+    if data[7] > FORMAT_VERSION {
+        return Err(DBError::from(StorageError::UnsupportedFormatVersion(
+            data[7],
+        )));
+    }
+
+    let read_u64 = |pos: usize| u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap());
+    let num_pages = read_u64(8);
+    let num_free = read_u64(16) as usize;
+    if num_free > (page_size - METADATA_HEADER_SIZE) / 8 {
+        return Err(DBError::from(StorageError::DeserializationError(format!(
+            "metadata page lists {} free pages, which cannot fit in a {} byte page.",
+            num_free, page_size
+        ))));
+    }
+
+    let free_pages = (0..num_free)
+        .map(|i| read_u64(METADATA_HEADER_SIZE + i * 8))
+        .collect();
+    Ok(Some((num_pages, free_pages)))
+}
+
 impl Drop for FileManager {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
     fn drop(&mut self) {
+        if !self.flush_on_drop {
+            return;
+        }
+
         // Attempt to flush all pages when FileManager is dropped
         if let Err(e) = self.flush_all_pages() {
-            eprintln!("Error flushing pages during shutdown: {}", e);
+            trace_event!(
+                error,
+                file_path = %self.file_path,
+                error = %e,
+                "error flushing pages during shutdown"
+            );
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::fs::OpenOptions;
+
     use super::*;
 
     #[test]
     fn test_file_manager_new_valid() -> DBResult<()> {
-        let _ = FileManager::new("fm_test.db", 4092, 100)?;
+        let path = "fm_test.db";
+        let _ = FileManager::new(path, 4092, 100)?;
+        remove_file(path)?;
 
         Ok(())
     }
@@ -340,4 +1969,1384 @@ mod test {
         // ^ It is not necessary to test negative values because
         // they are not `usize`s in Rust
     }
+
+    #[test]
+    fn test_in_memory_read_write_evict() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let pages: Vec<u64> = (0..3)
+            .map(|_| fm.allocate_page())
+            .collect::<DBResult<_>>()?;
+        for &page_id in &pages {
+            fm.write_page_to_pool(page_id, &[page_id as u8; 64])?;
+        }
+
+        // Flushing clears the dirty flags
+        fm.flush_all_pages()?;
+        assert_eq!(fm.stats().flushes, 3);
+        assert!(fm.buffer_pool.values().all(|page| !page.dirty));
+
+        // Evicted pages are read back from memory
+        fm.set_max_pages_in_pool(1)?;
+        for &page_id in &pages {
+            assert_eq!(fm.read_page(page_id)?, &[page_id as u8; 64][..]);
+        }
+        assert!(fm.stats().evictions >= 3);
+
+        // Unwritten pages cannot be read, just like past the end of a file
+        assert!(fm.read_page(10).is_err());
+
+        // Freed pages are reused
+        fm.deallocate_page(pages[1])?;
+        assert_eq!(fm.allocate_page()?, pages[1]);
+        assert_eq!(fm.read_page(pages[1])?, &[0; 64][..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_has_no_file() -> DBResult<()> {
+        assert!(FileManager::in_memory(0, 4).is_err());
+        assert!(FileManager::in_memory(64, 0).is_err());
+
+        let mut fm = FileManager::in_memory(64, 4)?;
+        assert!(fm.delete_file().is_err());
+        assert!(fm.rename_file("fm_test_in_memory.db").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_and_write_page() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let page_id = fm.allocate_page()?;
+        fm.flush_page(page_id)?;
+
+        // The swap succeeds while the page holds the expected bytes
+        assert!(fm.compare_and_write_page(page_id, &[0; 64], &[1; 64])?);
+        assert_eq!(fm.read_page(page_id)?, [1; 64]);
+
+        // After another write, the stale expected bytes no longer match
+        fm.write_page_to_pool(page_id, &[2; 64])?;
+        fm.flush_page(page_id)?;
+        assert!(!fm.compare_and_write_page(page_id, &[1; 64], &[3; 64])?);
+        assert_eq!(fm.read_page(page_id)?, [2; 64]);
+        assert!(fm.pool_state().contains(&(page_id, 0, false)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_pages() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let first = fm.allocate_page()?;
+
+        let pages = fm.allocate_pages(1000)?;
+        assert_eq!(pages, first + 1..first + 1001);
+        assert_eq!(fm.num_pages(), 1001);
+        assert!(fm.pool_state().len() <= 2);
+
+        // The new pages can be read and written, and later pages follow them
+        assert_eq!(fm.read_page(pages.end - 1)?, [0; 64]);
+        fm.write_page_to_pool(pages.start, &[5; 64])?;
+        fm.flush_page(pages.start)?;
+        assert_eq!(fm.read_page(pages.start)?, [5; 64]);
+        assert_eq!(fm.allocate_pages(1)?, pages.end..pages.end + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lfu_keeps_hot_page() -> DBResult<()> {
+        let policy = EvictionPolicy::Lfu {
+            aging_interval: 1000,
+        };
+        let mut fm = FileManager::in_memory(64, 3)?.with_eviction_policy(policy);
+        fm.allocate_page()?;
+        fm.allocate_page()?;
+        fm.allocate_pages(2)?;
+        fm.flush_all_pages()?;
+
+        for _ in 0..5 {
+            fm.read_page(1)?;
+        }
+        fm.read_page(3)?;
+
+        // Page 1 has the lowest id but the most uses, so a one-hit page goes
+        fm.read_page(4)?;
+        let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+        assert_eq!(resident, [1, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 3)?.with_eviction_policy(EvictionPolicy::Lru);
+        let pages = fm.allocate_pages(6)?;
+        fm.flush_all_pages()?;
+        fm.write_page_to_pool(pages.start, &[1; 64])?;
+        fm.read_page(2)?;
+        fm.read_page(3)?;
+
+        // Page 1 was touched first but written again, so page 2 is oldest
+        fm.write_page_to_pool(1, &[2; 64])?;
+        fm.read_page(4)?;
+        let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+        assert_eq!(resident, [1, 3, 4]);
+
+        // Scanning on evicts pages in the order they were touched
+        fm.read_page(5)?;
+        fm.read_page(6)?;
+        let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+        assert_eq!(resident, [4, 5, 6]);
+        assert_eq!(fm.read_page(1)?, [2; 64]);
+
+        // Pinned pages are skipped, even when they are the oldest
+        fm.pin_page(5)?;
+        fm.pin_page(6)?;
+        fm.pin_page(1)?;
+        assert!(matches!(
+            fm.read_page(2),
+            Err(DBError::StorageError(StorageError::BufferPoolFull(_)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clock_gives_second_chance() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 3)?.with_eviction_policy(EvictionPolicy::Clock);
+        let pages = fm.allocate_pages(6)?;
+        fm.flush_all_pages()?;
+        fm.pool_state().iter().for_each(|p| assert!(p.0 > 3));
+        for page_id in 1..=3 {
+            fm.read_page(page_id)?;
+        }
+
+        // Page 1 is read twice, so the hand clears its bit and moves on to
+        // page 2, which was only read once
+        fm.read_page(1)?;
+        fm.read_page(4)?;
+        let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+        assert_eq!(resident, [1, 3, 4]);
+
+        // The hand carries on from page 3, so pages read after page 1 go
+        // before the hand comes back around to it
+        fm.read_page(5)?;
+        fm.read_page(6)?;
+        let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+        assert_eq!(resident, [1, 5, 6]);
+
+        // A full rotation over pinned pages finds nothing to evict
+        fm.pin_pages(&[1, 5, 6])?;
+        assert!(matches!(
+            fm.read_page(pages.start + 1),
+            Err(DBError::StorageError(StorageError::BufferPoolFull(_)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replacer() -> DBResult<()> {
+        use crate::storage::{FifoReplacer, LruReplacer};
+
+        let replacers: [(Box<dyn Replacer>, [u64; 3]); 2] = [
+            (Box::new(FifoReplacer::new()), [2, 3, 4]),
+            (Box::new(LruReplacer::new()), [1, 3, 4]),
+        ];
+        for (replacer, expected) in replacers {
+            let mut fm = FileManager::in_memory(64, 3)?.with_replacer(replacer);
+            fm.allocate_pages(4)?;
+            fm.flush_all_pages()?;
+            for page_id in [1, 2, 3, 1] {
+                fm.read_page(page_id)?;
+            }
+
+            // Page 1 came in first, but was also used last
+            fm.read_page(4)?;
+            let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+            assert_eq!(resident, expected);
+
+            // Pinned pages are never offered to the replacer
+            fm.pin_pages(&expected)?;
+            assert!(matches!(
+                fm.read_page(if expected[0] == 1 { 2 } else { 1 }),
+                Err(DBError::StorageError(StorageError::BufferPoolFull(_)))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lfu_aging() -> DBResult<()> {
+        let policy = EvictionPolicy::Lfu { aging_interval: 8 };
+        let mut fm = FileManager::in_memory(64, 2)?.with_eviction_policy(policy);
+        fm.allocate_page()?;
+        fm.allocate_pages(2)?;
+        fm.flush_all_pages()?;
+
+        // Page 1 reaches 7 uses, then the eighth use halves it to 3
+        for _ in 0..6 {
+            fm.read_page(1)?;
+        }
+        for _ in 0..5 {
+            fm.read_page(2)?;
+        }
+
+        // Page 2 has fewer uses in total, but more since aging
+        fm.read_page(3)?;
+        let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+        assert_eq!(resident, [2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resident_page_is_not_evicted() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 2)?;
+        fm.allocate_page()?;
+        fm.allocate_pages(3)?;
+        fm.flush_all_pages()?;
+        fm.set_resident(1, true)?;
+        assert!(fm.is_marked_resident(1));
+
+        // The resident page does not take up room, and is skipped even
+        // though it has the lowest id
+        fm.read_page(2)?;
+        fm.read_page(3)?;
+        fm.read_page(4)?;
+        let resident: Vec<u64> = fm.pool_state().iter().map(|p| p.0).collect();
+        assert_eq!(resident, [1, 3, 4]);
+
+        // Pinning every other page still leaves room to load pages
+        fm.pin_page(3)?;
+        fm.read_page(2)?;
+        fm.unpin_page(3);
+
+        // Once unmarked, the page can be evicted again
+        fm.set_resident(1, false)?;
+        assert!(!fm.is_marked_resident(1));
+        fm.read_page(4)?;
+        assert!(!fm.pool_state().iter().any(|p| p.0 == 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resident_marks_follow_the_pool() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 2)?;
+        fm.allocate_pages(4)?;
+        fm.flush_all_pages()?;
+
+        // No more pages than fit in the pool can be resident
+        fm.set_resident(1, true)?;
+        fm.set_resident(2, true)?;
+        fm.set_resident(2, true)?;
+        assert!(matches!(
+            fm.set_resident(3, true),
+            Err(DBError::StorageError(StorageError::InvalidArgument(_)))
+        ));
+
+        // Pages which leave the pool are no longer resident, and do not
+        // throw off the count of pages in the pool
+        fm.corrupt_page_byte(1, 0, 0)?;
+        fm.deallocate_page(2)?;
+        assert!(!fm.is_marked_resident(1));
+        assert!(!fm.is_marked_resident(2));
+        assert_eq!(fm.counted_pages(), 0);
+        fm.read_page(3)?;
+        fm.read_page(4)?;
+        assert_eq!(fm.resident_page_ids(), [3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dirty_region_tracking() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?.with_dirty_region_tracking();
+        let page_id = fm.allocate_page()?;
+        fm.flush_page(page_id)?;
+
+        // Overwrite the page on disk so that rewritten bytes can be told apart
+        let page = page_id as usize * 64..(page_id as usize + 1) * 64;
+        let on_disk = |fm: &FileManager| match &fm.file {
+            Backend::Memory(data) => data[page.clone()].to_vec(),
+            _ => unreachable!(),
+        };
+        if let Backend::Memory(data) = &mut fm.file {
+            data[page.clone()].fill(0xEE);
+        }
+
+        // Two small, separate writes flush the bytes between them too
+        let mut data = vec![0; 64];
+        data[10] = 1;
+        fm.write_page_to_pool(page_id, &data)?;
+        data[20] = 2;
+        fm.write_page_to_pool(page_id, &data)?;
+        fm.flush_page(page_id)?;
+
+        let mut expected = vec![0xEE; 64];
+        expected[10..21].copy_from_slice(&data[10..21]);
+        assert_eq!(on_disk(&fm), expected);
+
+        // Overwriting every byte flushes the whole page
+        fm.write_page_to_pool(page_id, &[7; 64])?;
+        fm.flush_page(page_id)?;
+        assert_eq!(on_disk(&fm), [7; 64]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_resident() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 2)?;
+        fm.allocate_page()?;
+        fm.allocate_pages(2)?;
+        fm.flush_all_pages()?;
+        assert!(!fm.is_resident(2));
+
+        fm.read_page(2)?;
+        assert!(fm.is_resident(2));
+        assert_eq!(fm.resident_page_ids(), [1, 2]);
+
+        // Reading another page evicts the lowest unpinned page
+        fm.pin_page(1)?;
+        fm.read_page(3)?;
+        assert!(!fm.is_resident(2));
+        assert_eq!(fm.resident_page_ids(), [1, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_offset_overflow() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let page_id = u64::MAX / 2;
+
+        // `read_page` rejects the id as unknown before computing its offset
+        assert!(matches!(
+            fm.load_page(page_id),
+            Err(DBError::StorageError(StorageError::PageOffsetOverflow(id))) if id == page_id
+        ));
+        assert!(fm.resident_page_ids().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_all_pages_with_progress() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 8)?;
+        for _ in 0..5 {
+            fm.allocate_page()?;
+        }
+        fm.flush_page(3)?;
+
+        let mut calls = Vec::new();
+        fm.flush_all_pages_with_progress(|flushed, total| calls.push((flushed, total)))?;
+        assert_eq!(calls, [(1, 4), (2, 4), (3, 4), (4, 4)]);
+        assert!(fm.pool_state().iter().all(|&(_, _, dirty)| !dirty));
+
+        calls.clear();
+        fm.flush_all_pages_with_progress(|flushed, total| calls.push((flushed, total)))?;
+        assert_eq!(calls, [(0, 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_beyond_pool_size() -> DBResult<()> {
+        let path = "fm_test_allocate_beyond_pool_size.db";
+        let mut fm = FileManager::new(path, 64, 2)?;
+        for i in 1..=10 {
+            assert_eq!(fm.allocate_page()?, i);
+            fm.write_page_to_pool(i, &[i as u8; 64])?;
+        }
+        fm.flush_all_pages()?;
+        assert_eq!(fm.num_pages(), 10);
+        // The metadata page comes before the 10 allocated pages
+        assert_eq!(std::fs::metadata(path)?.len(), 11 * 64);
+        drop(fm);
+
+        let mut fm = FileManager::new(path, 64, 2)?;
+        assert_eq!(fm.num_pages(), 10);
+        assert_eq!(fm.read_page(1)?, [1; 64]);
+        assert_eq!(fm.read_page(10)?, [10; 64]);
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prewarm() -> DBResult<()> {
+        let path = "fm_test_prewarm.db";
+        let mut fm = FileManager::new(path, 64, 3)?;
+        let pages: Vec<u64> = (0..3)
+            .map(|_| fm.allocate_page())
+            .collect::<DBResult<_>>()?;
+        fm.flush_all_pages()?;
+        drop(fm);
+
+        let mut fm = FileManager::new(path, 64, 3)?;
+        assert_eq!(fm.prewarm(&pages)?, 3);
+
+        // Every prewarmed page is now a hit
+        let before = fm.stats();
+        for &page_id in &pages {
+            fm.read_page(page_id)?;
+        }
+        let after = fm.stats();
+        assert_eq!(after.hits - before.hits, 3);
+        assert_eq!(after.misses, before.misses);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_and_rebuild_free_list() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        fm.allocate_pages(4)?;
+        fm.free_pages = vec![2, 2, 9, 3, 0];
+
+        let reachable = [1, 3];
+        assert_eq!(
+            fm.validate_free_list(&reachable),
+            [
+                FreeListIssue::Duplicate(2),
+                FreeListIssue::OutOfRange(9),
+                FreeListIssue::Referenced(3),
+                FreeListIssue::OutOfRange(0),
+            ]
+        );
+
+        // Pinned unreachable pages cannot be freed
+        fm.pin_page(4)?;
+        assert!(fm.rebuild_free_list(&reachable).is_err());
+        fm.unpin_page(4);
+
+        fm.rebuild_free_list(&reachable)?;
+        assert!(fm.validate_free_list(&reachable).is_empty());
+        assert!(!fm.is_resident(4));
+        let mut reused = [fm.allocate_page()?, fm.allocate_page()?];
+        reused.sort_unstable();
+        assert_eq!(reused, [2, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_page_to() -> DBResult<()> {
+        /// Accepts at most 3 bytes per write.
+        struct ShortWriter(Vec<u8>);
+
+        impl Write for ShortWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..len]);
+                Ok(len)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let page_id = fm.allocate_page()?;
+        let data: Vec<u8> = (0..fm.usable_page_size() as u8).collect();
+        fm.write_page_to_pool(page_id, &data)?;
+
+        let mut out = ShortWriter(Vec::new());
+        fm.write_page_to(page_id, &mut out)?;
+        assert_eq!(out.0, fm.read_page(page_id)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_ahead_follows_stride() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 8)?.with_read_ahead(3);
+        fm.allocate_pages(20)?;
+
+        // Pages are read ahead once two reads have the same stride
+        for page_id in [2, 5, 8] {
+            fm.read_page(page_id)?;
+        }
+        assert_eq!(fm.stats().misses, 3);
+        for page_id in [11, 14, 17] {
+            assert!(fm.is_resident(page_id));
+            fm.read_page(page_id)?;
+        }
+        assert_eq!(fm.stats().hits, 3);
+        assert_eq!(fm.stats().misses, 3);
+
+        // Reading ahead reaches the last page of the file
+        assert!(fm.is_resident(20));
+        fm.read_page(20)?;
+        assert_eq!(fm.stats().misses, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_ahead_ignores_corrupt_page() -> DBResult<()> {
+        let path = "fm_test_read_ahead_corrupt.db";
+        let len = {
+            let mut fm = FileManager::new(path, 64, 4)?.with_checksums_and_compression()?;
+            let pages = fm.allocate_pages(6)?;
+            let len = fm.read_page(1)?.len();
+            for page_id in pages {
+                fm.write_page_to_pool(page_id, &vec![page_id as u8; len])?;
+            }
+            fm.flush_all_pages()?;
+            len
+        };
+
+        let mut fm = FileManager::new(path, 64, 4)?
+            .with_checksums_and_compression()?
+            .with_read_ahead(2);
+        fm.corrupt_page_byte(5, FRAME_HEADER_SIZE, 0xFF)?;
+
+        // Reading page 3 reads ahead pages 4 and 5, and page 5 is corrupt
+        for page_id in 1..=3 {
+            assert_eq!(fm.read_page(page_id)?, vec![page_id as u8; len]);
+        }
+        assert!(fm.is_resident(4));
+        assert!(!fm.is_resident(5));
+        assert!(matches!(
+            fm.read_page(5),
+            Err(DBError::StorageError(StorageError::ChecksumMismatch(5)))
+        ));
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_ahead_keeps_page_being_read() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 2)?.with_read_ahead(1);
+        let pages = fm.allocate_pages(20)?;
+        for page_id in pages {
+            fm.write_page_to_pool(page_id, &[page_id as u8; 64])?;
+        }
+
+        // A backward stride reads ahead into lower ids, which would evict
+        // the page being read first under `LowestId`
+        for page_id in [10, 9, 8, 7] {
+            assert_eq!(fm.read_page(page_id)?, [page_id as u8; 64]);
+        }
+        assert!(fm.is_resident(6));
+        assert!(fm.pool_state().iter().all(|&(_, pins, _)| pins == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_page_pinned() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 3)?;
+        fm.allocate_pages(10)?;
+        let data = vec![5; fm.usable_page_size()];
+        fm.write_page_to_pool(1, &data)?;
+
+        let pinned = fm.read_page_pinned(1)?;
+        for page_id in (2..=10).chain(2..=10) {
+            fm.read_page(page_id)?;
+        }
+        assert!(fm.stats().evictions > 0);
+        assert!(fm.is_resident(1));
+        assert!(fm.deallocate_page(1).is_err());
+
+        // Writes made while the page is pinned do not change the pinned bytes
+        fm.write_page_to_pool(1, &[6; 64])?;
+        assert_eq!(pinned.page_id(), 1);
+        assert_eq!(&pinned[..], &data[..]);
+
+        drop(pinned);
+        fm.deallocate_page(1)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_pool_pressure() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        fm.allocate_pages(8)?;
+        fm.flush_all_pages()?;
+        let before = fm.pool_pressure();
+        assert_eq!(before.eviction_rate, 0.0);
+
+        // Cycling through twice the pool's capacity evicts on every read
+        for page_id in (1..=8).cycle().take(32) {
+            fm.read_page(page_id)?;
+        }
+        let pressure = fm.pool_pressure();
+        assert_eq!(pressure.occupancy, 4);
+        assert_eq!(pressure.peak_occupancy, 4);
+        assert!(pressure.evictions > before.evictions);
+        assert!(pressure.eviction_rate > 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_read_page() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        fm.allocate_pages(8)?;
+        let data = vec![7; fm.usable_page_size()];
+        fm.write_page_to_pool(3, &data)?;
+        let resident = fm.resident_page_ids();
+        let missing = (1..=8).find(|id| !resident.contains(id)).unwrap();
+
+        assert_eq!(fm.try_read_page(missing), None);
+        assert_eq!(fm.resident_page_ids(), resident);
+        assert_eq!(fm.stats().misses, 0);
+        assert_eq!(fm.try_read_page(3), Some(&data[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_read_ahead_for_random_reads() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 8)?.with_read_ahead(3);
+        fm.allocate_pages(20)?;
+        for page_id in [9, 3, 14, 6] {
+            fm.read_page(page_id)?;
+        }
+        assert_eq!(fm.resident_page_ids(), [3, 6, 9, 14]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_pool_bytes() -> DBResult<()> {
+        // The same budget admits more small pages than large ones
+        for (page_size, admitted) in [(64, 16), (256, 4)] {
+            let mut fm = FileManager::in_memory(page_size, 1)?.with_max_pool_bytes(1024)?;
+            fm.allocate_pages(20)?;
+            for page_id in 1..=admitted {
+                fm.read_page(page_id)?;
+            }
+            assert_eq!(fm.stats().evictions, 0);
+            fm.read_page(admitted + 1)?;
+            assert_eq!(fm.stats().evictions, 1);
+            assert_eq!(fm.resident_page_ids().len(), admitted as usize);
+            assert_eq!(fm.pool_bytes, 1024);
+
+            // Resident pages leave the budget to the others
+            fm.set_resident(1, true)?;
+            assert_eq!(fm.pool_bytes, 1024 - page_size);
+            fm.set_resident(1, false)?;
+            assert_eq!(fm.pool_bytes, 1024);
+        }
+
+        assert!(FileManager::in_memory(64, 1)?
+            .with_max_pool_bytes(63)
+            .is_err());
+
+        // Setting a page limit replaces the byte budget
+        let mut fm = FileManager::in_memory(64, 1)?.with_max_pool_bytes(1024)?;
+        fm.set_max_pages_in_pool(2)?;
+        assert_eq!(fm.max_pool_bytes(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_max_pages_in_pool() -> DBResult<()> {
+        let path = "fm_test_resize_pool.db";
+        let mut fm = FileManager::new(path, 64, 16)?;
+        let pages: Vec<u64> = (0..4)
+            .map(|_| fm.allocate_page())
+            .collect::<DBResult<_>>()?;
+        fm.flush_all_pages()?;
+        drop(fm);
+
+        let mut fm = FileManager::new(path, 64, 2)?;
+        assert!(fm.set_max_pages_in_pool(0).is_err());
+
+        // Raising the cap lets every page stay resident
+        fm.set_max_pages_in_pool(4)?;
+        for &page_id in &pages {
+            fm.read_page(page_id)?;
+        }
+        let before = fm.stats();
+        for &page_id in &pages {
+            fm.read_page(page_id)?;
+        }
+        assert_eq!(fm.stats().hits - before.hits, 4);
+        assert_eq!(fm.stats().evictions, 0);
+
+        // Lowering it evicts down to the new cap, but not past pinned pages
+        fm.pin_page(pages[0])?;
+        fm.pin_page(pages[1])?;
+        assert!(fm.set_max_pages_in_pool(1).is_err());
+        assert_eq!(fm.buffer_pool.len(), 4);
+        fm.set_max_pages_in_pool(2)?;
+        assert_eq!(fm.buffer_pool.len(), 2);
+        assert_eq!(fm.stats().evictions, 2);
+        assert_eq!(fm.max_pages_in_pool(), 2);
+
+        fm.unpin_page(pages[0]);
+        fm.unpin_page(pages[1]);
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin_pages() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 3)?;
+        fm.allocate_pages(6)?;
+        let pin_counts = |fm: &FileManager| -> Vec<u16> {
+            (1..=6)
+                .map(|id| fm.buffer_pool.get(&id).map_or(0, |page| page.pin_count))
+                .collect()
+        };
+
+        fm.pin_pages(&[1, 2, 2])?;
+        assert_eq!(pin_counts(&fm), [1, 2, 0, 0, 0, 0]);
+        fm.unpin_pages(&[1, 2, 2]);
+        assert_eq!(pin_counts(&fm), [0; 6]);
+
+        // The fourth page has no room, so none of the pages stay pinned
+        assert!(matches!(
+            fm.pin_pages(&[3, 4, 5, 6]),
+            Err(DBError::StorageError(StorageError::BufferPoolFull(_)))
+        ));
+        assert_eq!(pin_counts(&fm), [0; 6]);
+        fm.read_page(6)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disable_flush_on_drop() -> DBResult<()> {
+        let path = "fm_test_flush_on_drop.db";
+        let mut fm = FileManager::new(path, 64, 4)?;
+        let page_id = fm.allocate_page()?;
+        fm.flush_all_pages()?;
+        drop(fm);
+
+        let mut fm = FileManager::new(path, 64, 4)?;
+        fm.set_flush_on_drop(false);
+        fm.write_page_to_pool(page_id, &[7; 64])?;
+        drop(fm);
+
+        let mut fm = FileManager::new(path, 64, 4)?;
+        assert!(fm.flush_on_drop());
+        assert_eq!(fm.read_page(page_id)?, [0; 64]);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_on_drop_after_rename() -> DBResult<()> {
+        let (path, new_path) = (
+            "fm_test_flush_on_drop_rename.db",
+            "fm_test_flush_on_drop_renamed.db",
+        );
+        let mut fm = FileManager::new(path, 64, 4)?;
+        let page_id = fm.allocate_page()?;
+        let mut fm = fm.rename_file(new_path)?;
+        assert!(fm.flush_on_drop());
+        fm.write_page_to_pool(page_id, &[7; 64])?;
+        drop(fm);
+
+        let mut fm = FileManager::new(new_path, 64, 4)?;
+        assert_eq!(fm.read_page(page_id)?, [7; 64]);
+
+        // A manager which does not flush on drop still does not after a rename
+        fm.set_flush_on_drop(false);
+        let fm = fm.rename_file(path)?;
+        assert!(!fm.flush_on_drop());
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_resident_marks_after_rename() -> DBResult<()> {
+        let (path, new_path) = ("fm_test_resident_rename.db", "fm_test_resident_renamed.db");
+        let mut fm = FileManager::new(path, 64, 2)?;
+        fm.allocate_pages(3)?;
+        fm.set_resident(2, true)?;
+        let mut fm = fm.rename_file(new_path)?;
+        assert!(fm.is_marked_resident(2));
+        assert!(fm.is_resident(2));
+
+        // The resident page still leaves room for the other two
+        fm.read_page(1)?;
+        fm.read_page(3)?;
+        assert_eq!(fm.resident_page_ids(), [1, 2, 3]);
+
+        drop(fm);
+        remove_file(new_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_page_ids_are_rejected() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let page_id = fm.allocate_page()?;
+        let data = vec![0; fm.usable_page_size()];
+
+        for bad in [0, page_id + 1] {
+            let unknown = |result: DBResult<()>| {
+                matches!(
+                    result,
+                    Err(DBError::StorageError(StorageError::UnknownPage(id))) if id == bad
+                )
+            };
+            assert!(unknown(fm.read_page(bad).map(|_| ())));
+            assert!(unknown(fm.write_page_to_pool(bad, &data)));
+            assert!(unknown(fm.pin_page(bad)));
+            assert!(unknown(fm.set_resident(bad, true)));
+            assert!(unknown(fm.prewarm(&[bad]).map(|_| ())));
+            assert!(!fm.is_resident(bad));
+        }
+        assert_eq!(fm.num_pages(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prewarm_skips_when_pool_is_full() -> DBResult<()> {
+        let path = "fm_test_prewarm_full.db";
+        let mut fm = FileManager::new(path, 64, 2)?;
+        let pages = fm.allocate_pages(3)?;
+        fm.flush_all_pages()?;
+        fm.pin_page(pages.start)?;
+
+        // Only one unpinned slot is available, so only one page is loaded
+        assert_eq!(fm.prewarm(&[pages.start + 1, pages.start + 2])?, 1);
+        assert_eq!(fm.stats().evictions, 0);
+
+        // Pages already in the pool count as loaded after it fills up
+        let ids = [pages.start + 1, pages.start + 2, pages.start];
+        assert_eq!(fm.prewarm(&ids)?, 2);
+        assert_eq!(fm.stats().evictions, 0);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_header_is_untouched() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let header = fm.reserve_header_bytes(8)?;
+        assert_eq!(fm.usable_page_size(), 56);
+
+        let page_id = fm.allocate_page()?;
+        Arc::make_mut(&mut fm.buffer_pool.get_mut(&page_id).unwrap().data)[header.clone()]
+            .fill(0xAB);
+
+        // Only payload-sized writes are accepted, and they skip the header
+        assert!(fm.write_page_to_pool(page_id, &[1; 64]).is_err());
+        fm.write_page_to_pool(page_id, &[1; 56])?;
+        assert_eq!(fm.read_page(page_id)?, &[1; 56][..]);
+
+        let data = &fm.buffer_pool[&page_id].data;
+        assert_eq!(&data[header], &[0xAB; 8][..]);
+
+        // The layout cannot change once pages are in the pool
+        assert!(fm.reserve_header_bytes(4).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_restores_metadata() -> DBResult<()> {
+        let path = "fm_test_metadata.db";
+        let mut fm = FileManager::new(path, 64, 4)?;
+        let pages: Vec<u64> = (0..3)
+            .map(|_| fm.allocate_page())
+            .collect::<DBResult<_>>()?;
+        fm.deallocate_page(pages[1])?;
+        drop(fm);
+
+        // The file holds the metadata page plus three pages, so a length-based
+        // guess would be 4
+        let mut fm = FileManager::new(path, 64, 4)?;
+        assert_eq!(fm.num_pages(), 3);
+        assert_eq!(fm.allocate_page()?, pages[1]);
+        assert_eq!(fm.allocate_page()?, 4);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_version() -> DBResult<()> {
+        let path = "fm_test_format_version.db";
+        let mut fm = FileManager::new(path, 64, 4)?;
+        let page_id = fm.allocate_page()?;
+        fm.write_page_to_pool(page_id, &[3; 64])?;
+        drop(fm);
+
+        let mut fm = FileManager::new(path, 64, 4)?;
+        assert_eq!(fm.read_page(page_id)?, [3; 64]);
+        drop(fm);
+
+        // A file from a newer version of the library is rejected, even when
+        // recovering from corruption
+        let mut bytes = std::fs::read(path)?;
+        bytes[7] = FORMAT_VERSION + 1;
+        std::fs::write(path, bytes)?;
+        for mode in [OpenMode::Strict, OpenMode::Recover] {
+            assert!(matches!(
+                FileManager::open(path, 64, 4, mode),
+                Err(DBError::StorageError(StorageError::UnsupportedFormatVersion(v)))
+                    if v == FORMAT_VERSION + 1
+            ));
+        }
+
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_legacy_file_uses_length() -> DBResult<()> {
+        let path = "fm_test_legacy.db";
+        std::fs::write(path, [1; 64 * 3])?;
+
+        let fm = FileManager::new(path, 64, 4)?;
+        assert_eq!(fm.num_pages(), 3);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_io_error_names_page() -> DBResult<()> {
+        let path = "fm_test_page_io.db";
+        let mut fm = FileManager::new(path, 64, 4)?;
+        fm.allocate_page()?;
+        let page_id = fm.allocate_page()?;
+        fm.write_page_to_pool(page_id, &[1; 64])?;
+
+        // Writes through a read-only handle fail
+        fm.file = Backend::File(OpenOptions::new().read(true).open(path)?);
+        match fm.flush_page(page_id) {
+            Err(DBError::StorageError(StorageError::PageIo {
+                page_id: failed,
+                offset,
+                ..
+            })) => {
+                assert_eq!(failed, page_id);
+                assert_eq!(offset, page_id * 64);
+            }
+            result => panic!("expected a page I/O error, got {:?}", result),
+        }
+
+        fm.file = Backend::File(OpenOptions::new().read(true).write(true).open(path)?);
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_source() -> DBResult<()> {
+        use std::sync::Mutex;
+
+        /// Stores pages in a shared map, counting loads.
+        #[derive(Clone, Default)]
+        struct MapSource {
+            pages: Arc<Mutex<HashMap<u64, Vec<u8>>>>,
+            loads: Arc<Mutex<u64>>,
+        }
+
+        impl PageSource for MapSource {
+            fn load(&self, page_id: u64, buf: &mut [u8]) -> DBResult<()> {
+                *self.loads.lock().unwrap() += 1;
+                match self.pages.lock().unwrap().get(&page_id) {
+                    Some(page) => buf.copy_from_slice(page),
+                    None => buf.fill(0),
+                }
+                Ok(())
+            }
+
+            fn store(&self, page_id: u64, buf: &[u8]) -> DBResult<()> {
+                self.pages.lock().unwrap().insert(page_id, buf.to_vec());
+                Ok(())
+            }
+        }
+
+        let source = MapSource::default();
+        let mut fm = FileManager::in_memory(64, 2)?;
+        fm.set_page_source(Box::new(source.clone()));
+        fm.allocate_pages(3)?;
+        for page_id in 1..=3 {
+            fm.write_page_to_pool(page_id, &[page_id as u8; 64])?;
+        }
+        fm.flush_all_pages()?;
+        assert_eq!(source.pages.lock().unwrap()[&2], [2; 64]);
+
+        // The file's copies of the pages were never written
+        let Backend::Memory(data) = &fm.file else {
+            unreachable!()
+        };
+        assert!(data[64..].iter().all(|&byte| byte == 0));
+
+        source.pages.lock().unwrap().insert(1, vec![9; 64]);
+        let loads = *source.loads.lock().unwrap();
+        assert_eq!(fm.read_page(1)?, [9; 64]);
+        assert_eq!(*source.loads.lock().unwrap(), loads + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_page_byte() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 16)?.with_checksums_and_compression()?;
+        let page_id = fm.allocate_page()?;
+        let len = fm.read_page(page_id)?.len();
+        fm.write_page_to_pool(page_id, &vec![3; len])?;
+        fm.flush_page(page_id)?;
+        assert_eq!(fm.read_page(page_id)?, vec![3; len]);
+
+        fm.corrupt_page_byte(page_id, FRAME_HEADER_SIZE, 0xFF)?;
+        assert!(matches!(
+            fm.read_page(page_id),
+            Err(DBError::StorageError(StorageError::ChecksumMismatch(id))) if id == page_id
+        ));
+        fm.simulate_crash();
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_crash() -> DBResult<()> {
+        let path = "fm_test_simulate_crash.db";
+        let mut fm = FileManager::new(path, 64, 16)?;
+        fm.allocate_pages(2)?;
+        fm.write_page_to_pool(1, &[1; 64])?;
+        fm.flush_all_pages()?;
+        fm.write_page_to_pool(1, &[2; 64])?;
+        fm.allocate_page()?;
+        fm.simulate_crash();
+
+        // Only what was flushed survives
+        let mut fm = FileManager::new(path, 64, 16)?;
+        assert_eq!(fm.num_pages(), 2);
+        assert_eq!(fm.read_page(1)?, [1; 64]);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_clean_page_preference() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 3)?.with_clean_page_preference();
+        fm.allocate_pages(4)?;
+        fm.flush_all_pages()?;
+        fm.read_page(2)?;
+        fm.read_page(3)?;
+        fm.write_page_to_pool(1, &[1; 64])?;
+
+        // Page 1 has the lowest id, but is dirty
+        let flushes = fm.stats().flushes;
+        fm.read_page(4)?;
+        assert_eq!(fm.stats().flushes, flushes);
+        assert!(fm.buffer_pool.contains_key(&1));
+        assert!(!fm.buffer_pool.contains_key(&2));
+
+        // Once every unpinned page is dirty, one of them is flushed
+        for page_id in [3, 4] {
+            fm.write_page_to_pool(page_id, &[page_id as u8; 64])?;
+        }
+        fm.read_page(2)?;
+        assert_eq!(fm.stats().flushes, flushes + 1);
+        assert!(!fm.buffer_pool.contains_key(&1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_verification() -> DBResult<()> {
+        for checksums in [false, true] {
+            let mut fm = FileManager::in_memory(64, 4)?.with_write_verification();
+            if checksums {
+                fm = fm.with_checksums_and_compression()?;
+            }
+            let page_id = fm.allocate_page()?;
+            let len = fm.read_page(page_id)?.len();
+            fm.write_page_to_pool(page_id, &vec![7; len])?;
+            fm.flush_page(page_id)?;
+
+            fm.corrupt_writes = true;
+            fm.write_page_to_pool(page_id, &vec![8; len])?;
+            assert!(matches!(
+                fm.flush_page(page_id),
+                Err(DBError::StorageError(StorageError::WriteVerificationFailed(id)))
+                    if id == page_id
+            ));
+            fm.set_flush_on_drop(false);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_direct_io() -> DBResult<()> {
+        let path = "fm_test_direct_io.db";
+        assert!(matches!(
+            FileManager::new_direct(path, 1000, 4),
+            Err(DBError::StorageError(StorageError::InvalidArgument(_)))
+        ));
+
+        let page_size = 4096;
+        let mut page = vec![0xAB; page_size];
+        {
+            let mut fm = FileManager::new_direct(path, page_size, 4)?;
+            let page_id = fm.allocate_page()?;
+            fm.write_page_to_pool(page_id, &page)?;
+        }
+        {
+            // Partial writes keep the rest of their block
+            let mut fm = FileManager::new_direct(path, page_size, 4)?.with_dirty_region_tracking();
+            assert_eq!(fm.read_page(1)?, page);
+            page[100..110].fill(0xCD);
+            fm.write_page_to_pool(1, &page)?;
+        }
+        let mut fm = FileManager::new(path, page_size, 4)?;
+        assert_eq!(fm.read_page(1)?, page);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_fd_pool() -> DBResult<()> {
+        let pool = FdPool::new(2)?;
+        let paths: Vec<String> = (0..5)
+            .map(|i| format!("fm_test_fd_pool_{}.db", i))
+            .collect();
+        let mut fms = paths
+            .iter()
+            .map(|path| {
+                let mut fm = FileManager::new(path, 64, 2)?.with_fd_pool(&pool);
+                fm.allocate_pages(3)?;
+                Ok(fm)
+            })
+            .collect::<DBResult<Vec<_>>>()?;
+        assert_eq!(pool.open_files(), 2);
+
+        // Writes soon evict earlier pages, so every file is accessed
+        for page_id in 1..=3 {
+            for (i, fm) in fms.iter_mut().enumerate() {
+                fm.write_page_to_pool(page_id, &[i as u8 + page_id as u8 - 1; 64])?;
+                assert!(pool.open_files() <= 2);
+            }
+        }
+        assert!(pool.reopens() > 0);
+        for (i, fm) in fms.iter_mut().enumerate() {
+            for page_id in 1..=3 {
+                assert_eq!(fm.read_page(page_id)?, [i as u8 + page_id as u8 - 1; 64]);
+            }
+        }
+
+        drop(fms);
+        assert_eq!(pool.open_files(), 0);
+        for path in &paths {
+            assert_eq!(FileManager::new(path, 64, 2)?.num_pages(), 3);
+            remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_io() {
+        use std::io::Read;
+
+        /// A reader which fails with each of `errors` in turn before
+        /// succeeding.
+        struct FlakyReader {
+            errors: Vec<io::ErrorKind>,
+        }
+
+        impl Read for FlakyReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.errors.pop() {
+                    Some(kind) => Err(io::Error::from(kind)),
+                    None => Ok(buf.len()),
+                }
+            }
+        }
+
+        let retries = Some((3, Duration::from_millis(1)));
+        let mut buf = [0; 8];
+        let mut reader = FlakyReader {
+            errors: vec![io::ErrorKind::Interrupted; 2],
+        };
+        assert_eq!(retry_io(retries, || reader.read(&mut buf)).unwrap(), 8);
+
+        // Permanent errors, and transient ones once retries run out, fail
+        let mut reader = FlakyReader {
+            errors: vec![io::ErrorKind::Interrupted, io::ErrorKind::PermissionDenied],
+        };
+        let err = retry_io(retries, || reader.read(&mut buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(reader.errors.len(), 1);
+        let mut reader = FlakyReader {
+            errors: vec![io::ErrorKind::TimedOut; 2],
+        };
+        let err = retry_io(Some((1, Duration::ZERO)), || reader.read(&mut buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let mut reader = FlakyReader {
+            errors: vec![io::ErrorKind::WouldBlock],
+        };
+        assert!(retry_io(None, || reader.read(&mut buf)).is_err());
+    }
+
+    #[test]
+    fn test_allocate_pages_reuses_free_run() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 16)?;
+        fm.allocate_pages(8)?;
+        for page_id in 1..=8 {
+            fm.write_page_to_pool(page_id, &[page_id as u8; 64])?;
+        }
+        fm.flush_all_pages()?;
+
+        // Page 2 is free on its own, and 4 to 6 form a run
+        for page_id in [2, 5, 4, 6, 8] {
+            fm.deallocate_page(page_id)?;
+        }
+        fm.allocate_page()?;
+        assert_eq!(fm.allocate_pages(3)?, 4..7);
+        assert_eq!(fm.num_pages(), 8);
+        assert_eq!(fm.free_pages, [2]);
+        for page_id in 4..7 {
+            assert_eq!(fm.read_page(page_id)?, [0; 64]);
+        }
+
+        // No run of 2 is left, so the file is grown
+        assert_eq!(fm.allocate_pages(2)?, 9..11);
+        assert_eq!(fm.num_pages(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deallocate_page() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 4)?;
+        let a = fm.allocate_page()?;
+        let b = fm.allocate_page()?;
+
+        // Pinned, unallocated, and already-freed pages cannot be freed
+        fm.pin_page(b)?;
+        assert!(fm.deallocate_page(b).is_err());
+        assert!(fm.deallocate_page(b + 1).is_err());
+        fm.deallocate_page(a)?;
+        assert!(fm.deallocate_page(a).is_err());
+
+        // The freed page is reused
+        assert_eq!(fm.allocate_page()?, a);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_deallocate_punches_hole() -> DBResult<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = "fm_test_punch_hole.db";
+        let page_size = 4096;
+        let mut fm = FileManager::new(path, page_size, 16)?.with_hole_punching();
+        let page_ids: Vec<u64> = (0..8)
+            .map(|_| fm.allocate_page())
+            .collect::<DBResult<_>>()?;
+        for &page_id in &page_ids {
+            fm.write_page_to_pool(page_id, &vec![0xAB; page_size])?;
+        }
+        fm.flush_all_pages()?;
+        let metadata = |fm: &FileManager| match &fm.file {
+            Backend::File(file) => file.metadata(),
+            _ => unreachable!(),
+        };
+        let blocks_before = metadata(&fm)?.blocks();
+
+        for &page_id in &page_ids[..4] {
+            fm.deallocate_page(page_id)?;
+        }
+        let blocks_after = metadata(&fm)?.blocks();
+        assert!(blocks_after < blocks_before);
+
+        // The file keeps its length and the freed pages read back as zeros
+        assert_eq!(metadata(&fm)?.len(), 9 * page_size as u64);
+        let page_id = fm.allocate_page()?;
+        assert!(page_ids[..4].contains(&page_id));
+        assert!(fm.read_page(page_id)?.iter().all(|&b| b == 0));
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_read_page_miss_emits_event() -> DBResult<()> {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// The message and `page_id` of each captured event.
+        type CapturedEvents = Arc<Mutex<Vec<(String, Option<u64>)>>>;
+
+        /// A `Subscriber` which records the message and `page_id` of every
+        /// event it receives.
+        #[derive(Clone, Default)]
+        struct CapturingSubscriber {
+            events: CapturedEvents,
+        }
+
+        #[derive(Default)]
+        struct EventVisitor {
+            message: String,
+            page_id: Option<u64>,
+        }
+
+        impl Visit for EventVisitor {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                if field.name() == "page_id" {
+                    self.page_id = Some(value);
+                }
+            }
+
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.message = format!("{:?}", value);
+                }
+            }
+        }
+
+        impl Subscriber for CapturingSubscriber {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, _: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut visitor = EventVisitor::default();
+                event.record(&mut visitor);
+                self.events
+                    .lock()
+                    .unwrap()
+                    .push((visitor.message, visitor.page_id));
+            }
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        let path = "fm_test_tracing.db";
+        {
+            let mut fm = FileManager::new(path, 64, 4)?;
+            fm.allocate_page()?;
+        }
+
+        let subscriber = CapturingSubscriber::default();
+        let events = subscriber.events.clone();
+        tracing::subscriber::with_default(subscriber, || -> DBResult<()> {
+            let mut fm = FileManager::new(path, 64, 4)?;
+            fm.read_page(1)?;
+            Ok(())
+        })?;
+        remove_file(path)?;
+
+        let events = events.lock().unwrap();
+        assert!(events
+            .iter()
+            .any(|(message, page_id)| message == "read page from disk" && *page_id == Some(1)));
+        Ok(())
+    }
 }
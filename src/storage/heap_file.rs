@@ -0,0 +1,1136 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::BufRead;
+
+use crate::datatypes::{Accumulator, Aggregate, LoadReport, Schema, Tuple, Type, Value};
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+use super::slotted_page::check_fill_factor;
+use super::tuple::{deserialize_tuple, read_field, serialize_tuple};
+use super::{BlobHandle, CorruptItem, FileManager, SlottedPage};
+
+/// Size in bytes of the id of the next page, which is stored at the start of
+/// the payload of each page of a `HeapFile`.
+const NEXT_PAGE_SIZE: usize = 8;
+
+/// A `RecordId` identifies a tuple stored in a `HeapFile` by the page it is
+/// in and its slot within that page. It does not change for as long as the
+/// tuple exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordId {
+    pub page_id: u64,
+    pub slot: u16,
+}
+
+/// Whether a tuple fits in a page of a `HeapFile`, as returned by
+/// `HeapFile::check_tuple_fit`. Each variant holds the serialized size of
+/// the tuple in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TupleFit {
+    /// The tuple fits in an empty page, so it can be inserted.
+    Inline(usize),
+    /// The tuple does not fit in a page, but would if its strings and
+    /// composite values were stored as blobs in overflow pages instead.
+    NeedsOverflow(usize),
+    /// The tuple does not fit in a page even if all of its variable-length
+    /// values were stored as blobs, so the schema must change.
+    TooLargeEvenForOverflow(usize),
+}
+
+impl TupleFit {
+    /// Returns the serialized size of the tuple in bytes.
+    pub fn size(&self) -> usize {
+        match *self {
+            TupleFit::Inline(size)
+            | TupleFit::NeedsOverflow(size)
+            | TupleFit::TooLargeEvenForOverflow(size) => size,
+        }
+    }
+}
+
+/// What `HeapFile::load_csv` does with a row which cannot be parsed or
+/// inserted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnBadRow {
+    /// Record the row in the report and continue with the next one.
+    Skip,
+    /// Record the row in the report and stop loading.
+    Abort,
+}
+
+/// A `HeapFile` stores the tuples of a table, in no particular order, in a
+/// chain of pages managed by a `FileManager`. The payload of each page starts
+/// with the id of the next page in the chain (0 for the last page), and the
+/// rest of it is a `SlottedPage`.
+///
+/// A `HeapFile` only remembers the first and last pages of its chain, so it
+/// can be reopened from the id of its first page.
+pub struct HeapFile {
+    first_page: u64,
+    last_page: u64,
+    schema: Schema,
+    fill_factor: u8,
+}
+
+impl HeapFile {
+    /// Creates a new empty `HeapFile` for tuples with the given `schema`,
+    /// allocating its first page in `fm`.
+    pub fn create(fm: &mut FileManager, schema: Schema) -> DBResult<Self> {
+        let page = SlottedPage::new(slotted_page_size(fm)?)?;
+        let page_id = fm.allocate_page()?;
+        write_heap_page(fm, page_id, 0, &page)?;
+
+        Ok(HeapFile {
+            first_page: page_id,
+            last_page: page_id,
+            schema,
+            fill_factor: 100,
+        })
+    }
+
+    /// Opens an existing `HeapFile` whose chain of pages starts at
+    /// `first_page`.
+    pub fn open(fm: &mut FileManager, first_page: u64, schema: Schema) -> DBResult<Self> {
+        let chain = HeapFile::chain(fm, first_page)?;
+        let last_page = chain.last().copied().unwrap_or(first_page);
+
+        Ok(HeapFile {
+            first_page,
+            last_page,
+            schema,
+            fill_factor: 100,
+        })
+    }
+
+    /// Returns the id of the first page of this `HeapFile`, which can be used
+    /// to reopen it.
+    pub fn first_page(&self) -> u64 {
+        self.first_page
+    }
+
+    /// Sets the percentage of each page that `insert` fills before starting
+    /// a new page, so that pages have room left for tuples to grow when they
+    /// are updated in place; see `SlottedPage::set_fill_factor`. This is 100
+    /// by default, and is not stored in the file, so it must be set again
+    /// after reopening. Returns an error if `fill_factor` is not between 1
+    /// and 100.
+    pub fn with_fill_factor(mut self, fill_factor: u8) -> DBResult<Self> {
+        check_fill_factor(fill_factor)?;
+        self.fill_factor = fill_factor;
+        Ok(self)
+    }
+
+    /// Returns the percentage of each page that `insert` fills; see
+    /// `with_fill_factor`.
+    pub fn fill_factor(&self) -> u8 {
+        self.fill_factor
+    }
+
+    /// Returns the schema of the tuples in this `HeapFile`.
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Inserts `tuple` and returns its `RecordId`. The tuple is added to the
+    /// last page of the chain, or to a newly allocated page if it does not
+    /// fit there. Returns an error if the tuple does not conform to the
+    /// schema or is too large to fit in an empty page.
+    pub fn insert(&mut self, fm: &mut FileManager, tuple: &Tuple) -> DBResult<RecordId> {
+        let bytes = serialize_tuple(tuple, &self.schema)?;
+        self.insert_bytes(fm, &bytes)
+    }
+
+    /// Inserts an already serialized tuple as in `insert`.
+    fn insert_bytes(&mut self, fm: &mut FileManager, bytes: &[u8]) -> DBResult<RecordId> {
+        let (next_page, mut page) = read_heap_page(fm, self.last_page)?;
+        page.set_fill_factor(self.fill_factor)?;
+        match page.insert(bytes) {
+            Ok(slot) => {
+                write_heap_page(fm, self.last_page, next_page, &page)?;
+                return Ok(RecordId {
+                    page_id: self.last_page,
+                    slot,
+                });
+            }
+            Err(DBError::StorageError(StorageError::PageFull(_))) => {}
+            Err(e) => return Err(e),
+        }
+
+        // The tuple does not fit in the last page, so start a new one
+        let mut new_page = SlottedPage::new(slotted_page_size(fm)?)?;
+        let slot = new_page.insert(bytes)?;
+        let new_page_id = fm.allocate_page()?;
+        write_heap_page(fm, new_page_id, 0, &new_page)?;
+        write_heap_page(fm, self.last_page, new_page_id, &page)?;
+        self.last_page = new_page_id;
+
+        Ok(RecordId {
+            page_id: new_page_id,
+            slot,
+        })
+    }
+
+    /// Loads rows of comma-separated values from `reader` into the heap
+    /// file, reading one line at a time so that memory use does not grow
+    /// with the input. Each page is flushed as soon as it is full.
+    ///
+    /// Each line holds one field per column, which is parsed with
+    /// `Value::parse`. Fields may be quoted with `"`, using `""` for a quote
+    /// inside them, and an empty unquoted field is null. Quoted fields
+    /// cannot span lines, and blank lines are skipped. There is no header
+    /// line, so one should be read from `reader` first if there is one.
+    ///
+    /// Rows which cannot be parsed or inserted are recorded in the report by
+    /// their line number, starting from 1, and handled according to
+    /// `on_bad_row`. Returns an error if reading from `reader` or writing a
+    /// page fails, in which case the rows loaded so far are kept.
+    pub fn load_csv(
+        &mut self,
+        fm: &mut FileManager,
+        mut reader: impl BufRead,
+        on_bad_row: OnBadRow,
+    ) -> DBResult<LoadReport> {
+        let mut report = LoadReport::default();
+        let mut line = String::new();
+        let mut line_number = 0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(report);
+            }
+            line_number += 1;
+            let text = line.trim_end_matches(['\n', '\r']);
+            if text.is_empty() {
+                continue;
+            }
+
+            let last_page = self.last_page;
+            // Serializing checks the row against the schema, so any error
+            // before the row is written is a problem with the row
+            let inserted = parse_csv_row(text, &self.schema)
+                .and_then(|tuple| serialize_tuple(&tuple, &self.schema))
+                .and_then(|bytes| match self.insert_bytes(fm, &bytes) {
+                    // The tuple is too large for a page: a problem with the
+                    // row, not the file
+                    Err(e @ DBError::StorageError(StorageError::PageFull(_))) => Err(e),
+                    result => Ok(result),
+                });
+            match inserted {
+                Ok(result) => {
+                    result?;
+                    report.inserted += 1;
+                }
+                Err(e) => {
+                    report.rejected.push((line_number, e));
+                    if on_bad_row == OnBadRow::Abort {
+                        return Ok(report);
+                    }
+                }
+            }
+            if self.last_page != last_page {
+                fm.flush_page(last_page)?;
+            }
+        }
+    }
+
+    /// Returns the tuple with the given `rid`, or `None` if it has been
+    /// deleted. `rid` must refer to a page of this `HeapFile`.
+    pub fn get(&self, fm: &mut FileManager, rid: RecordId) -> DBResult<Option<Tuple>> {
+        let (_, page) = read_heap_page(fm, rid.page_id)?;
+        page.get(rid.slot)
+            .map(|bytes| deserialize_tuple(bytes, &self.schema))
+            .transpose()
+    }
+
+    /// Deletes the tuple with the given `rid`. `rid` must refer to a page of
+    /// this `HeapFile`.
+    pub fn delete(&mut self, fm: &mut FileManager, rid: RecordId) -> DBResult<()> {
+        let (next_page, mut page) = read_heap_page(fm, rid.page_id)?;
+        page.delete(rid.slot)?;
+        write_heap_page(fm, rid.page_id, next_page, &page)
+    }
+
+    /// Deletes every tuple of this `HeapFile`, deallocating every page of its
+    /// chain except the first, which is left empty so that the heap file can
+    /// still be reopened from it. Blobs which the tuples point to are not
+    /// freed.
+    pub fn truncate(&mut self, fm: &mut FileManager) -> DBResult<()> {
+        let chain = self.page_ids(fm)?;
+        let page = SlottedPage::new(slotted_page_size(fm)?)?;
+        write_heap_page(fm, self.first_page, 0, &page)?;
+        for &page_id in &chain[1..] {
+            fm.deallocate_page(page_id)?;
+        }
+        self.last_page = self.first_page;
+        Ok(())
+    }
+
+    /// Deletes this `HeapFile`, deallocating every page of its chain and the
+    /// overflow pages of the blobs its tuples point to, so that they can be
+    /// reused.
+    pub fn destroy(self, fm: &mut FileManager) -> DBResult<()> {
+        let blob_columns: Vec<usize> = (0..self.schema.types().len())
+            .filter(|&i| matches!(self.schema.types()[i], Type::Blob))
+            .collect();
+        if !blob_columns.is_empty() {
+            let tuples: Vec<Tuple> = self.scan(fm).collect::<DBResult<_>>()?;
+            for tuple in tuples {
+                for &column in &blob_columns {
+                    if let Value::Blob(handle) = tuple.values()[column] {
+                        fm.delete_blob(handle)?;
+                    }
+                }
+            }
+        }
+
+        for page_id in self.page_ids(fm)? {
+            fm.deallocate_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of all pages of this `HeapFile`, in chain order.
+    pub fn page_ids(&self, fm: &mut FileManager) -> DBResult<Vec<u64>> {
+        HeapFile::chain(fm, self.first_page)
+    }
+
+    /// Checks whether `tuple` would fit in a page of this `HeapFile` in
+    /// `fm`, without inserting it. Tuples which do not fit are rejected by
+    /// `insert`, so callers can use this to move large values to blobs or
+    /// catch schemas whose tuples can never fit. Returns an error if the
+    /// tuple does not conform to the schema.
+    pub fn check_tuple_fit(&self, fm: &FileManager, tuple: &Tuple) -> DBResult<TupleFit> {
+        let size = serialize_tuple(tuple, &self.schema)?.len();
+        let capacity = SlottedPage::max_tuple_len(slotted_page_size(fm)?);
+        if size <= capacity {
+            return Ok(TupleFit::Inline(size));
+        }
+
+        // Each variable-length value could be replaced by a blob handle
+        let savings: usize = tuple
+            .values()
+            .iter()
+            .filter(|value| {
+                matches!(
+                    value,
+                    Value::Char(_) | Value::Varchar(_) | Value::Enum(..) | Value::Struct(_)
+                )
+            })
+            .map(|value| {
+                value
+                    .serialized_len()
+                    .saturating_sub(BlobHandle::SERIALIZED_SIZE)
+            })
+            .sum();
+        if size - savings <= capacity {
+            Ok(TupleFit::NeedsOverflow(size))
+        } else {
+            Ok(TupleFit::TooLargeEvenForOverflow(size))
+        }
+    }
+
+    /// Returns the total size in bytes of the pages this `HeapFile` occupies
+    /// in `fm`: the pages of its chain and the overflow pages of the blobs
+    /// its tuples point to. If the schema has a `Blob` column, every tuple is
+    /// read to find its blobs.
+    pub fn disk_size(&self, fm: &mut FileManager) -> DBResult<u64> {
+        let mut num_pages = self.page_ids(fm)?.len();
+
+        let blob_columns: Vec<usize> = (0..self.schema.types().len())
+            .filter(|&i| matches!(self.schema.types()[i], Type::Blob))
+            .collect();
+        if !blob_columns.is_empty() {
+            let tuples: Vec<Tuple> = self.scan(fm).collect::<DBResult<_>>()?;
+            for tuple in tuples {
+                for &column in &blob_columns {
+                    if let Value::Blob(handle) = tuple.values()[column] {
+                        num_pages += fm.blob_page_ids(handle)?.len();
+                    }
+                }
+            }
+        }
+
+        Ok(num_pages as u64 * fm.page_size() as u64)
+    }
+
+    /// Returns a `TableCursor` which scans all tuples of this `HeapFile` in
+    /// page and slot order.
+    pub fn scan<'a>(&self, fm: &'a mut FileManager) -> TableCursor<'a> {
+        TableCursor {
+            fm,
+            schema: self.schema.clone(),
+            order: ScanOrder::Forward {
+                next_page: self.first_page,
+                pages_read: 0,
+            },
+            buffer: VecDeque::new(),
+            deserializations: 0,
+        }
+    }
+
+    /// Returns a `TableCursor` which scans all tuples of this `HeapFile` in
+    /// the reverse of the order of `scan`, so tuples appended most recently
+    /// come first. The chain of pages is followed before any tuples are
+    /// read, so that its pages can be read from last to first.
+    pub fn scan_rev<'a>(&self, fm: &'a mut FileManager) -> DBResult<TableCursor<'a>> {
+        let page_ids = HeapFile::chain(fm, self.first_page)?;
+        Ok(TableCursor {
+            fm,
+            schema: self.schema.clone(),
+            order: ScanOrder::Reverse(page_ids),
+            buffer: VecDeque::new(),
+            deserializations: 0,
+        })
+    }
+
+    /// Follows the chain of pages starting at `first_page` and returns their
+    /// ids in order. Only the links between pages are read, so a page whose
+    /// tuples are corrupt does not stop the chain from being followed.
+    fn chain(fm: &mut FileManager, first_page: u64) -> DBResult<Vec<u64>> {
+        let mut page_ids = Vec::new();
+        let mut page_id = first_page;
+        while page_id != 0 {
+            if page_ids.len() as u64 >= fm.num_pages() {
+                return Err(cycle_error());
+            }
+            page_ids.push(page_id);
+            page_id = read_next_page(fm, page_id)?;
+        }
+        Ok(page_ids)
+    }
+}
+
+/// A `TableCursor` scans the tuples of a `HeapFile` one page at a time,
+/// yielding each tuple or the first error encountered, after which it stops.
+///
+/// If the `FileManager` is in `OpenMode::Recover`, corrupt pages and tuples
+/// are skipped and recorded in the `FileManager` instead of being returned
+/// as errors.
+pub struct TableCursor<'a> {
+    fm: &'a mut FileManager,
+    schema: Schema,
+    order: ScanOrder,
+    buffer: VecDeque<(RecordId, Vec<u8>)>,
+    deserializations: usize,
+}
+
+/// The order in which a `TableCursor` reads the pages of a `HeapFile`.
+enum ScanOrder {
+    /// Follows the chain from its first page. `next_page` is 0 once the end
+    /// of the chain is reached.
+    Forward { next_page: u64, pages_read: u64 },
+    /// Pops the ids of the pages of the chain, so the last page is read
+    /// first. Tuples within each page are read from the last slot.
+    Reverse(Vec<u64>),
+}
+
+impl<'a> TableCursor<'a> {
+    /// Returns a cursor which only yields tuples for which `pred` returns
+    /// `true`.
+    ///
+    /// Before calling `pred`, only the given `columns` of each tuple are
+    /// decoded; every other value of the tuple passed to `pred` is `Null`.
+    /// Tuples are only fully deserialized if `pred` accepts them, which is
+    /// much cheaper for selective scans of wide tuples. `pred` must
+    /// therefore only look at the given `columns`. Returns an error if any
+    /// of `columns` is out of range for the schema.
+    pub fn filter<F: Fn(&Tuple) -> bool>(
+        self,
+        columns: &[usize],
+        pred: F,
+    ) -> DBResult<FilteredCursor<'a, F>> {
+        for &column in columns {
+            self.schema.column_type(column)?;
+        }
+        Ok(FilteredCursor {
+            cursor: self,
+            columns: columns.to_vec(),
+            pred,
+        })
+    }
+
+    /// Computes `aggregate` over the values of `column` in every remaining
+    /// tuple, in a single pass which only holds one page in memory at a
+    /// time. Returns the first error encountered by the scan or the
+    /// aggregate.
+    pub fn fold_aggregate(self, column: usize, aggregate: Aggregate) -> DBResult<Value> {
+        self.schema.column_type(column)?;
+        let mut accumulator = Accumulator::new(aggregate);
+        for tuple in self {
+            accumulator.update(&tuple?.values()[column])?;
+        }
+        Ok(accumulator.finish())
+    }
+
+    /// Computes `aggregate` over the values of `column` like
+    /// `fold_aggregate`, separately for each group of tuples with the same
+    /// values in the `group_by` columns. Returns the aggregate for each
+    /// group, keyed by a tuple of its values in the `group_by` columns.
+    ///
+    /// The group values and an accumulator for each group are kept in memory
+    /// until the scan ends, so memory use grows with the number of distinct
+    /// groups, however few tuples each of them has.
+    pub fn group_aggregate(
+        self,
+        group_by: &[usize],
+        column: usize,
+        aggregate: Aggregate,
+    ) -> DBResult<HashMap<Tuple, Value>> {
+        for &group_column in group_by {
+            self.schema.column_type(group_column)?;
+        }
+        self.schema.column_type(column)?;
+
+        let mut groups: HashMap<Tuple, Accumulator> = HashMap::new();
+        for tuple in self {
+            let tuple = tuple?;
+            let key = Tuple::new(
+                group_by
+                    .iter()
+                    .map(|&i| tuple.values()[i].clone())
+                    .collect(),
+            );
+            groups
+                .entry(key)
+                .or_insert_with(|| Accumulator::new(aggregate))
+                .update(&tuple.values()[column])?;
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(key, accumulator)| (key, accumulator.finish()))
+            .collect())
+    }
+
+    /// Returns the number of tuples this cursor has fully deserialized.
+    pub fn deserializations(&self) -> usize {
+        self.deserializations
+    }
+
+    /// Returns the serialized bytes of the next tuple, reading the next page
+    /// of the chain if necessary.
+    fn next_raw(&mut self) -> Option<DBResult<(RecordId, Vec<u8>)>> {
+        while self.buffer.is_empty() {
+            let page_id = match &mut self.order {
+                ScanOrder::Forward { next_page: 0, .. } => return None,
+                ScanOrder::Forward {
+                    next_page,
+                    pages_read,
+                } => {
+                    *pages_read += 1;
+                    if *pages_read > self.fm.num_pages() {
+                        *next_page = 0;
+                        return Some(Err(cycle_error()));
+                    }
+                    *next_page
+                }
+                ScanOrder::Reverse(page_ids) => page_ids.pop()?,
+            };
+
+            let (next_page, page) = match read_heap_page(self.fm, page_id) {
+                Ok(page) => page,
+                Err(e) => {
+                    // Skip the page's tuples, but keep scanning if the
+                    // error can be recovered from. A forward scan also needs
+                    // the link to the next page to still be readable.
+                    let result = self.fm.recover_from(CorruptItem::Page(page_id), e);
+                    match &mut self.order {
+                        ScanOrder::Forward { next_page, .. } => {
+                            *next_page = match result {
+                                Ok(()) => read_next_page(self.fm, page_id).unwrap_or(0),
+                                Err(_) => 0,
+                            };
+                        }
+                        ScanOrder::Reverse(page_ids) if result.is_err() => page_ids.clear(),
+                        ScanOrder::Reverse(_) => {}
+                    }
+                    if let Err(e) = result {
+                        return Some(Err(e));
+                    }
+                    continue;
+                }
+            };
+
+            let tuples = (0..page.num_slots()).filter_map(|slot| {
+                page.get(slot)
+                    .map(|bytes| (RecordId { page_id, slot }, bytes.to_vec()))
+            });
+            match &mut self.order {
+                ScanOrder::Forward {
+                    next_page: next, ..
+                } => {
+                    self.buffer.extend(tuples);
+                    *next = next_page;
+                }
+                ScanOrder::Reverse(_) => self.buffer.extend(tuples.rev()),
+            }
+        }
+
+        self.buffer.pop_front().map(Ok)
+    }
+
+    fn deserialize(&mut self, bytes: &[u8]) -> DBResult<Tuple> {
+        self.deserializations += 1;
+        deserialize_tuple(bytes, &self.schema)
+    }
+}
+
+impl Iterator for TableCursor<'_> {
+    type Item = DBResult<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (rid, bytes) = match self.next_raw()? {
+                Ok(raw) => raw,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match self.deserialize(&bytes) {
+                Ok(tuple) => return Some(Ok(tuple)),
+                Err(e) => {
+                    if let Err(e) = self.fm.recover_from(CorruptItem::Tuple(rid), e) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A `TableCursor` which only yields tuples accepted by a predicate; see
+/// `TableCursor::filter`.
+pub struct FilteredCursor<'a, F> {
+    cursor: TableCursor<'a>,
+    columns: Vec<usize>,
+    pred: F,
+}
+
+impl<F> FilteredCursor<'_, F> {
+    /// Returns the number of tuples this cursor has fully deserialized.
+    pub fn deserializations(&self) -> usize {
+        self.cursor.deserializations
+    }
+}
+
+impl<F: Fn(&Tuple) -> bool> FilteredCursor<'_, F> {
+    /// Decodes only the filtered columns of a tuple and checks whether it is
+    /// accepted by the predicate.
+    fn accepts(&self, bytes: &[u8]) -> DBResult<bool> {
+        let schema = &self.cursor.schema;
+        let mut values = vec![Value::Null; schema.types().len()];
+        for &column in &self.columns {
+            values[column] = read_field(bytes, schema, column)?;
+        }
+        Ok((self.pred)(&Tuple::new(values)))
+    }
+}
+
+impl<F: Fn(&Tuple) -> bool> Iterator for FilteredCursor<'_, F> {
+    type Item = DBResult<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (rid, bytes) = match self.cursor.next_raw()? {
+                Ok(raw) => raw,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let result = match self.accepts(&bytes) {
+                Ok(true) => self.cursor.deserialize(&bytes).map(Some),
+                Ok(false) => Ok(None),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(Some(tuple)) => return Some(Ok(tuple)),
+                Ok(None) => {}
+                Err(e) => {
+                    if let Err(e) = self.cursor.fm.recover_from(CorruptItem::Tuple(rid), e) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the size of the `SlottedPage` stored in each page of a `HeapFile`.
+fn slotted_page_size(fm: &FileManager) -> DBResult<usize> {
+    fm.usable_page_size()
+        .checked_sub(NEXT_PAGE_SIZE)
+        .ok_or_else(|| {
+            DBError::from(StorageError::InvalidArgument(format!(
+                "usable page size of {} bytes is too small for a HeapFile.",
+                fm.usable_page_size()
+            )))
+        })
+}
+
+/// Reads a page of a `HeapFile`, returning the id of the next page in the
+/// chain and the page's tuples.
+fn read_heap_page(fm: &mut FileManager, page_id: u64) -> DBResult<(u64, SlottedPage)> {
+    let data = fm.read_page(page_id)?;
+    let next_page = u64::from_le_bytes(data[..NEXT_PAGE_SIZE].try_into().unwrap());
+    let page = SlottedPage::from_bytes(data[NEXT_PAGE_SIZE..].to_vec())?;
+    Ok((next_page, page))
+}
+
+/// Reads only the id of the next page in the chain from a page of a
+/// `HeapFile`.
+fn read_next_page(fm: &mut FileManager, page_id: u64) -> DBResult<u64> {
+    let data = fm.read_page(page_id)?;
+    Ok(u64::from_le_bytes(
+        data[..NEXT_PAGE_SIZE].try_into().unwrap(),
+    ))
+}
+
+/// Returns the error for a chain of pages which is longer than the file,
+/// meaning that it must loop back on itself.
+fn cycle_error() -> DBError {
+    DBError::from(StorageError::DeserializationError(
+        "HeapFile page chain contains a cycle.".to_string(),
+    ))
+}
+
+/// Writes a page of a `HeapFile` to the buffer pool.
+fn write_heap_page(
+    fm: &mut FileManager,
+    page_id: u64,
+    next_page: u64,
+    page: &SlottedPage,
+) -> DBResult<()> {
+    let mut data = Vec::with_capacity(fm.usable_page_size());
+    data.extend_from_slice(&next_page.to_le_bytes());
+    data.extend_from_slice(page.as_bytes());
+    fm.write_page_to_pool(page_id, &data)
+}
+
+/// Parses a line of comma-separated values, as described in
+/// `HeapFile::load_csv`, into a tuple of `schema`.
+fn parse_csv_row(line: &str, schema: &Schema) -> DBResult<Tuple> {
+    let parse_error = |message: &str| DBError::from(StorageError::ParseError(message.to_string()));
+    let mut fields: Vec<Option<String>> = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err(parse_error("unterminated quoted field.")),
+                }
+            }
+            fields.push(Some(field));
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                if c == '"' {
+                    return Err(parse_error("quote inside an unquoted field."));
+                }
+                field.push(c);
+            }
+            fields.push((!field.is_empty()).then_some(field));
+        }
+        match chars.next() {
+            Some(',') => {}
+            None => break,
+            Some(_) => return Err(parse_error("unexpected text after a quoted field.")),
+        }
+    }
+
+    if fields.len() != schema.types().len() {
+        return Err(DBError::from(StorageError::ParseError(format!(
+            "expected {} fields but found {}.",
+            schema.types().len(),
+            fields.len()
+        ))));
+    }
+    let values = fields
+        .iter()
+        .zip(schema.types())
+        .map(|(field, ty)| {
+            field
+                .as_deref()
+                .map_or(Ok(Value::Null), |f| Value::parse(f, ty))
+        })
+        .collect::<DBResult<_>>()?;
+    Ok(Tuple::new(values))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::remove_file;
+
+    use super::*;
+    use crate::datatypes::{Table, Type};
+
+    fn schema() -> Schema {
+        Schema::new(vec![Type::Int, Type::Varchar(16), Type::Bool])
+    }
+
+    fn row(i: i32) -> Tuple {
+        Tuple::new(vec![
+            Value::Int(i),
+            Value::Varchar(format!("row {}", i)),
+            Value::Bool(i % 3 == 0),
+        ])
+    }
+
+    #[test]
+    fn test_load_csv() -> DBResult<()> {
+        let mut csv = String::new();
+        for i in 0..40 {
+            csv.push_str(&format!("{},row {},{}\n", i, i, i % 3 == 0));
+        }
+        // Quoted fields, a null, and bad rows on lines 42 and 43
+        csv.push_str("40,\"a, \"\"quote\"\"\",\n41,x,maybe\n\"42,1,true\n\n43,,false\n");
+
+        let mut fm = FileManager::in_memory(128, 8)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+        let report = heap.load_csv(&mut fm, csv.as_bytes(), OnBadRow::Skip)?;
+        assert_eq!(report.inserted, 42);
+        let lines: Vec<usize> = report.rejected.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [42, 43]);
+        assert!(heap.page_ids(&mut fm)?.len() > 4);
+
+        let rows: Vec<Tuple> = heap.scan(&mut fm).collect::<DBResult<_>>()?;
+        assert_eq!(rows.len(), 42);
+        assert_eq!(rows[..40], (0..40).map(row).collect::<Vec<_>>()[..]);
+        assert_eq!(
+            rows[40],
+            Tuple::new(vec![
+                Value::Int(40),
+                Value::Varchar("a, \"quote\"".to_string()),
+                Value::Null,
+            ])
+        );
+        assert_eq!(rows[41].values()[1], Value::Null);
+
+        // Aborting stops at the first bad row
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+        let report = heap.load_csv(
+            &mut fm,
+            "1,a,true\n2,b\n3,c,true\n".as_bytes(),
+            OnBadRow::Abort,
+        )?;
+        assert_eq!(report.inserted, 1);
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].0, 2);
+
+        // Rows which are too large to serialize are rejected like bad rows
+        let mut heap = HeapFile::create(&mut fm, schema().with_size_limits(8, 64))?;
+        let report = heap.load_csv(
+            &mut fm,
+            "1,ok,true\n2,much too long,false\n3,ok,true\n".as_bytes(),
+            OnBadRow::Skip,
+        )?;
+        assert_eq!(report.inserted, 2);
+        assert!(matches!(
+            report.rejected[..],
+            [(2, DBError::StorageError(StorageError::ValueTooLarge { .. }))]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_heap_file_insert_get_delete() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(128, 16)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+
+        let rids: Vec<RecordId> = (0..20)
+            .map(|i| heap.insert(&mut fm, &row(i)))
+            .collect::<DBResult<_>>()?;
+        assert!(heap.page_ids(&mut fm)?.len() > 1);
+        assert_eq!(heap.get(&mut fm, rids[7])?, Some(row(7)));
+
+        heap.delete(&mut fm, rids[7])?;
+        assert_eq!(heap.get(&mut fm, rids[7])?, None);
+
+        // Reopening from the first page finds all pages of the chain
+        let heap = HeapFile::open(&mut fm, heap.first_page(), schema())?;
+        let rows: Vec<Tuple> = heap.scan(&mut fm).collect::<DBResult<_>>()?;
+        let expected: Vec<Tuple> = (0..20).filter(|&i| i != 7).map(row).collect();
+        assert_eq!(rows, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(128, 16)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+        for i in 0..20 {
+            heap.insert(&mut fm, &row(i))?;
+        }
+        let pages = heap.page_ids(&mut fm)?;
+        assert!(pages.len() > 1);
+
+        heap.truncate(&mut fm)?;
+        assert_eq!(heap.page_ids(&mut fm)?, &pages[..1]);
+        assert_eq!(heap.scan(&mut fm).count(), 0);
+
+        // The pages after the first are reused as the heap file grows again
+        for i in 0..20 {
+            heap.insert(&mut fm, &row(i))?;
+        }
+        let mut reused = heap.page_ids(&mut fm)?;
+        reused.sort_unstable();
+        assert_eq!(reused, pages);
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_aggregates() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(128, 16)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+        let mut table = Table::new("rows".to_string(), schema());
+        for i in 0..40 {
+            heap.insert(&mut fm, &row(i))?;
+            table.insert(row(i))?;
+        }
+        assert!(heap.page_ids(&mut fm)?.len() > 1);
+
+        let aggregates = [
+            Aggregate::Count,
+            Aggregate::Sum,
+            Aggregate::Min,
+            Aggregate::Max,
+            Aggregate::Avg,
+        ];
+        for aggregate in aggregates {
+            assert_eq!(
+                heap.scan(&mut fm).fold_aggregate(0, aggregate)?,
+                table.aggregate(0, aggregate)?
+            );
+        }
+
+        // Group by the Bool column, and compare each group against the
+        // matching part of the in-memory table
+        let groups = heap
+            .scan(&mut fm)
+            .group_aggregate(&[2], 0, Aggregate::Sum)?;
+        let (flagged, unflagged) = table.partition(|tuple| tuple.values()[2] == Value::Bool(true));
+        assert_eq!(groups.len(), 2);
+        for (flag, part) in [(true, flagged), (false, unflagged)] {
+            let key = Tuple::new(vec![Value::Bool(flag)]);
+            assert_eq!(groups[&key], part.aggregate(0, Aggregate::Sum)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_factor() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(256, 16)?;
+        let schema = Schema::new(vec![Type::BigInt]);
+        assert!(HeapFile::create(&mut fm, schema.clone())?
+            .with_fill_factor(0)
+            .is_err());
+        let mut heap = HeapFile::create(&mut fm, schema)?.with_fill_factor(80)?;
+        for i in 0..40 {
+            heap.insert(&mut fm, &Tuple::new(vec![Value::BigInt(i)]))?;
+        }
+
+        // Each full page has room for more 9 byte tuples and their slots, but
+        // stops before using more than 80% of the page
+        let page_ids = heap.page_ids(&mut fm)?;
+        let capacity = slotted_page_size(&fm)?;
+        for &page_id in &page_ids[..page_ids.len() - 1] {
+            let (_, page) = read_heap_page(&mut fm, page_id)?;
+            let used = capacity - page.free_space();
+            assert!(page.free_space() >= 13);
+            assert!(used <= capacity * 80 / 100);
+            assert!(used + 13 > capacity * 80 / 100);
+        }
+        assert_eq!(heap.scan(&mut fm).count(), 40);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_tuple_fit() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 16)?;
+        let schema = Schema::new(vec![Type::Int, Type::Varchar(200)]);
+        let heap = HeapFile::create(&mut fm, schema.clone())?;
+
+        let small = Tuple::new(vec![Value::Int(1), Value::Varchar("hi".to_string())]);
+        let large = Tuple::new(vec![Value::Int(1), Value::Varchar("x".repeat(100))]);
+        for (tuple, fits) in [(&small, true), (&large, false)] {
+            let fit = heap.check_tuple_fit(&fm, tuple)?;
+            assert_eq!(fit.size(), serialize_tuple(tuple, &schema)?.len());
+            assert_eq!(matches!(fit, TupleFit::Inline(_)), fits);
+            assert_eq!(matches!(fit, TupleFit::NeedsOverflow(_)), !fits);
+        }
+
+        // Fixed-size values cannot be moved to overflow pages
+        let wide = Schema::new(vec![Type::BigInt; 8]);
+        let heap = HeapFile::create(&mut fm, wide)?;
+        let tuple = Tuple::new(vec![Value::BigInt(0); 8]);
+        assert_eq!(
+            heap.check_tuple_fit(&fm, &tuple)?,
+            TupleFit::TooLargeEvenForOverflow(65)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reverse_scan() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(128, 16)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+        let rids: Vec<RecordId> = (0..20)
+            .map(|i| heap.insert(&mut fm, &row(i)))
+            .collect::<DBResult<_>>()?;
+        heap.delete(&mut fm, rids[3])?;
+        assert!(heap.page_ids(&mut fm)?.len() > 1);
+
+        let mut forward: Vec<Tuple> = heap.scan(&mut fm).collect::<DBResult<_>>()?;
+        let reverse: Vec<Tuple> = heap.scan_rev(&mut fm)?.collect::<DBResult<_>>()?;
+        forward.reverse();
+        assert_eq!(reverse, forward);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_size() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(128, 32)?;
+        let schema = Schema::new(vec![Type::Int, Type::Blob]);
+        let mut heap = HeapFile::create(&mut fm, schema)?;
+        for i in 0..20 {
+            let blob = if i % 5 == 0 {
+                Value::Blob(fm.store_blob(&[i as u8; 300])?)
+            } else {
+                Value::Null
+            };
+            heap.insert(&mut fm, &Tuple::new(vec![Value::Int(i), blob]))?;
+        }
+
+        // Every allocated page belongs to the heap file or one of its blobs
+        let data_pages = heap.page_ids(&mut fm)?.len() as u64;
+        assert!(data_pages > 1);
+        assert!(fm.num_pages() > data_pages);
+        assert_eq!(heap.disk_size(&mut fm)?, fm.num_pages() * 128);
+        Ok(())
+    }
+
+    #[test]
+    fn test_heap_file_rejects_oversized_tuple() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(32, 16)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+
+        let tuple = Tuple::new(vec![
+            Value::Int(1),
+            Value::Varchar("far too long".to_string()),
+            Value::Bool(true),
+        ]);
+        assert!(heap.insert(&mut fm, &tuple).is_err());
+        assert_eq!(heap.page_ids(&mut fm)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_and_reclaim_orphaned_pages() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(64, 16)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+        heap.insert(&mut fm, &row(1))?;
+
+        // Lose the only reference to a blob without deleting it
+        fm.store_blob(&[7; 100])?;
+        let blob_pages: Vec<u64> = (heap.first_page() + 1..=fm.num_pages()).collect();
+        assert_eq!(blob_pages.len(), 2);
+
+        let reachable = heap.page_ids(&mut fm)?;
+        assert_eq!(fm.find_orphaned_pages(&reachable), blob_pages);
+
+        fm.reclaim_orphans(&blob_pages)?;
+        assert!(fm.find_orphaned_pages(&reachable).is_empty());
+        assert!(blob_pages.contains(&fm.allocate_page()?));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recover_skips_corrupt_page() -> DBResult<()> {
+        use std::os::unix::fs::FileExt;
+
+        use crate::storage::OpenMode;
+
+        let path = "heap_test_recover.db";
+        let page_size = 128;
+        let (first_page, corrupt_page, corrupt_rows) = {
+            let mut fm = FileManager::new(path, page_size, 16)?;
+            let mut heap = HeapFile::create(&mut fm, schema())?;
+            let rids: Vec<RecordId> = (0..20)
+                .map(|i| heap.insert(&mut fm, &row(i)))
+                .collect::<DBResult<_>>()?;
+
+            let page_ids = heap.page_ids(&mut fm)?;
+            assert!(page_ids.len() > 2);
+            let corrupt_page = page_ids[1];
+            let corrupt_rows: Vec<i32> = (0..20)
+                .filter(|&i| rids[i as usize].page_id == corrupt_page)
+                .collect();
+            (heap.first_page(), corrupt_page, corrupt_rows)
+        };
+
+        // Overwrite the slot count of the middle page of the chain
+        let file = std::fs::OpenOptions::new().write(true).open(path)?;
+        let offset = corrupt_page * page_size as u64 + NEXT_PAGE_SIZE as u64;
+        file.write_at(&[0xFF, 0xFF], offset)?;
+        drop(file);
+
+        // Scanning fails in strict mode
+        let mut fm = FileManager::new(path, page_size, 16)?;
+        let heap = HeapFile::open(&mut fm, first_page, schema())?;
+        assert!(heap.scan(&mut fm).collect::<DBResult<Vec<_>>>().is_err());
+        drop(fm);
+
+        // Recovery mode yields the tuples of every other page
+        let mut fm = FileManager::open(path, page_size, 16, OpenMode::Recover)?;
+        let heap = HeapFile::open(&mut fm, first_page, schema())?;
+        let rows: Vec<Tuple> = heap.scan(&mut fm).collect::<DBResult<_>>()?;
+        let expected: Vec<Tuple> = (0..20)
+            .filter(|i| !corrupt_rows.contains(i))
+            .map(row)
+            .collect();
+        assert_eq!(rows, expected);
+        assert_eq!(fm.corrupt_items(), [CorruptItem::Page(corrupt_page)]);
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_filtered_scan() -> DBResult<()> {
+        let mut fm = FileManager::in_memory(128, 16)?;
+        let mut heap = HeapFile::create(&mut fm, schema())?;
+        for i in 0..30 {
+            heap.insert(&mut fm, &row(i))?;
+        }
+
+        let is_flagged = |tuple: &Tuple| tuple.values()[2] == Value::Bool(true);
+
+        let mut expected = Vec::new();
+        for tuple in heap.scan(&mut fm) {
+            let tuple = tuple?;
+            if is_flagged(&tuple) {
+                expected.push(tuple);
+            }
+        }
+
+        let mut cursor = heap.scan(&mut fm).filter(&[2], is_flagged)?;
+        let rows: Vec<Tuple> = cursor.by_ref().collect::<DBResult<_>>()?;
+        assert_eq!(rows, expected);
+
+        // Only the matching rows were fully deserialized
+        assert_eq!(cursor.deserializations(), expected.len());
+        assert!(cursor.deserializations() < 30);
+
+        // Columns are checked before anything is read
+        assert!(matches!(
+            heap.scan(&mut fm).filter(&[2, 3], is_flagged),
+            Err(DBError::StorageError(StorageError::InvalidArgument(_)))
+        ));
+        Ok(())
+    }
+}
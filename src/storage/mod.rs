@@ -1,6 +1,40 @@
-mod tuple;
+pub mod tuple;
 
 mod file_manager;
-pub use file_manager::FileManager;
+pub use file_manager::{
+    BufferPoolStats, CorruptItem, EvictionPolicy, FileManager, FreeListIssue, OpenMode, PinnedPage,
+    PoolPressure, FORMAT_VERSION,
+};
+
+mod backend;
+
+mod fd_pool;
+pub use fd_pool::FdPool;
+
+mod page_source;
+pub use page_source::PageSource;
+
+mod replacer;
+pub use replacer::{FifoReplacer, LruReplacer, Replacer};
+
+mod access_trace;
+pub use access_trace::{replay, AccessEvent};
+
+mod shared_file_manager;
+pub use shared_file_manager::SharedFileManager;
+
+mod blob;
+pub use blob::BlobHandle;
+
+mod heap_file;
+pub use heap_file::{FilteredCursor, HeapFile, OnBadRow, RecordId, TableCursor, TupleFit};
+
+mod page_frame;
+
+mod page_layout;
+pub use page_layout::PageLayout;
+
+mod slotted_page;
+pub use slotted_page::SlottedPage;
 
 pub mod error;
@@ -0,0 +1,237 @@
+//! The on-disk frame of a page whose contents are checksummed and
+//! compressed, as enabled by `FileManager::with_checksums_and_compression`.
+//! Both features share this one layout:
+//!
+//! ```text
+//! [checksum: u32][uncompressed_len: u32][compressed payload...][padding]
+//! ```
+//!
+//! The checksum is the CRC-32 of everything after it in the page, so it
+//! covers the length, the compressed bytes, and the zero padding which fills
+//! the rest of the page. It is verified before anything is decompressed, so
+//! corrupt bytes are never passed to the decompressor.
+//!
+//! A page of all zeros, as left on disk by growing the file or punching a
+//! hole, is not a valid frame; it is read as a page of zeros.
+//!
+//! The payload is compressed with PackBits run-length encoding, which suits
+//! pages that are mostly zeros or repeated bytes. If the compressed payload
+//! would not fit in the page, it is stored uncompressed instead and the top
+//! bit of `uncompressed_len` is set.
+
+use std::ops::Range;
+
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+
+/// Size in bytes of the checksum and length at the start of each frame.
+pub(super) const FRAME_HEADER_SIZE: usize = 8;
+
+/// Set in the length field of frames whose payload is not compressed.
+const RAW_FLAG: u32 = 1 << 31;
+
+/// Encodes the bytes of an in-memory page as a frame of the same length.
+/// The page's `reserved` bytes, which hold nothing in memory, make room for
+/// the frame header and are not stored.
+pub(super) fn encode_page(data: &[u8], reserved: &Range<usize>) -> Vec<u8> {
+    let body = [&data[..reserved.start], &data[reserved.end..]].concat();
+    let capacity = data.len() - FRAME_HEADER_SIZE;
+
+    let compressed = pack_bits(&body);
+    let (len, payload) = if compressed.len() <= capacity {
+        (body.len() as u32, compressed)
+    } else {
+        (body.len() as u32 | RAW_FLAG, body)
+    };
+
+    let mut frame = vec![0; data.len()];
+    frame[4..8].copy_from_slice(&len.to_le_bytes());
+    frame[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + payload.len()].copy_from_slice(&payload);
+    let checksum = crc32fast::hash(&frame[4..]);
+    frame[..4].copy_from_slice(&checksum.to_le_bytes());
+    frame
+}
+
+/// Decodes a frame read from disk back into the bytes of the in-memory page
+/// with the given `page_id`, with its `reserved` bytes zeroed. Returns an
+/// error if the checksum does not match or the payload is invalid.
+pub(super) fn decode_page(
+    frame: &[u8],
+    reserved: &Range<usize>,
+    page_id: u64,
+) -> DBResult<Vec<u8>> {
+    if frame.iter().all(|&b| b == 0) {
+        return Ok(frame.to_vec());
+    }
+
+    let checksum = u32::from_le_bytes(frame[..4].try_into().unwrap());
+    if crc32fast::hash(&frame[4..]) != checksum {
+        trace_event!(warn, page_id, "page checksum mismatch");
+        return Err(DBError::from(StorageError::ChecksumMismatch(page_id)));
+    }
+
+    let len = u32::from_le_bytes(frame[4..8].try_into().unwrap());
+    let body_len = frame.len() - FRAME_HEADER_SIZE;
+    let payload = &frame[FRAME_HEADER_SIZE..];
+    let body = if len == body_len as u32 | RAW_FLAG {
+        Some(payload.to_vec())
+    } else if len == body_len as u32 {
+        unpack_bits(payload, body_len)
+    } else {
+        None
+    };
+    let body = body.ok_or_else(|| {
+        DBError::from(StorageError::DeserializationError(format!(
+            "invalid compressed payload in page {}.",
+            page_id
+        )))
+    })?;
+
+    let mut data = Vec::with_capacity(frame.len());
+    data.extend_from_slice(&body[..reserved.start]);
+    data.resize(reserved.end, 0);
+    data.extend_from_slice(&body[reserved.start..]);
+    Ok(data)
+}
+
+/// Compresses `input` with PackBits: each run of 2 to 128 equal bytes is
+/// stored as a header byte of `257 - run_len` followed by the byte, and each
+/// run of up to 128 other bytes as a header byte of `run_len - 1` followed
+/// by the bytes themselves.
+fn pack_bits(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let mut run = 1;
+        while i + run < input.len() && run < 128 && input[i + run] == input[i] {
+            run += 1;
+        }
+
+        if run >= 2 {
+            out.push((257 - run) as u8);
+            out.push(input[i]);
+            i += run;
+        } else {
+            // Take literal bytes until the next repeated run starts
+            let start = i;
+            i += 1;
+            while i < input.len()
+                && i - start < 128
+                && !(i + 1 < input.len() && input[i] == input[i + 1])
+            {
+                i += 1;
+            }
+            out.push((i - start - 1) as u8);
+            out.extend_from_slice(&input[start..i]);
+        }
+    }
+    out
+}
+
+/// Decompresses exactly `len` bytes compressed by `pack_bits` from the start
+/// of `input`, ignoring any bytes after them. Returns `None` if `input` is
+/// truncated or would decompress to more than `len` bytes.
+fn unpack_bits(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while out.len() < len {
+        let header = *input.get(i)?;
+        i += 1;
+        match header {
+            0..=127 => {
+                let run = header as usize + 1;
+                out.extend_from_slice(input.get(i..i + run)?);
+                i += run;
+            }
+            128 => {}
+            129..=255 => {
+                let run = 257 - header as usize;
+                out.resize(out.len() + run, *input.get(i)?);
+                i += 1;
+            }
+        }
+    }
+
+    (out.len() == len).then_some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{remove_file, OpenOptions};
+    use std::io::{Seek, SeekFrom, Write};
+
+    use super::*;
+    use crate::storage::FileManager;
+
+    #[test]
+    fn test_pack_bits_round_trip() {
+        let inputs: [Vec<u8>; 4] = [
+            vec![0; 300],
+            (0..=255).collect(),
+            b"aaabcdddddddde".to_vec(),
+            vec![],
+        ];
+        for input in inputs {
+            let packed = pack_bits(&input);
+            assert_eq!(unpack_bits(&packed, input.len()), Some(input));
+        }
+    }
+
+    #[test]
+    fn test_frame_round_trip() -> DBResult<()> {
+        let reserved = 0..FRAME_HEADER_SIZE;
+
+        // Compressible and incompressible pages both fit in the frame
+        let mut zeros = vec![0; 64];
+        zeros[20] = 7;
+        let noise: Vec<u8> = (0..64).map(|i| (i * 37 % 251) as u8).collect();
+        for page in [zeros, noise] {
+            let mut data = page.clone();
+            data[reserved.clone()].fill(0);
+            let frame = encode_page(&data, &reserved);
+            assert_eq!(frame.len(), 64);
+            assert_eq!(decode_page(&frame, &reserved, 1)?, data);
+        }
+
+        // Pages which were never written read back as zeros
+        assert_eq!(decode_page(&[0; 64], &reserved, 1)?, vec![0; 64]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksummed_compressed_pages() -> DBResult<()> {
+        let path = "frame_test.db";
+        let page_size = 256;
+        let mut data = vec![0; page_size - FRAME_HEADER_SIZE];
+        data[..5].copy_from_slice(b"hello");
+        {
+            let mut fm = FileManager::new(path, page_size, 4)?.with_checksums_and_compression()?;
+            let page_id = fm.allocate_page()?;
+            fm.write_page_to_pool(page_id, &data)?;
+        }
+
+        let mut fm = FileManager::new(path, page_size, 4)?.with_checksums_and_compression()?;
+        assert_eq!(fm.read_page(1)?, &data[..]);
+        drop(fm);
+
+        // Corrupt the compressed run of zeros, which would otherwise decompress
+        // to far more bytes than the page holds
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(
+            page_size as u64 + FRAME_HEADER_SIZE as u64 + 6,
+        ))?;
+        file.write_all(&[0x81])?;
+        drop(file);
+
+        let mut fm = FileManager::new(path, page_size, 4)?.with_checksums_and_compression()?;
+        assert!(matches!(
+            fm.read_page(1),
+            Err(DBError::StorageError(StorageError::ChecksumMismatch(1)))
+        ));
+
+        drop(fm);
+        remove_file(path)?;
+        Ok(())
+    }
+}
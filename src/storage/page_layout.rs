@@ -0,0 +1,93 @@
+use std::ops::Range;
+
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+
+/// A `PageLayout` divides each page of a database file into a reserved
+/// header at the start of the page, followed by the payload which callers of
+/// `FileManager` read and write.
+///
+/// Features that need to store their own data in every page, such as
+/// checksums, reserve a region of the header with `reserve`; each call
+/// returns a distinct region, so features cannot overwrite each other's
+/// bytes or the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageLayout {
+    page_size: usize,
+    header_size: usize,
+}
+
+impl PageLayout {
+    /// Creates a new `PageLayout` for pages of `page_size` bytes with no
+    /// reserved header, so the whole page is payload.
+    pub fn new(page_size: usize) -> Self {
+        PageLayout {
+            page_size,
+            header_size: 0,
+        }
+    }
+
+    /// Reserves `len` more bytes at the end of the header and returns their
+    /// range within the page. The payload shrinks accordingly; an error is
+    /// returned if this would leave no payload.
+    pub fn reserve(&mut self, len: usize) -> DBResult<Range<usize>> {
+        if self.header_size + len >= self.page_size {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "cannot reserve {} more header bytes in a {} byte page with a {} byte header.",
+                len, self.page_size, self.header_size
+            ))));
+        }
+
+        let start = self.header_size;
+        self.header_size += len;
+        Ok(start..self.header_size)
+    }
+
+    /// Returns the size in bytes of a whole page, including the header.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Returns the size in bytes of the reserved header.
+    pub fn header_size(&self) -> usize {
+        self.header_size
+    }
+
+    /// Returns the offset of the payload from the start of the page.
+    pub fn payload_offset(&self) -> usize {
+        self.header_size
+    }
+
+    /// Returns the size in bytes of the payload.
+    pub fn payload_len(&self) -> usize {
+        self.page_size - self.header_size
+    }
+
+    /// Returns the range of the payload within the page.
+    pub fn payload_range(&self) -> Range<usize> {
+        self.header_size..self.page_size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_page_layout_reserve() -> DBResult<()> {
+        let mut layout = PageLayout::new(64);
+        assert_eq!(layout.payload_range(), 0..64);
+
+        assert_eq!(layout.reserve(4)?, 0..4);
+        assert_eq!(layout.reserve(8)?, 4..12);
+        assert_eq!(layout.header_size(), 12);
+        assert_eq!(layout.payload_offset(), 12);
+        assert_eq!(layout.payload_len(), 52);
+
+        // The payload cannot be reserved away entirely
+        assert!(layout.reserve(52).is_err());
+        assert_eq!(layout.payload_len(), 52);
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+//! Pluggable storage for the pages of a `FileManager`, so that it can act as
+//! a cache in front of storage other than its own file.
+
+use crate::DBResult;
+
+/// A `PageSource` stores the pages of a `FileManager` in place of its file,
+/// as set by `FileManager::set_page_source`. The `FileManager` still keeps
+/// its metadata page in the file, and pages are read and written through its
+/// buffer pool as usual; the source is only used to load pages which are not
+/// in the pool and to store dirty pages when they are flushed.
+///
+/// Bytes are passed exactly as they would be stored in the file, including
+/// any checksum and compression frame, and always as whole pages.
+pub trait PageSource: Send {
+    /// Reads the page with the given `page_id` into `buf`, which is one page
+    /// long. Pages which have never been stored should read as zeros.
+    fn load(&self, page_id: u64, buf: &mut [u8]) -> DBResult<()>;
+
+    /// Stores `buf` as the contents of the page with the given `page_id`.
+    fn store(&self, page_id: u64, buf: &[u8]) -> DBResult<()>;
+}
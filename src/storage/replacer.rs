@@ -0,0 +1,101 @@
+//! Pluggable page replacement for the buffer pool of a `FileManager`, for
+//! workloads which the built-in `EvictionPolicy`s do not suit.
+
+use std::collections::VecDeque;
+
+/// A `Replacer` chooses which page a `FileManager` evicts from its buffer
+/// pool when it is full, in place of its `EvictionPolicy`, as set by
+/// `FileManager::with_replacer`.
+///
+/// The `FileManager` reports each read, write, or pin of a page in the pool
+/// to `record_access`, and each page which leaves the pool for any reason to
+/// `remove`. Pages may be in the pool without having been accessed, such as
+/// pages which were read ahead.
+pub trait Replacer: Send {
+    /// Records a use of the page with the given `page_id`.
+    fn record_access(&mut self, page_id: u64);
+
+    /// Returns the page to evict out of `unpinned`, the ids of the pages
+    /// which can be evicted in ascending order, or `None` to evict nothing.
+    /// Returning a page which is not in `unpinned` also evicts nothing.
+    fn evict(&mut self, unpinned: &[u64]) -> Option<u64>;
+
+    /// Forgets the page with the given `page_id`, which has left the pool.
+    fn remove(&mut self, page_id: u64);
+}
+
+/// A `Replacer` which evicts pages in the order they were first used after
+/// entering the pool, however often they have been used since. Pages which
+/// have not been used are evicted first, lowest id first.
+#[derive(Debug, Default)]
+pub struct FifoReplacer {
+    order: VecDeque<u64>,
+}
+
+impl FifoReplacer {
+    /// Creates a new `FifoReplacer` which has not seen any pages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Replacer for FifoReplacer {
+    fn record_access(&mut self, page_id: u64) {
+        if !self.order.contains(&page_id) {
+            self.order.push_back(page_id);
+        }
+    }
+
+    fn evict(&mut self, unpinned: &[u64]) -> Option<u64> {
+        first_in_order(&self.order, unpinned)
+    }
+
+    fn remove(&mut self, page_id: u64) {
+        self.order.retain(|&id| id != page_id);
+    }
+}
+
+/// A `Replacer` which evicts the least recently used page. Pages which have
+/// not been used are evicted first, lowest id first.
+#[derive(Debug, Default)]
+pub struct LruReplacer {
+    /// Pages from least to most recently used.
+    order: VecDeque<u64>,
+}
+
+impl LruReplacer {
+    /// Creates a new `LruReplacer` which has not seen any pages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Replacer for LruReplacer {
+    fn record_access(&mut self, page_id: u64) {
+        self.remove(page_id);
+        self.order.push_back(page_id);
+    }
+
+    fn evict(&mut self, unpinned: &[u64]) -> Option<u64> {
+        first_in_order(&self.order, unpinned)
+    }
+
+    fn remove(&mut self, page_id: u64) {
+        self.order.retain(|&id| id != page_id);
+    }
+}
+
+/// Returns the lowest page of `unpinned` which is not in `order` if there is
+/// one, and otherwise the first page of `order` which is in `unpinned`.
+/// `unpinned` must be sorted.
+fn first_in_order(order: &VecDeque<u64>, unpinned: &[u64]) -> Option<u64> {
+    unpinned
+        .iter()
+        .find(|page_id| !order.contains(page_id))
+        .or_else(|| {
+            order
+                .iter()
+                .find(|page_id| unpinned.binary_search(page_id).is_ok())
+        })
+        .copied()
+}
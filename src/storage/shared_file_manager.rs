@@ -0,0 +1,240 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+use super::FileManager;
+
+/// A `SharedFileManager` is a handle to a `FileManager` which can be cloned
+/// and shared between threads. Each operation locks the `FileManager` for
+/// its duration.
+///
+/// If the `FileManager` has an eviction timeout, operations that fail because
+/// every page in the buffer pool is pinned are retried until the timeout
+/// elapses. The lock is released between attempts so that other threads can
+/// unpin pages in the meantime.
+#[derive(Clone)]
+pub struct SharedFileManager {
+    inner: Arc<Mutex<FileManager>>,
+    commits: Arc<(Mutex<CommitState>, Condvar)>,
+}
+
+/// Tracks the commits made through a `SharedFileManager` so that they can be
+/// batched. Commits are numbered in the order they are requested.
+#[derive(Default)]
+struct CommitState {
+    /// Number of commits requested so far.
+    requested: u64,
+    /// Number of the last commit known to be durable.
+    durable: u64,
+    /// Whether a thread is currently waiting for or running a sync.
+    syncing: bool,
+}
+
+impl SharedFileManager {
+    /// Creates a new `SharedFileManager` which takes ownership of `fm`.
+    pub fn new(fm: FileManager) -> Self {
+        SharedFileManager {
+            inner: Arc::new(Mutex::new(fm)),
+            commits: Arc::new((Mutex::new(CommitState::default()), Condvar::new())),
+        }
+    }
+
+    /// Reads a page through the buffer pool and returns a copy of its bytes.
+    /// A copy is returned because the page may be evicted by another thread
+    /// as soon as the lock is released.
+    pub fn read_page(&self, page_id: u64) -> DBResult<Vec<u8>> {
+        self.with_retry(|fm| fm.read_page(page_id).map(|data| data.to_vec()))
+    }
+
+    /// Allocates a new page; see `FileManager::allocate_page`.
+    pub fn allocate_page(&self) -> DBResult<u64> {
+        self.with_retry(|fm| fm.allocate_page())
+    }
+
+    /// Pins a page in the buffer pool; see `FileManager::pin_page`.
+    pub fn pin_page(&self, page_id: u64) -> DBResult<()> {
+        self.with_retry(|fm| fm.pin_page(page_id))
+    }
+
+    /// Unpins a page in the buffer pool; see `FileManager::unpin_page`.
+    pub fn unpin_page(&self, page_id: u64) -> DBResult<Option<u16>> {
+        Ok(self.lock()?.unpin_page(page_id))
+    }
+
+    /// Flushes a page to disk if it is dirty; see `FileManager::flush_page`.
+    pub fn flush_page(&self, page_id: u64) -> DBResult<()> {
+        self.lock()?.flush_page(page_id)
+    }
+
+    /// Flushes all dirty pages to disk; see `FileManager::flush_all_pages`.
+    pub fn flush_all_pages(&self) -> DBResult<()> {
+        self.lock()?.flush_all_pages()
+    }
+
+    /// Makes all changes made so far durable by flushing all pages and
+    /// syncing the file, and returns once they are.
+    ///
+    /// If the `FileManager` has group commit enabled, concurrent commits
+    /// are batched: the first thread to commit waits for the group commit
+    /// window to elapse or the batch to fill, and then syncs once for every
+    /// commit in the batch while the other threads wait for it.
+    pub fn commit(&self) -> DBResult<()> {
+        let Some((window, max_batch)) = self.lock()?.group_commit() else {
+            return self.flush_all_pages();
+        };
+
+        let (commits, synced) = &*self.commits;
+        let mut state = commits.lock().map_err(poisoned)?;
+        state.requested += 1;
+        let ticket = state.requested;
+        synced.notify_all();
+
+        while state.durable < ticket {
+            if state.syncing {
+                state = synced.wait(state).map_err(poisoned)?;
+                continue;
+            }
+
+            // Lead a batch, giving other commits a chance to join it
+            state.syncing = true;
+            let deadline = Instant::now() + window;
+            while state.requested - state.durable < max_batch as u64 {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = synced
+                    .wait_timeout(state, deadline - now)
+                    .map_err(poisoned)?
+                    .0;
+            }
+            let batch_end = state.requested;
+
+            drop(state);
+            let result = self.flush_all_pages();
+            state = commits.lock().map_err(poisoned)?;
+
+            // If the sync failed, the batch stays pending and another commit
+            // leads the next attempt
+            state.syncing = false;
+            if result.is_ok() {
+                state.durable = state.durable.max(batch_end);
+            }
+            synced.notify_all();
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Locks the `FileManager`, returning an error if another thread
+    /// panicked while holding the lock.
+    fn lock(&self) -> DBResult<MutexGuard<'_, FileManager>> {
+        self.inner.lock().map_err(poisoned)
+    }
+
+    /// Runs `op` against the `FileManager`, retrying while it fails with
+    /// `BufferPoolFull` until the eviction timeout elapses.
+    fn with_retry<T>(&self, mut op: impl FnMut(&mut FileManager) -> DBResult<T>) -> DBResult<T> {
+        let start = Instant::now();
+        loop {
+            let mut fm = self.lock()?;
+            match op(&mut fm) {
+                Err(DBError::StorageError(StorageError::BufferPoolFull(_)))
+                    if fm
+                        .eviction_timeout()
+                        .is_some_and(|timeout| start.elapsed() < timeout) =>
+                {
+                    drop(fm);
+                    thread::yield_now();
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Returns the error for a lock which was poisoned by a thread panicking
+/// while holding it.
+fn poisoned<T>(_: T) -> DBError {
+    DBError::from(StorageError::LockPoisoned)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_allocate_waits_for_unpin() -> DBResult<()> {
+        let fm = FileManager::in_memory(64, 2)?.with_eviction_timeout(Duration::from_secs(5));
+        let shared = SharedFileManager::new(fm);
+
+        // Fill the pool with pinned pages
+        let first = shared.allocate_page()?;
+        let second = shared.allocate_page()?;
+        shared.flush_page(first)?;
+        shared.flush_page(second)?;
+        shared.pin_page(first)?;
+        shared.pin_page(second)?;
+
+        let unpinner = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                shared.unpin_page(first)
+            })
+        };
+
+        let result = shared.allocate_page();
+        unpinner.join().unwrap()?;
+
+        assert!(result.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_commit_batches_syncs() -> DBResult<()> {
+        let fm = FileManager::in_memory(64, 8)?.with_group_commit(Duration::from_millis(20), 8);
+        let shared = SharedFileManager::new(fm);
+
+        let committers: Vec<_> = (0..32)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || shared.commit())
+            })
+            .collect();
+        for committer in committers {
+            committer.join().unwrap()?;
+        }
+
+        // Every commit was made durable, with far fewer syncs than commits
+        assert_eq!(shared.commits.0.lock().unwrap().durable, 32);
+        let stats = shared.lock()?.stats();
+        assert!(stats.syncs >= 1);
+        assert!(stats.syncs < 32 / 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_without_timeout_fails() -> DBResult<()> {
+        let shared = SharedFileManager::new(FileManager::in_memory(64, 2)?);
+
+        let first = shared.allocate_page()?;
+        let second = shared.allocate_page()?;
+        shared.flush_page(first)?;
+        shared.flush_page(second)?;
+        shared.pin_page(first)?;
+        shared.pin_page(second)?;
+
+        let result = shared.allocate_page();
+        assert!(matches!(
+            result,
+            Err(DBError::StorageError(StorageError::BufferPoolFull(_)))
+        ));
+        Ok(())
+    }
+}
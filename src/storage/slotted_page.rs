@@ -0,0 +1,401 @@
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+
+/// Size in bytes of the fixed header at the start of a `SlottedPage`: the
+/// number of slots followed by the offset where tuple data begins.
+const HEADER_SIZE: usize = 4;
+
+/// Size in bytes of a single entry in the slot directory: the offset of the
+/// tuple within the page followed by its length.
+const SLOT_SIZE: usize = 4;
+
+/// A `SlottedPage` stores variable-length tuples within a single page.
+///
+/// The page begins with a header and a slot directory which grows forward;
+/// tuple data is written from the end of the page and grows backward. Each
+/// tuple is identified by its slot number, which never changes for as long
+/// as the tuple is in the page, so slot numbers can be used in record IDs.
+///
+/// Layout (all integers are little-endian `u16`s):
+///
+/// ```text
+/// [num_slots][data_start][slot 0: offset, len][slot 1: offset, len]...
+/// ...free space...[tuple data]
+/// ```
+///
+/// A deleted slot has an offset and length of 0, and may be reused by a
+/// later insert.
+pub struct SlottedPage {
+    data: Vec<u8>,
+    auto_compact: bool,
+    fill_factor: u8,
+}
+
+impl SlottedPage {
+    /// Creates a new empty `SlottedPage` of `page_size` bytes. `page_size`
+    /// must be large enough to hold the page header and no larger than
+    /// `u16::MAX`, since offsets within the page are stored as `u16`s.
+    pub fn new(page_size: usize) -> DBResult<Self> {
+        Self::check_page_size(page_size)?;
+
+        let mut page = SlottedPage {
+            data: vec![0; page_size],
+            auto_compact: true,
+            fill_factor: 100,
+        };
+        page.set_num_slots(0);
+        page.set_data_start(page_size as u16);
+        Ok(page)
+    }
+
+    /// Interprets `data` as an existing `SlottedPage`, such as one that was
+    /// read from disk. Returns an error if the header is inconsistent with
+    /// the size of `data`.
+    pub fn from_bytes(data: Vec<u8>) -> DBResult<Self> {
+        Self::check_page_size(data.len())?;
+
+        let page = SlottedPage {
+            data,
+            auto_compact: true,
+            fill_factor: 100,
+        };
+
+        let directory_end = HEADER_SIZE + page.num_slots() as usize * SLOT_SIZE;
+        let data_start = page.data_start() as usize;
+        if directory_end > data_start || data_start > page.data.len() {
+            return Err(DBError::from(StorageError::InvalidArgument(
+                "invalid SlottedPage: slot directory overlaps tuple data.".to_string(),
+            )));
+        }
+
+        for slot in 0..page.num_slots() {
+            let (offset, len) = page.slot(slot);
+            let (offset, len) = (offset as usize, len as usize);
+            if len != 0 && (offset < data_start || offset + len > page.data.len()) {
+                return Err(DBError::from(StorageError::InvalidArgument(format!(
+                    "invalid SlottedPage: slot {} points outside of the tuple data.",
+                    slot
+                ))));
+            }
+        }
+
+        Ok(page)
+    }
+
+    /// Returns the raw bytes of this page, suitable for writing to disk.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Sets whether `insert` should compact the page when a tuple would fit
+    /// in the page's total free space but not in its contiguous free space.
+    /// This is enabled by default.
+    pub fn set_auto_compact(&mut self, enabled: bool) {
+        self.auto_compact = enabled;
+    }
+
+    /// Sets the percentage of the page that `insert` may fill, leaving the
+    /// rest free for tuples to grow in place. An insert which would take the
+    /// space used by the header, slots, and live tuples past this
+    /// percentage of the page fails as if the page were full, unless the
+    /// page has no live tuples. This is 100 by default. Returns an error if
+    /// `fill_factor` is not between 1 and 100.
+    pub fn set_fill_factor(&mut self, fill_factor: u8) -> DBResult<()> {
+        check_fill_factor(fill_factor)?;
+        self.fill_factor = fill_factor;
+        Ok(())
+    }
+
+    /// Returns the number of slots in the slot directory, including slots
+    /// that have been deleted.
+    pub fn num_slots(&self) -> u16 {
+        self.read_u16(0)
+    }
+
+    /// Returns the length in bytes of the largest tuple which fits in an empty
+    /// `SlottedPage` of `page_size` bytes, along with its slot.
+    pub fn max_tuple_len(page_size: usize) -> usize {
+        page_size.saturating_sub(HEADER_SIZE + SLOT_SIZE)
+    }
+
+    /// Returns the number of contiguous free bytes between the end of the
+    /// slot directory and the start of the tuple data.
+    pub fn free_space(&self) -> usize {
+        self.data_start() as usize - self.directory_end()
+    }
+
+    /// Inserts `tuple` into the page and returns its slot number. Deleted
+    /// slots are reused before a new slot is added to the directory.
+    ///
+    /// If the tuple does not fit in the contiguous free space but would fit
+    /// after compacting, and auto-compaction is enabled, the page is
+    /// compacted first. Otherwise, an error is returned and the page is left
+    /// unchanged.
+    pub fn insert(&mut self, tuple: &[u8]) -> DBResult<u16> {
+        if tuple.is_empty() {
+            return Err(DBError::from(StorageError::InvalidArgument(
+                "cannot insert an empty tuple into a SlottedPage.".to_string(),
+            )));
+        }
+
+        let free_slot = (0..self.num_slots()).find(|&slot| self.slot(slot).1 == 0);
+        let needed = tuple.len() + if free_slot.is_some() { 0 } else { SLOT_SIZE };
+
+        if self.fill_factor < 100 && (0..self.num_slots()).any(|slot| self.slot(slot).1 > 0) {
+            let used = self.data.len() - self.free_space() - self.fragmented_space();
+            if used + needed > self.data.len() * self.fill_factor as usize / 100 {
+                return Err(DBError::from(StorageError::PageFull(tuple.len())));
+            }
+        }
+
+        if needed > self.free_space() {
+            if !self.auto_compact || needed > self.free_space() + self.fragmented_space() {
+                return Err(DBError::from(StorageError::PageFull(tuple.len())));
+            }
+            self.compact();
+        }
+
+        // Compaction may have trimmed deleted slots from the end of the
+        // directory, so look for a free slot again
+        let slot = match (0..self.num_slots()).find(|&slot| self.slot(slot).1 == 0) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.num_slots();
+                self.set_num_slots(slot + 1);
+                slot
+            }
+        };
+
+        let offset = self.data_start() as usize - tuple.len();
+        self.data[offset..offset + tuple.len()].copy_from_slice(tuple);
+        self.set_data_start(offset as u16);
+        self.set_slot(slot, offset as u16, tuple.len() as u16);
+
+        Ok(slot)
+    }
+
+    /// Returns the bytes of the tuple in the given `slot`, or `None` if the
+    /// slot does not exist or has been deleted.
+    pub fn get(&self, slot: u16) -> Option<&[u8]> {
+        if slot >= self.num_slots() {
+            return None;
+        }
+
+        let (offset, len) = self.slot(slot);
+        if len == 0 {
+            return None;
+        }
+
+        let offset = offset as usize;
+        Some(&self.data[offset..offset + len as usize])
+    }
+
+    /// Deletes the tuple in the given `slot`. The space it occupied is not
+    /// reclaimed until the page is compacted.
+    pub fn delete(&mut self, slot: u16) -> DBResult<()> {
+        if self.get(slot).is_none() {
+            return Err(DBError::from(StorageError::UnknownSlot(slot)));
+        }
+
+        self.set_slot(slot, 0, 0);
+        Ok(())
+    }
+
+    /// Compacts the page by sliding all live tuples together at the end of
+    /// the page and trimming deleted slots from the end of the slot
+    /// directory. Slot numbers of live tuples are unchanged. Returns the
+    /// number of bytes of contiguous free space reclaimed.
+    pub fn compact(&mut self) -> usize {
+        let free_before = self.free_space();
+
+        // Trim deleted slots from the end of the directory; slots before the
+        // last live slot must be kept so live slot numbers stay the same
+        let mut num_slots = self.num_slots();
+        while num_slots > 0 && self.slot(num_slots - 1).1 == 0 {
+            num_slots -= 1;
+        }
+        self.set_num_slots(num_slots);
+
+        // Move tuples in order of decreasing offset so that a tuple is never
+        // overwritten before it has been moved
+        let mut live: Vec<(u16, u16, u16)> = (0..num_slots)
+            .map(|slot| {
+                let (offset, len) = self.slot(slot);
+                (slot, offset, len)
+            })
+            .filter(|&(_, _, len)| len > 0)
+            .collect();
+        live.sort_by_key(|&(_, offset, _)| std::cmp::Reverse(offset));
+
+        let mut data_start = self.data.len();
+        for (slot, offset, len) in live {
+            let (offset, len) = (offset as usize, len as usize);
+            data_start -= len;
+            self.data.copy_within(offset..offset + len, data_start);
+            self.set_slot(slot, data_start as u16, len as u16);
+        }
+        self.set_data_start(data_start as u16);
+
+        self.free_space() - free_before
+    }
+
+    fn check_page_size(page_size: usize) -> DBResult<()> {
+        if page_size <= HEADER_SIZE || page_size > u16::MAX as usize {
+            return Err(DBError::from(StorageError::InvalidArgument(format!(
+                "invalid SlottedPage size: {} bytes; must be between {} and {} bytes.",
+                page_size,
+                HEADER_SIZE + 1,
+                u16::MAX
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes that are not contiguous free space and
+    /// are not used by a live tuple or slot, which `compact` could reclaim.
+    fn fragmented_space(&self) -> usize {
+        let live_bytes: usize = (0..self.num_slots())
+            .map(|slot| self.slot(slot).1 as usize)
+            .sum();
+        self.data.len() - self.data_start() as usize - live_bytes
+    }
+
+    fn directory_end(&self) -> usize {
+        HEADER_SIZE + self.num_slots() as usize * SLOT_SIZE
+    }
+
+    fn data_start(&self) -> u16 {
+        self.read_u16(2)
+    }
+
+    fn slot(&self, slot: u16) -> (u16, u16) {
+        let pos = HEADER_SIZE + slot as usize * SLOT_SIZE;
+        (self.read_u16(pos), self.read_u16(pos + 2))
+    }
+
+    fn set_num_slots(&mut self, num_slots: u16) {
+        self.write_u16(0, num_slots);
+    }
+
+    fn set_data_start(&mut self, data_start: u16) {
+        self.write_u16(2, data_start);
+    }
+
+    fn set_slot(&mut self, slot: u16, offset: u16, len: u16) {
+        let pos = HEADER_SIZE + slot as usize * SLOT_SIZE;
+        self.write_u16(pos, offset);
+        self.write_u16(pos + 2, len);
+    }
+
+    fn read_u16(&self, pos: usize) -> u16 {
+        u16::from_le_bytes([self.data[pos], self.data[pos + 1]])
+    }
+
+    fn write_u16(&mut self, pos: usize, value: u16) {
+        self.data[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Returns an error if `fill_factor` is not a percentage between 1 and 100.
+pub(super) fn check_fill_factor(fill_factor: u8) -> DBResult<()> {
+    if !(1..=100).contains(&fill_factor) {
+        return Err(DBError::from(StorageError::InvalidArgument(format!(
+            "invalid fill factor: {}; must be between 1 and 100.",
+            fill_factor
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Fills a 64-byte page with four 10-byte tuples and deletes the first
+    /// and third, leaving 20 bytes of fragmented free space.
+    fn fragmented_page() -> DBResult<(SlottedPage, Vec<u16>)> {
+        let mut page = SlottedPage::new(64)?;
+        let mut slots = Vec::new();
+        for i in 0..4 {
+            slots.push(page.insert(&[i; 10])?);
+        }
+        page.delete(slots[0])?;
+        page.delete(slots[2])?;
+        Ok((page, slots))
+    }
+
+    #[test]
+    fn test_slotted_page_insert_get_delete() -> DBResult<()> {
+        let mut page = SlottedPage::new(64)?;
+        let a = page.insert(b"hello")?;
+        let b = page.insert(b"world!")?;
+
+        assert_eq!(page.get(a), Some(&b"hello"[..]));
+        assert_eq!(page.get(b), Some(&b"world!"[..]));
+
+        page.delete(a)?;
+        assert_eq!(page.get(a), None);
+        assert!(page.delete(a).is_err());
+
+        // The deleted slot is reused
+        assert_eq!(page.insert(b"again")?, a);
+
+        // The page survives a round trip through its bytes
+        let page = SlottedPage::from_bytes(page.as_bytes().to_vec())?;
+        assert_eq!(page.get(a), Some(&b"again"[..]));
+        assert_eq!(page.get(b), Some(&b"world!"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_slotted_page_compact() -> DBResult<()> {
+        let (mut page, slots) = fragmented_page()?;
+        page.set_auto_compact(false);
+
+        // 20 bytes are free in total, but only 4 of them are contiguous
+        assert_eq!(page.free_space(), 4);
+        assert!(page.insert(&[9; 20]).is_err());
+
+        assert_eq!(page.compact(), 20);
+        assert_eq!(page.free_space(), 24);
+
+        // Slot numbers of live tuples are unchanged
+        assert_eq!(page.get(slots[1]), Some(&[1; 10][..]));
+        assert_eq!(page.get(slots[3]), Some(&[3; 10][..]));
+
+        let slot = page.insert(&[9; 20])?;
+        assert_eq!(page.get(slot), Some(&[9; 20][..]));
+        assert_eq!(page.get(slots[1]), Some(&[1; 10][..]));
+        assert_eq!(page.get(slots[3]), Some(&[3; 10][..]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_slotted_page_auto_compact() -> DBResult<()> {
+        let (mut page, slots) = fragmented_page()?;
+
+        let slot = page.insert(&[9; 20])?;
+        assert_eq!(page.get(slot), Some(&[9; 20][..]));
+        assert_eq!(page.get(slots[1]), Some(&[1; 10][..]));
+        assert_eq!(page.get(slots[3]), Some(&[3; 10][..]));
+
+        // Still too large even after compacting
+        assert!(page.insert(&[9; 30]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_slotted_page_compact_trims_trailing_slots() -> DBResult<()> {
+        let mut page = SlottedPage::new(64)?;
+        let a = page.insert(&[1; 10])?;
+        let b = page.insert(&[2; 10])?;
+        page.delete(b)?;
+
+        // The deleted tuple's bytes and its slot entry are both reclaimed
+        assert_eq!(page.compact(), 10 + SLOT_SIZE);
+        assert_eq!(page.num_slots(), 1);
+        assert_eq!(page.get(a), Some(&[1; 10][..]));
+        Ok(())
+    }
+}
@@ -1 +1,1002 @@
+//! Serialization of `Value`s and `Tuple`s to and from the bytes stored in
+//! pages.
+//!
+//! A tuple is stored as a null bitmap followed by its non-null values packed
+//! back-to-back in column order, with no padding between them. The bitmap
+//! has one bit per column, starting from the least significant bit of its
+//! first byte, which is set if the column is null; null values take up no
+//! other space. Each non-null value is encoded as follows (all integers are
+//! little-endian):
+//!
+//! * Integer, `Float`, and `Double` values use their fixed native width.
+//! * `Bool` values use a single byte, 0 or 1.
+//! * `Date` values store an `i32` count of days and `Timestamp` values an
+//!   `i64` count of microseconds, both since 1970-01-01.
+//! * `Uuid` values store their 16 bytes in order.
+//! * `Blob` values store their 16-byte `BlobHandle`; the blob's bytes are
+//!   stored separately in overflow pages.
+//! * `Decimal` values take 9 bytes: the unscaled value as an `i64`
+//!   followed by the `u8` scale, which must match the column type.
+//! * `Char` and `Varchar` values store a `u16` byte length followed by the
+//!   UTF-8 bytes of the string.
+//! * `Struct` and `Enum` values store a `u32` byte length of the rest of the
+//!   value, so they can be skipped without knowing their definitions, then
+//!   (for enums) the `u8` discriminant, a `u16` count of nested values, and
+//!   each nested value. Struct values written by `serialize_struct` may
+//!   also contain padding between their fields, which is included in the
+//!   length.
+//!
+//! Non-null values of checksummed columns (see `Schema::with_checksum`) are
+//! followed by a `u32` CRC-32 of their serialized bytes.
 
+use crate::catalog::StructDef;
+use crate::datatypes::{Schema, Tuple, Type, Value, ValueRef};
+use crate::{DBError, DBResult};
+
+use super::error::StorageError;
+use super::BlobHandle;
+
+/// Size in bytes of the checksum after each value of a checksummed column.
+const CHECKSUM_SIZE: usize = 4;
+
+/// Size in bytes of a serialized `Decimal` value: its unscaled `i64`
+/// followed by its `u8` scale.
+pub const DECIMAL_SIZE: usize = 9;
+
+impl Value {
+    /// Returns the exact number of bytes `serialize_value` writes for this
+    /// value, so that buffers can be allocated up front.
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            Value::Null => 0,
+            Value::Char(s) | Value::Varchar(s) => 2 + s.len(),
+            Value::Tiny(_) | Value::UnsignedTiny(_) | Value::Bool(_) => 1,
+            Value::Short(_) | Value::UnsignedShort(_) => 2,
+            Value::Int(_) | Value::UnsignedInt(_) | Value::Float(_) | Value::Date(_) => 4,
+            Value::BigInt(_)
+            | Value::UnsignedBigInt(_)
+            | Value::Double(_)
+            | Value::Timestamp(_) => 8,
+            Value::Decimal(..) => DECIMAL_SIZE,
+            Value::Uuid(_) => 16,
+            Value::Blob(_) => BlobHandle::SERIALIZED_SIZE,
+            Value::Enum(_, values) => 4 + 1 + composite_body_len(values),
+            Value::Struct(values) => 4 + composite_body_len(values),
+        }
+    }
+
+    /// Encodes this value so that comparing the encodings of two values
+    /// byte by byte, as `memcmp` does, orders them the same way as
+    /// comparing the values themselves, so that index keys can be compared
+    /// without being decoded.
+    ///
+    /// Each value starts with a tag byte of its variant's position in
+    /// `Value` plus one, so values of different variants are ordered by
+    /// variant and `Null` comes last. The tag is followed by:
+    ///
+    /// * For signed integers, `Date`, and `Timestamp`: the value in
+    ///   big-endian with its sign bit flipped, so negative values come first.
+    /// * For unsigned integers: the value in big-endian.
+    /// * For `Float` and `Double`: the bits in big-endian, with every bit
+    ///   flipped for negative numbers and only the sign bit flipped
+    ///   otherwise. Unlike comparing the values, this orders `-0.0` before
+    ///   `0.0` and NaNs with the sign bit set before every other number and
+    ///   other NaNs after.
+    /// * For `Decimal`: the unscaled value as for a `BigInt`, then the scale.
+    /// * For `Bool`: 0 or 1. For `Uuid`: its 16 bytes.
+    /// * For `Blob`: its first page and length, each as a big-endian `u64`.
+    /// * For `Char` and `Varchar`: the UTF-8 bytes with each 0 byte escaped
+    ///   as `[0, 0xFF]`, then the terminator `[0, 0]`, so a string comes
+    ///   before any longer string it is a prefix of.
+    /// * For `Enum`: the discriminant, then each nested value encoded like
+    ///   this, then a 0 byte, which is lower than any tag. `Struct`s are the
+    ///   same without the discriminant.
+    /// * For `Null`: nothing.
+    pub fn encode_order_preserving(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_order_preserving_into(&mut out);
+        out
+    }
+
+    /// Appends the encoding of `encode_order_preserving` to `out`.
+    fn encode_order_preserving_into(&self, out: &mut Vec<u8>) {
+        let tag = match self {
+            Value::Char(_) => 1,
+            Value::Varchar(_) => 2,
+            Value::Tiny(_) => 3,
+            Value::Short(_) => 4,
+            Value::Int(_) => 5,
+            Value::BigInt(_) => 6,
+            Value::UnsignedTiny(_) => 7,
+            Value::UnsignedShort(_) => 8,
+            Value::UnsignedInt(_) => 9,
+            Value::UnsignedBigInt(_) => 10,
+            Value::Float(_) => 11,
+            Value::Double(_) => 12,
+            Value::Decimal(..) => 13,
+            Value::Bool(_) => 14,
+            Value::Date(_) => 15,
+            Value::Timestamp(_) => 16,
+            Value::Uuid(_) => 17,
+            Value::Blob(_) => 18,
+            Value::Enum(..) => 19,
+            Value::Struct(_) => 20,
+            Value::Null => 21,
+        };
+        out.push(tag);
+
+        match self {
+            Value::Char(s) | Value::Varchar(s) => {
+                for &b in s.as_bytes() {
+                    out.push(b);
+                    if b == 0 {
+                        out.push(0xFF);
+                    }
+                }
+                out.extend_from_slice(&[0, 0]);
+            }
+            Value::Tiny(v) => out.push(*v as u8 ^ 0x80),
+            Value::Short(v) => out.extend_from_slice(&(*v as u16 ^ (1 << 15)).to_be_bytes()),
+            Value::Int(v) | Value::Date(v) => {
+                out.extend_from_slice(&(*v as u32 ^ (1 << 31)).to_be_bytes())
+            }
+            Value::BigInt(v) | Value::Timestamp(v) => {
+                out.extend_from_slice(&(*v as u64 ^ (1 << 63)).to_be_bytes())
+            }
+            Value::UnsignedTiny(v) => out.push(*v),
+            Value::UnsignedShort(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Value::UnsignedInt(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Value::UnsignedBigInt(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Value::Float(v) => {
+                let bits = v.to_bits();
+                let mask = if bits >> 31 == 1 { u32::MAX } else { 1 << 31 };
+                out.extend_from_slice(&(bits ^ mask).to_be_bytes());
+            }
+            Value::Double(v) => {
+                let bits = v.to_bits();
+                let mask = if bits >> 63 == 1 { u64::MAX } else { 1 << 63 };
+                out.extend_from_slice(&(bits ^ mask).to_be_bytes());
+            }
+            Value::Decimal(v, scale) => {
+                out.extend_from_slice(&(*v as u64 ^ (1 << 63)).to_be_bytes());
+                out.push(*scale);
+            }
+            Value::Bool(v) => out.push(*v as u8),
+            Value::Uuid(v) => out.extend_from_slice(v),
+            Value::Blob(handle) => {
+                let bytes = handle.to_bytes();
+                for half in bytes.chunks(8) {
+                    out.extend(half.iter().rev());
+                }
+            }
+            Value::Enum(discriminant, values) => {
+                out.push(*discriminant);
+                values
+                    .iter()
+                    .for_each(|v| v.encode_order_preserving_into(out));
+                out.push(0);
+            }
+            Value::Struct(values) => {
+                values
+                    .iter()
+                    .for_each(|v| v.encode_order_preserving_into(out));
+                out.push(0);
+            }
+            Value::Null => {}
+        }
+    }
+}
+
+/// Appends the serialized form of `value` to `out`. `Null` values are
+/// written as zero bytes, since tuples record them in their null bitmap.
+pub fn serialize_value(value: &Value, out: &mut Vec<u8>) -> DBResult<()> {
+    match value {
+        Value::Null => {}
+        Value::Char(s) | Value::Varchar(s) => {
+            let len = u16::try_from(s.len()).map_err(|_| {
+                StorageError::TypeMismatch(format!(
+                    "string of {} bytes is longer than the maximum of {} bytes.",
+                    s.len(),
+                    u16::MAX
+                ))
+            })?;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Tiny(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Short(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Int(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::BigInt(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::UnsignedTiny(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::UnsignedShort(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::UnsignedInt(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::UnsignedBigInt(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Float(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Double(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Decimal(v, scale) => {
+            out.extend_from_slice(&v.to_le_bytes());
+            out.push(*scale);
+        }
+        Value::Bool(v) => out.push(*v as u8),
+        Value::Date(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Timestamp(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Uuid(v) => out.extend_from_slice(v),
+        Value::Blob(handle) => out.extend_from_slice(&handle.to_bytes()),
+        Value::Enum(discriminant, values) => {
+            serialize_composite(Some(*discriminant), values, out)?;
+        }
+        Value::Struct(values) => serialize_composite(None, values, out)?,
+    }
+    Ok(())
+}
+
+/// Deserializes a single value of type `ty` from the start of `bytes`,
+/// returning the value and the number of bytes it occupied.
+///
+/// `Struct` and `Enum` values cannot be deserialized yet, since there is no
+/// way to look up their definitions; an error is returned for them.
+pub fn deserialize_value(bytes: &[u8], ty: &Type) -> DBResult<(Value, usize)> {
+    let (value, len) = deserialize_ref(bytes, ty)?;
+    Ok((value.to_owned(), len))
+}
+
+/// Deserializes a single value of type `ty` from the start of `bytes` like
+/// `deserialize_value`, but borrows string data from `bytes` instead of
+/// copying it.
+pub fn deserialize_ref<'a>(bytes: &'a [u8], ty: &Type) -> DBResult<(ValueRef<'a>, usize)> {
+    let len = field_len(bytes, ty)?;
+    let data = &bytes[..len];
+
+    let value = match ty {
+        Type::Char(_) | Type::Varchar(_) => {
+            let s = std::str::from_utf8(&data[2..]).map_err(|e| {
+                StorageError::DeserializationError(format!("invalid UTF-8 in string: {}", e))
+            })?;
+            if let Type::Char(_) = ty {
+                ValueRef::Char(s)
+            } else {
+                ValueRef::Varchar(s)
+            }
+        }
+        Type::Tiny => ValueRef::Tiny(i8::from_le_bytes(to_array(data))),
+        Type::Short => ValueRef::Short(i16::from_le_bytes(to_array(data))),
+        Type::Int => ValueRef::Int(i32::from_le_bytes(to_array(data))),
+        Type::BigInt => ValueRef::BigInt(i64::from_le_bytes(to_array(data))),
+        Type::UnsignedTiny => ValueRef::UnsignedTiny(u8::from_le_bytes(to_array(data))),
+        Type::UnsignedShort => ValueRef::UnsignedShort(u16::from_le_bytes(to_array(data))),
+        Type::UnsignedInt => ValueRef::UnsignedInt(u32::from_le_bytes(to_array(data))),
+        Type::UnsignedBigInt => ValueRef::UnsignedBigInt(u64::from_le_bytes(to_array(data))),
+        Type::Float => ValueRef::Float(f32::from_le_bytes(to_array(data))),
+        Type::Double => ValueRef::Double(f64::from_le_bytes(to_array(data))),
+        Type::Decimal(expected) => {
+            let scale = data[8];
+            if scale != *expected {
+                return Err(DBError::from(StorageError::DeserializationError(format!(
+                    "decimal has scale {} but column has scale {}.",
+                    scale, expected
+                ))));
+            }
+            ValueRef::Decimal(i64::from_le_bytes(to_array(data)), scale)
+        }
+        Type::Bool => match data[0] {
+            0 => ValueRef::Bool(false),
+            1 => ValueRef::Bool(true),
+            b => {
+                return Err(DBError::from(StorageError::DeserializationError(format!(
+                    "invalid Bool byte: {}",
+                    b
+                ))))
+            }
+        },
+        Type::Date => ValueRef::Date(i32::from_le_bytes(to_array(data))),
+        Type::Timestamp => ValueRef::Timestamp(i64::from_le_bytes(to_array(data))),
+        Type::Uuid => ValueRef::Uuid(to_array(data)),
+        Type::Blob => ValueRef::Blob(BlobHandle::from_bytes(data)?),
+        Type::Enum(name) | Type::Struct(name) => {
+            return Err(DBError::from(StorageError::UnsupportedType(format!(
+                "cannot deserialize values of composite type `{}` without its definition.",
+                name
+            ))))
+        }
+    };
+
+    Ok((value, len))
+}
+
+/// Appends the serialized form of a `Struct` value with the given field
+/// `values` to `out`, padding its fields to the alignment of `def`. Returns
+/// an error if the values do not match the types of the struct's fields or
+/// any of them is null, since there is no null bitmap for fields.
+///
+/// With an alignment of 1 this writes the same bytes as `serialize_value`.
+pub fn serialize_struct(values: &[Value], def: &StructDef, out: &mut Vec<u8>) -> DBResult<()> {
+    if values.len() != def.fields().len() {
+        return Err(DBError::from(StorageError::TypeMismatch(format!(
+            "struct value has {} fields but its definition has {}.",
+            values.len(),
+            def.fields().len()
+        ))));
+    }
+
+    let count = u16::try_from(values.len()).map_err(|_| {
+        StorageError::TypeMismatch(format!(
+            "composite value has {} fields; the maximum is {}.",
+            values.len(),
+            u16::MAX
+        ))
+    })?;
+
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&count.to_le_bytes());
+    let fields_start = out.len();
+    for (value, ty) in values.iter().zip(def.fields()) {
+        if value.is_null() {
+            return Err(DBError::from(StorageError::TypeMismatch(
+                "struct fields cannot be null.".to_string(),
+            )));
+        }
+        value.check_type(ty)?;
+        pad_to(out, fields_start, field_alignment(ty, def));
+        serialize_value(value, out)?;
+    }
+    pad_to(out, fields_start, def.alignment());
+
+    let len = (out.len() - start - 4) as u32;
+    out[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+/// Deserializes a `Struct` value written by `serialize_struct` with the
+/// definition `def` from the start of `bytes`, skipping the padding between
+/// its fields. Returns the value and the number of bytes it occupied.
+///
+/// Fields which are themselves `Struct` or `Enum` values cannot be
+/// deserialized yet; an error is returned for them.
+pub fn deserialize_struct(bytes: &[u8], def: &StructDef) -> DBResult<(Value, usize)> {
+    let len = field_len(bytes, &Type::Struct(String::new()))?;
+    let count = u16::from_le_bytes(to_array(take(&bytes[4..len], 2)?)) as usize;
+    if count != def.fields().len() {
+        return Err(DBError::from(StorageError::DeserializationError(format!(
+            "struct value has {} fields but its definition has {}.",
+            count,
+            def.fields().len()
+        ))));
+    }
+
+    let fields = &bytes[6..len];
+    let mut pos: usize = 0;
+    let mut values = Vec::with_capacity(count);
+    for ty in def.fields() {
+        pos = pos.next_multiple_of(field_alignment(ty, def));
+        let (value, field_len) = deserialize_value(fields.get(pos..).unwrap_or(&[]), ty)?;
+        values.push(value);
+        pos += field_len;
+    }
+    if pos.next_multiple_of(def.alignment()) != fields.len() {
+        return Err(DBError::from(StorageError::DeserializationError(format!(
+            "struct value has {} bytes of fields but its definition uses {}.",
+            fields.len(),
+            pos.next_multiple_of(def.alignment())
+        ))));
+    }
+
+    Ok((Value::Struct(values), len))
+}
+
+/// Returns the alignment of a field of type `ty` in a struct with the
+/// definition `def`: its natural alignment, capped at the struct's.
+fn field_alignment(ty: &Type, def: &StructDef) -> usize {
+    let natural = match ty {
+        Type::Tiny | Type::UnsignedTiny | Type::Bool | Type::Uuid => 1,
+        Type::Short | Type::UnsignedShort | Type::Char(_) | Type::Varchar(_) => 2,
+        Type::Int | Type::UnsignedInt | Type::Float | Type::Date => 4,
+        Type::Enum(_) | Type::Struct(_) => 4,
+        Type::BigInt | Type::UnsignedBigInt | Type::Double | Type::Decimal(_) => 8,
+        Type::Timestamp | Type::Blob => 8,
+    };
+    natural.min(def.alignment())
+}
+
+/// Appends zero bytes to `out` until its length past `start` is a multiple
+/// of `alignment`.
+fn pad_to(out: &mut Vec<u8>, start: usize, alignment: usize) {
+    let len = (out.len() - start).next_multiple_of(alignment);
+    out.resize(start + len, 0);
+}
+
+/// Serializes `tuple` according to `schema`. Returns an error if the tuple
+/// does not conform to the schema, or if it or any of its values is larger
+/// than the schema's size limits.
+pub fn serialize_tuple(tuple: &Tuple, schema: &Schema) -> DBResult<Vec<u8>> {
+    schema.check_tuple(tuple)?;
+
+    let values = tuple.values();
+    let bitmap_len = null_bitmap_len(values.len());
+    let mut len = bitmap_len;
+    for (column, value) in values.iter().enumerate() {
+        let value_len = value.serialized_len();
+        check_size(value_len, schema.max_value_bytes())?;
+        if !value.is_null() && schema.is_checksummed(column) {
+            len += CHECKSUM_SIZE;
+        }
+        len += value_len;
+    }
+    check_size(len, schema.max_tuple_bytes())?;
+
+    let mut out = Vec::with_capacity(len);
+    out.resize(bitmap_len, 0);
+    for (column, value) in values.iter().enumerate() {
+        if value.is_null() {
+            out[column / 8] |= 1 << (column % 8);
+        } else {
+            let start = out.len();
+            serialize_value(value, &mut out)?;
+            if schema.is_checksummed(column) {
+                let checksum = crc32fast::hash(&out[start..]);
+                out.extend_from_slice(&checksum.to_le_bytes());
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Deserializes a tuple which was serialized according to `schema`.
+pub fn deserialize_tuple(bytes: &[u8], schema: &Schema) -> DBResult<Tuple> {
+    let types = schema.types();
+    let bitmap = take(bytes, null_bitmap_len(types.len()))?;
+
+    let mut values = Vec::with_capacity(types.len());
+    let mut pos = bitmap.len();
+    for (column, ty) in types.iter().enumerate() {
+        if is_null(bitmap, column) {
+            values.push(Value::Null);
+        } else {
+            let (value, len) = deserialize_value(&bytes[pos..], ty)?;
+            values.push(value);
+            pos += len + verify_checksum(&bytes[pos..], len, schema, column)?;
+        }
+    }
+
+    if pos != bytes.len() {
+        return Err(DBError::from(StorageError::DeserializationError(format!(
+            "{} unexpected trailing bytes after tuple.",
+            bytes.len() - pos
+        ))));
+    }
+
+    Ok(Tuple::new(values))
+}
+
+/// Reads only the value in the given `column` of a tuple which was
+/// serialized according to `schema`. Preceding values are skipped using
+/// their fixed sizes or length prefixes without being deserialized, which
+/// is much cheaper than deserializing the whole tuple when only a few
+/// columns of a wide tuple are needed.
+pub fn read_field(bytes: &[u8], schema: &Schema, column: usize) -> DBResult<Value> {
+    let types = schema.types();
+    if column >= types.len() {
+        return Err(DBError::from(StorageError::InvalidArgument(format!(
+            "column {} is out of range for a schema with {} columns.",
+            column,
+            types.len()
+        ))));
+    }
+
+    let bitmap = take(bytes, null_bitmap_len(types.len()))?;
+    if is_null(bitmap, column) {
+        return Ok(Value::Null);
+    }
+
+    let mut pos = bitmap.len();
+    for (i, ty) in types[..column].iter().enumerate() {
+        if !is_null(bitmap, i) {
+            let rest = skip(bytes, pos)?;
+            let mut width = field_len(rest, ty)?;
+            if schema.is_checksummed(i) {
+                width += CHECKSUM_SIZE;
+            }
+            pos += take(rest, width)?.len();
+        }
+    }
+
+    let rest = skip(bytes, pos)?;
+    let (value, len) = deserialize_value(rest, &types[column])?;
+    verify_checksum(rest, len, schema, column)?;
+    Ok(value)
+}
+
+/// Checks the checksum after the `len` serialized bytes of a value at the
+/// start of `bytes`, if its `column` is checksummed. Returns the size of the
+/// checksum, which is 0 for other columns.
+fn verify_checksum(bytes: &[u8], len: usize, schema: &Schema, column: usize) -> DBResult<usize> {
+    if !schema.is_checksummed(column) {
+        return Ok(0);
+    }
+
+    let stored = u32::from_le_bytes(to_array(take(&bytes[len..], CHECKSUM_SIZE)?));
+    if crc32fast::hash(&bytes[..len]) != stored {
+        return Err(DBError::from(StorageError::DeserializationError(format!(
+            "checksum mismatch in column `{}`.",
+            schema.names()[column]
+        ))));
+    }
+    Ok(CHECKSUM_SIZE)
+}
+
+/// Returns an error if `size` bytes is more than `limit`.
+fn check_size(size: usize, limit: usize) -> DBResult<()> {
+    if size > limit {
+        return Err(DBError::from(StorageError::ValueTooLarge { size, limit }));
+    }
+    Ok(())
+}
+
+/// Returns the number of bytes in the null bitmap of a tuple with
+/// `num_columns` columns.
+fn null_bitmap_len(num_columns: usize) -> usize {
+    num_columns.div_ceil(8)
+}
+
+/// Returns `true` if `column` is marked as null in a tuple's null `bitmap`.
+fn is_null(bitmap: &[u8], column: usize) -> bool {
+    bitmap[column / 8] & (1 << (column % 8)) != 0
+}
+
+/// Returns the number of bytes occupied by the serialized value of type
+/// `ty` at the start of `bytes`, checking that `bytes` is long enough to
+/// contain it.
+fn field_len(bytes: &[u8], ty: &Type) -> DBResult<usize> {
+    let len = match ty {
+        Type::Char(_) | Type::Varchar(_) => {
+            2 + u16::from_le_bytes(to_array(take(bytes, 2)?)) as usize
+        }
+        Type::Tiny | Type::UnsignedTiny | Type::Bool => 1,
+        Type::Short | Type::UnsignedShort => 2,
+        Type::Int | Type::UnsignedInt | Type::Float | Type::Date => 4,
+        Type::BigInt | Type::UnsignedBigInt | Type::Double | Type::Timestamp => 8,
+        Type::Decimal(_) => DECIMAL_SIZE,
+        Type::Uuid => 16,
+        Type::Blob => BlobHandle::SERIALIZED_SIZE,
+        Type::Enum(_) | Type::Struct(_) => {
+            4 + u32::from_le_bytes(to_array(take(bytes, 4)?)) as usize
+        }
+    };
+
+    take(bytes, len)?;
+    Ok(len)
+}
+
+fn serialize_composite(
+    discriminant: Option<u8>,
+    values: &[Value],
+    out: &mut Vec<u8>,
+) -> DBResult<()> {
+    let count = u16::try_from(values.len()).map_err(|_| {
+        StorageError::TypeMismatch(format!(
+            "composite value has {} fields; the maximum is {}.",
+            values.len(),
+            u16::MAX
+        ))
+    })?;
+
+    // Reserve space for the length, then fill it in once the body is written
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    if let Some(discriminant) = discriminant {
+        out.push(discriminant);
+    }
+    out.extend_from_slice(&count.to_le_bytes());
+    for value in values {
+        serialize_value(value, out)?;
+    }
+
+    let len = (out.len() - start - 4) as u32;
+    out[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+/// Returns the serialized length of the count and nested `values` of a
+/// `Struct` or `Enum` value.
+fn composite_body_len(values: &[Value]) -> usize {
+    2 + values.iter().map(Value::serialized_len).sum::<usize>()
+}
+
+/// Returns the first `len` bytes of `bytes`, or an error if there are fewer.
+fn take(bytes: &[u8], len: usize) -> DBResult<&[u8]> {
+    bytes.get(..len).ok_or_else(|| {
+        DBError::from(StorageError::DeserializationError(format!(
+            "expected {} bytes but only {} remain.",
+            len,
+            bytes.len()
+        )))
+    })
+}
+
+/// Returns the bytes after the first `pos`, or an error if there are fewer
+/// than `pos` bytes.
+fn skip(bytes: &[u8], pos: usize) -> DBResult<&[u8]> {
+    bytes.get(pos..).ok_or_else(|| {
+        DBError::from(StorageError::DeserializationError(format!(
+            "expected {} bytes but only {} remain.",
+            pos,
+            bytes.len()
+        )))
+    })
+}
+
+/// Converts the first `N` bytes of `bytes` into an array. Callers must have
+/// already checked that `bytes` is long enough.
+fn to_array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    bytes[..N].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wide_schema() -> Schema {
+        Schema::new(vec![
+            Type::Int,
+            Type::Varchar(32),
+            Type::Bool,
+            Type::Char(8),
+            Type::Decimal(2),
+            Type::Double,
+            Type::Varchar(32),
+        ])
+    }
+
+    fn wide_tuple() -> Tuple {
+        Tuple::new(vec![
+            Value::Int(-42),
+            Value::Varchar("hello, world".to_string()),
+            Value::Bool(true),
+            Value::Char("abc".to_string()),
+            Value::Decimal(12345, 2),
+            Value::Double(6.25),
+            Value::Varchar("last".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_tuple_round_trip() -> DBResult<()> {
+        let schema = wide_schema();
+        let tuple = wide_tuple();
+        let bytes = serialize_tuple(&tuple, &schema)?;
+        assert_eq!(deserialize_tuple(&bytes, &schema)?, tuple);
+        Ok(())
+    }
+
+    #[test]
+    fn test_temporal_and_uuid_round_trip() -> DBResult<()> {
+        let schema = Schema::new(vec![Type::Date, Type::Timestamp, Type::Uuid, Type::Int]);
+        let tuple = Tuple::new(vec![
+            Value::Date(-719_162),
+            Value::Timestamp(1_700_000_000_123_456),
+            Value::Uuid(*b"0123456789abcdef"),
+            Value::Int(7),
+        ]);
+        let bytes = serialize_tuple(&tuple, &schema)?;
+        assert_eq!(bytes.len(), null_bitmap_len(4) + 4 + 8 + 16 + 4);
+        assert_eq!(deserialize_tuple(&bytes, &schema)?, tuple);
+        assert_eq!(read_field(&bytes, &schema, 3)?, Value::Int(7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_tuple_errs() {
+        let schema = Schema::new(vec![Type::Int, Type::Varchar(4)]);
+
+        // Wrong number of values
+        let tuple = Tuple::new(vec![Value::Int(1)]);
+        assert!(serialize_tuple(&tuple, &schema).is_err());
+
+        // Wrong type
+        let tuple = Tuple::new(vec![Value::Bool(true), Value::Varchar("a".to_string())]);
+        assert!(serialize_tuple(&tuple, &schema).is_err());
+
+        // String too long for the column
+        let tuple = Tuple::new(vec![Value::Int(1), Value::Varchar("abcde".to_string())]);
+        assert!(serialize_tuple(&tuple, &schema).is_err());
+    }
+
+    #[test]
+    fn test_order_preserving_encoding() {
+        let string = |s: &str| Value::Varchar(s.to_string());
+        let groups = [
+            vec![
+                Value::Int(i32::MIN),
+                Value::Int(-300),
+                Value::Int(-1),
+                Value::Int(0),
+                Value::Int(1),
+                Value::Int(256),
+                Value::Int(i32::MAX),
+            ],
+            vec![
+                Value::BigInt(i64::MIN),
+                Value::BigInt(-2),
+                Value::BigInt(7),
+                Value::BigInt(i64::MAX),
+            ],
+            vec![
+                Value::UnsignedShort(0),
+                Value::UnsignedShort(255),
+                Value::UnsignedShort(256),
+            ],
+            vec![
+                string(""),
+                string("\0"),
+                string("\0a"),
+                string("a"),
+                string("a\0"),
+                string("ab"),
+                string("b"),
+                string("é"),
+            ],
+            vec![
+                Value::Double(f64::NEG_INFINITY),
+                Value::Double(-2.5),
+                Value::Double(-1e-300),
+                Value::Double(0.0),
+                Value::Double(1e-300),
+                Value::Double(2.5),
+                Value::Double(f64::INFINITY),
+            ],
+            vec![Value::Float(-1.5), Value::Float(0.25), Value::Float(3.0)],
+            vec![
+                Value::Struct(vec![Value::Int(1)]),
+                Value::Struct(vec![Value::Int(1), string("")]),
+                Value::Struct(vec![Value::Int(1), Value::Null]),
+                Value::Struct(vec![Value::Int(2)]),
+            ],
+            vec![Value::Enum(0, vec![Value::Int(9)]), Value::Enum(1, vec![])],
+            vec![Value::Int(5), string("a"), Value::Null],
+        ];
+
+        for group in groups {
+            for a in &group {
+                for b in &group {
+                    let (ea, eb) = (a.encode_order_preserving(), b.encode_order_preserving());
+                    assert_eq!(a.partial_cmp(b), Some(ea.cmp(&eb)), "{:?} vs {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_size_limits() -> DBResult<()> {
+        let schema = Schema::new(vec![Type::Varchar(u16::MAX), Type::Varchar(u16::MAX)])
+            .with_size_limits(100, 150);
+        let tuple = |a: usize, b: usize| {
+            Tuple::new(vec![
+                Value::Varchar("a".repeat(a)),
+                Value::Varchar("b".repeat(b)),
+            ])
+        };
+
+        // Each string has a 2 byte length prefix, and the bitmap takes 1 byte
+        assert!(serialize_tuple(&tuple(98, 47), &schema).is_ok());
+        for (a, b, size, limit) in [(99, 0, 101, 100), (98, 48, 151, 150)] {
+            assert!(matches!(
+                serialize_tuple(&tuple(a, b), &schema),
+                Err(DBError::StorageError(StorageError::ValueTooLarge { size: s, limit: l }))
+                    if s == size && l == limit
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimal_layout() -> DBResult<()> {
+        let schema = Schema::new(vec![Type::Decimal(2)]);
+        for v in [12345, -5, 0, i64::MIN] {
+            let tuple = Tuple::new(vec![Value::Decimal(v, 2)]);
+            let bytes = serialize_tuple(&tuple, &schema)?;
+            assert_eq!(bytes.len(), null_bitmap_len(1) + DECIMAL_SIZE);
+            assert_eq!(bytes[1..9], v.to_le_bytes());
+            assert_eq!(bytes[9], 2);
+            assert_eq!(deserialize_tuple(&bytes, &schema)?, tuple);
+        }
+
+        // A stored scale which differs from the column's is rejected
+        let bytes = serialize_tuple(&Tuple::new(vec![Value::Decimal(1, 2)]), &schema)?;
+        let other = Schema::new(vec![Type::Decimal(3)]);
+        let err = deserialize_tuple(&bytes, &other).unwrap_err().to_string();
+        assert!(err.contains("scale 2 but column has scale 3"), "{}", err);
+        assert!(serialize_tuple(&Tuple::new(vec![Value::Decimal(1, 3)]), &schema).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_struct_alignment() -> DBResult<()> {
+        let fields = vec![Type::Tiny, Type::Int, Type::Short];
+        let values = vec![Value::Tiny(1), Value::Int(2), Value::Short(3)];
+        let packed = StructDef::new(fields.clone());
+        let aligned = StructDef::new(fields).with_alignment(4)?;
+
+        let mut packed_bytes = Vec::new();
+        serialize_struct(&values, &packed, &mut packed_bytes)?;
+        let mut plain_bytes = Vec::new();
+        serialize_value(&Value::Struct(values.clone()), &mut plain_bytes)?;
+        assert_eq!(packed_bytes, plain_bytes);
+        assert_eq!(&packed_bytes[6..], [1, 2, 0, 0, 0, 3, 0]);
+
+        // The Int is padded to offset 4, and the fields to 12 bytes
+        let mut aligned_bytes = Vec::new();
+        serialize_struct(&values, &aligned, &mut aligned_bytes)?;
+        assert_eq!(&aligned_bytes[6..], [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]);
+
+        for (bytes, def) in [(&packed_bytes, &packed), (&aligned_bytes, &aligned)] {
+            let expected = (Value::Struct(values.clone()), bytes.len());
+            assert_eq!(deserialize_struct(bytes, def)?, expected);
+            assert_eq!(
+                field_len(bytes, &Type::Struct("s".to_string()))?,
+                bytes.len()
+            );
+        }
+        assert!(deserialize_struct(&aligned_bytes, &packed).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksummed_column() -> DBResult<()> {
+        let schema = wide_schema().with_checksum("column_1")?;
+        let tuple = wide_tuple();
+        let mut bytes = serialize_tuple(&tuple, &schema)?;
+        assert_eq!(
+            bytes.len(),
+            serialize_tuple(&tuple, &wide_schema())?.len() + CHECKSUM_SIZE
+        );
+        assert_eq!(deserialize_tuple(&bytes, &schema)?, tuple);
+        assert_eq!(read_field(&bytes, &schema, 2)?, tuple.values()[2]);
+
+        // Flip a bit in the string's bytes, after its length prefix
+        let value_start = null_bitmap_len(7) + 4 + 2;
+        bytes[value_start] ^= 1;
+        for result in [
+            deserialize_tuple(&bytes, &schema).map(|_| ()),
+            read_field(&bytes, &schema, 1).map(|_| ()),
+        ] {
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("column `column_1`"), "{}", err);
+        }
+
+        // Later columns can still be read without verifying it
+        assert_eq!(read_field(&bytes, &schema, 2)?, tuple.values()[2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_field() -> DBResult<()> {
+        let schema = wide_schema();
+        let tuple = wide_tuple();
+        let bytes = serialize_tuple(&tuple, &schema)?;
+        let full = deserialize_tuple(&bytes, &schema)?;
+
+        for column in [0, 3, 6] {
+            assert_eq!(read_field(&bytes, &schema, column)?, full.values()[column]);
+        }
+
+        assert!(read_field(&bytes, &schema, 7).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_field_truncated() -> DBResult<()> {
+        let schema = Schema::with_names(
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vec![Type::Int, Type::BigInt, Type::Int],
+        )?
+        .with_checksum("b")?;
+        let tuple = Tuple::new(vec![Value::Int(1), Value::BigInt(2), Value::Int(3)]);
+        let bytes = serialize_tuple(&tuple, &schema)?;
+        assert_eq!(read_field(&bytes, &schema, 2)?, Value::Int(3));
+
+        // Every truncation, including one inside the checksum of `b`, is an
+        // error rather than a panic
+        for len in 0..bytes.len() {
+            assert!(matches!(
+                read_field(&bytes[..len], &schema, 2),
+                Err(DBError::StorageError(StorageError::DeserializationError(_)))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_field_skips_composite() -> DBResult<()> {
+        let schema = Schema::new(vec![Type::Struct("point".to_string()), Type::Int]);
+        let tuple = Tuple::new(vec![
+            Value::Struct(vec![Value::Int(1), Value::Varchar("y".to_string())]),
+            Value::Int(7),
+        ]);
+        let bytes = serialize_tuple(&tuple, &schema)?;
+
+        assert_eq!(read_field(&bytes, &schema, 1)?, Value::Int(7));
+        assert!(read_field(&bytes, &schema, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_nulls_round_trip() -> DBResult<()> {
+        let schema = wide_schema();
+        let mut values = wide_tuple().values().to_vec();
+        values[1] = Value::Null;
+        values[6] = Value::Null;
+        let tuple = Tuple::new(values);
+
+        let bytes = serialize_tuple(&tuple, &schema)?;
+        assert_eq!(deserialize_tuple(&bytes, &schema)?, tuple);
+        assert_eq!(read_field(&bytes, &schema, 1)?, Value::Null);
+        assert_eq!(
+            read_field(&bytes, &schema, 3)?,
+            Value::Char("abc".to_string())
+        );
+        assert_eq!(read_field(&bytes, &schema, 6)?, Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialized_len() -> DBResult<()> {
+        let values = vec![
+            Value::Char("abc".to_string()),
+            Value::Varchar("héllo".to_string()),
+            Value::Tiny(-1),
+            Value::Short(-2),
+            Value::Int(-3),
+            Value::BigInt(-4),
+            Value::UnsignedTiny(1),
+            Value::UnsignedShort(2),
+            Value::UnsignedInt(3),
+            Value::UnsignedBigInt(4),
+            Value::Float(0.5),
+            Value::Double(0.25),
+            Value::Decimal(12345, 2),
+            Value::Bool(true),
+            Value::Blob(BlobHandle::from_bytes(&[1; BlobHandle::SERIALIZED_SIZE])?),
+            Value::Enum(3, vec![Value::Int(1), Value::Null]),
+            Value::Struct(vec![
+                Value::Varchar("x".to_string()),
+                Value::Struct(vec![Value::Bool(false)]),
+            ]),
+            Value::Null,
+        ];
+
+        for value in values {
+            let mut out = Vec::new();
+            serialize_value(&value, &mut out)?;
+            assert_eq!(value.serialized_len(), out.len(), "{:?}", value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_ref_borrows() -> DBResult<()> {
+        let mut bytes = Vec::new();
+        serialize_value(&Value::Varchar("borrowed".to_string()), &mut bytes)?;
+
+        let (value, len) = deserialize_ref(&bytes, &Type::Varchar(16))?;
+        assert_eq!(len, bytes.len());
+        let ValueRef::Varchar(s) = value else {
+            panic!("expected a Varchar but got {:?}", value);
+        };
+        assert_eq!(s, "borrowed");
+
+        // The string points into `bytes` rather than a new allocation
+        assert!(bytes.as_ptr_range().contains(&s.as_ptr()));
+
+        assert_eq!(value.to_owned(), Value::Varchar("borrowed".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_truncated() -> DBResult<()> {
+        let schema = wide_schema();
+        let bytes = serialize_tuple(&wide_tuple(), &schema)?;
+        assert!(deserialize_tuple(&bytes[..bytes.len() - 1], &schema).is_err());
+        assert!(read_field(&bytes[..10], &schema, 6).is_err());
+        Ok(())
+    }
+}